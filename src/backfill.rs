@@ -0,0 +1,364 @@
+use std::{
+    collections::HashSet,
+    fmt::Display,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serenity::all::{ChannelId, Http, MessageId, MessagePagination};
+use tokio::time::sleep;
+
+use crate::{
+    database::Database,
+    ingest::filter::IngestFilter,
+    llm::backend::EmbedBackend,
+    privacy::OptOutStore,
+    vec_db::{db_handler::VdbHandler, vector::DbVector},
+};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Kind backfills are recorded under in the database's job table, targeting the channel id.
+pub const BACKFILL_JOB: &str = "backfill";
+
+/// Discord returns at most this many messages per history request.
+pub const HISTORY_PAGE_SIZE: u8 = 100;
+
+/// Retries of a page whose messages could not be embedded before they are counted as failed.
+const EMBED_RETRIES: u32 = 4;
+const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Which part of a channel's history to store.
+#[derive(Debug, Clone)]
+pub struct BackfillRequest {
+    pub channel_id: ChannelId,
+    /// Most messages to read, the whole history when unset.
+    pub limit: Option<usize>,
+    /// Only read messages sent before this one, starting from the newest message when unset.
+    pub before: Option<MessageId>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BackfillProgress {
+    pub read: usize,
+    pub stored: usize,
+    /// Messages that were already stored, that the ingestion filter rejects or whose authors
+    /// opted out.
+    pub skipped: usize,
+    /// Messages that could not be embedded.
+    pub failed: usize,
+}
+
+/// Channels with a backfill running, so the same channel is not walked twice at once. Jobs are
+/// also recorded in the database, so how a backfill ended is known after a restart.
+pub struct BackfillJobs {
+    running: Mutex<HashSet<ChannelId>>,
+    database: Database,
+}
+
+/// Marks a channel's backfill as running until dropped.
+pub struct BackfillJob {
+    jobs: Arc<BackfillJobs>,
+    channel_id: ChannelId,
+    /// Id of the job's database record, `None` if it could not be recorded.
+    record_id: Option<i64>,
+}
+
+impl BackfillJobs {
+    pub fn new(database: Database) -> Self {
+        Self {
+            running: Mutex::new(HashSet::new()),
+            database,
+        }
+    }
+
+    /// Claims `channel_id`, `None` if a backfill of it is already running.
+    pub async fn start(self: &Arc<Self>, channel_id: ChannelId) -> Option<BackfillJob> {
+        let claimed = self
+            .running
+            .lock()
+            .expect("Backfill jobs lock poisoned")
+            .insert(channel_id);
+        if !claimed {
+            return None;
+        }
+        // The record only informs admins, so a backfill runs even if it cannot be written.
+        let record_id = match self
+            .database
+            .start_job(BACKFILL_JOB, channel_id.get())
+            .await
+        {
+            Ok(record_id) => Some(record_id),
+            Err(err) => {
+                println!("Failed to record backfill of {channel_id}, {err}");
+                None
+            }
+        };
+        Some(BackfillJob {
+            jobs: self.clone(),
+            channel_id,
+            record_id,
+        })
+    }
+}
+
+impl BackfillJob {
+    pub async fn record_progress(&self, progress: BackfillProgress) {
+        let Some(record_id) = self.record_id else {
+            return;
+        };
+        if let Err(err) = self
+            .jobs
+            .database
+            .update_job_progress(record_id, progress.to_string())
+            .await
+        {
+            println!("Failed to record backfill progress, {err}");
+        }
+    }
+
+    pub async fn finish(self, result: &Result<BackfillProgress>) {
+        let Some(record_id) = self.record_id else {
+            return;
+        };
+        let outcome = match result {
+            Ok(progress) => Ok(progress.to_string()),
+            Err(err) => Err(err.to_string()),
+        };
+        if let Err(err) = self.jobs.database.finish_job(record_id, outcome).await {
+            println!(
+                "Failed to record end of backfill of {}, {}",
+                self.channel_id, err
+            );
+        }
+    }
+}
+
+impl Drop for BackfillJob {
+    fn drop(&mut self) {
+        self.jobs
+            .running
+            .lock()
+            .expect("Backfill jobs lock poisoned")
+            .remove(&self.channel_id);
+    }
+}
+
+/// Walks the channel's history from newest to oldest, embedding and storing every message not
+/// stored yet that `filter` allows and whose author has not opted out, one page at a time.
+/// `on_progress` is called after every page.
+///
+/// Requests go through serenity's `Http` client, which waits out Discord's rate limits.
+pub async fn backfill_channel<F, Fut>(
+    http: &Http,
+    embed_engine: &dyn EmbedBackend,
+    vec_db_client: &VdbHandler,
+    filter: &IngestFilter,
+    opt_outs: &OptOutStore,
+    request: &BackfillRequest,
+    mut on_progress: F,
+) -> Result<BackfillProgress>
+where
+    F: FnMut(BackfillProgress) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let guild_id = request
+        .channel_id
+        .to_channel(http)
+        .await?
+        .guild()
+        .ok_or(Error::NotGuildChannel)?
+        .guild_id;
+
+    let mut progress = BackfillProgress::default();
+    let mut before = request.before;
+    loop {
+        let remaining = request
+            .limit
+            .map_or(HISTORY_PAGE_SIZE as usize, |limit| limit - progress.read);
+        if remaining == 0 {
+            break;
+        }
+        let page = http
+            .get_messages(
+                request.channel_id,
+                before.map(MessagePagination::Before),
+                Some(remaining.min(HISTORY_PAGE_SIZE as usize) as u8),
+            )
+            .await?;
+        let Some(oldest) = page.last() else {
+            break;
+        };
+        before = Some(oldest.id);
+        progress.read += page.len();
+
+        let existing = vec_db_client
+            .existing_message_ids(page.iter().map(|message| message.id.get()).collect())
+            .await
+            .map_err(Error::VectorDB)?;
+
+        let (pending, skipped): (Vec<_>, Vec<_>) = page
+            .into_iter()
+            .map(|mut message| {
+                // Messages fetched over http do not say which guild they belong to.
+                message.guild_id = Some(guild_id);
+                message
+            })
+            .partition(|message| {
+                !existing.contains(&message.id.get())
+                    && filter.allows(message)
+                    && !opt_outs.is_opted_out(message.author.id)
+            });
+        progress.skipped += skipped.len();
+
+        let contents: Vec<String> = pending
+            .iter()
+            .map(|message| message.content.clone())
+            .collect();
+        let Some(embeddings) = embed_with_retries(embed_engine, &contents).await else {
+            progress.failed += pending.len();
+            on_progress(progress).await;
+            continue;
+        };
+        let mut vectors = Vec::with_capacity(pending.len());
+        for (message, embedding) in pending.iter().zip(embeddings) {
+            vectors.push(DbVector::new(embedding, message).map_err(Error::VectorDB)?);
+        }
+
+        progress.stored += vectors.len();
+        vec_db_client
+            .add_vectors(vectors)
+            .await
+            .map_err(Error::VectorDB)?;
+        on_progress(progress).await;
+    }
+
+    Ok(progress)
+}
+
+/// Embeds a page of messages, backing off exponentially between tries so a short outage of the
+/// embedding service does not leave a gap in the history. Gives up with `None` after
+/// `EMBED_RETRIES` retries.
+async fn embed_with_retries(
+    embed_engine: &dyn EmbedBackend,
+    contents: &[String],
+) -> Option<Vec<Vec<f32>>> {
+    if contents.is_empty() {
+        return Some(Vec::new());
+    }
+    let mut delay = INITIAL_RETRY_DELAY;
+    let mut retries = 0;
+    loop {
+        match embed_engine.get_embeds(contents).await {
+            Ok(embeddings) => return Some(embeddings),
+            Err(err) if retries < EMBED_RETRIES => {
+                println!(
+                    "Failed to embed {} messages, retrying in {delay:?}, {err}",
+                    contents.len()
+                );
+                sleep(delay).await;
+                delay *= 2;
+                retries += 1;
+            }
+            Err(err) => {
+                println!(
+                    "Failed to embed {} messages, giving up, {err}",
+                    contents.len()
+                );
+                return None;
+            }
+        }
+    }
+}
+
+impl Display for BackfillProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} messages read, {} stored, {} skipped",
+            self.read, self.stored, self.skipped
+        )?;
+        if self.failed > 0 {
+            write!(f, ", {} failed to embed", self.failed)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to read channel history, {0}")]
+    Discord(#[from] serenity::Error),
+    #[error("Only channels in a server can be backfilled")]
+    NotGuildChannel,
+    #[error("Failed to store messages in vector database client.\n{0}")]
+    VectorDB(anyhow::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::llm;
+
+    /// Fails the first `failures` requests, then embeds every message as `[1.0]`.
+    struct FlakyEmbed {
+        failures: u32,
+        calls: AtomicU32,
+    }
+
+    #[async_trait]
+    impl EmbedBackend for FlakyEmbed {
+        async fn get_embed(&self, _: &str) -> llm::error::Result<Vec<f32>> {
+            if self.calls.fetch_add(1, Ordering::Relaxed) < self.failures {
+                return Err(llm::error::Error::EmptyResponseError);
+            }
+            Ok(vec![1.0])
+        }
+
+        async fn get_embeds(&self, messages: &[String]) -> llm::error::Result<Vec<Vec<f32>>> {
+            self.get_embed("").await?;
+            Ok(vec![vec![1.0]; messages.len()])
+        }
+    }
+
+    fn flaky(failures: u32) -> FlakyEmbed {
+        FlakyEmbed {
+            failures,
+            calls: AtomicU32::new(0),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_a_page_until_it_is_embedded() {
+        let embed = flaky(EMBED_RETRIES);
+        let contents = vec!["one".to_string(), "two".to_string()];
+        let started = tokio::time::Instant::now();
+
+        let embeddings = embed_with_retries(&embed, &contents).await.unwrap();
+        assert_eq!(embeddings.len(), 2);
+        assert_eq!(embed.calls.load(Ordering::Relaxed), EMBED_RETRIES + 1);
+        // 1 + 2 + 4 + 8 seconds of backing off.
+        assert_eq!(started.elapsed(), INITIAL_RETRY_DELAY * 15);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_after_the_last_retry() {
+        let embed = flaky(EMBED_RETRIES + 1);
+        assert!(embed_with_retries(&embed, &["one".to_string()])
+            .await
+            .is_none());
+        assert_eq!(embed.calls.load(Ordering::Relaxed), EMBED_RETRIES + 1);
+    }
+
+    #[tokio::test]
+    async fn an_empty_page_needs_no_request() {
+        let embed = flaky(u32::MAX);
+        assert_eq!(embed_with_retries(&embed, &[]).await, Some(vec![]));
+        assert_eq!(embed.calls.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,105 @@
+use chattyrs::backfill::{backfill_channel, BackfillRequest};
+use chattyrs::database::Database;
+use chattyrs::environment::get_environment;
+use chattyrs::guild_config::GuildConfigs;
+use chattyrs::ingest::filter::IngestFilter;
+use chattyrs::llm::engine::LlmEngine;
+use chattyrs::privacy::OptOutStore;
+use chattyrs::vec_db::db_handler::VdbHandler;
+use serenity::all::{Channel, ChannelId, MessageId};
+use serenity::http::Http;
+
+const USAGE: &str = "Usage: backfill <channel id> [--limit <count>] [--before <message id>]";
+
+/// Stores a channel's history in the vector database, the command line counterpart of the
+/// `/backfill` command. Messages already stored are skipped, so an interrupted backfill can be
+/// rerun as is.
+#[tokio::main]
+async fn main() {
+    let mut args = std::env::args().skip(1);
+    let mut channel_id = None;
+    let mut limit = None;
+    let mut before = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--limit" => limit = Some(parse_number_arg(args.next()) as usize),
+            "--before" => before = Some(MessageId::new(parse_number_arg(args.next()))),
+            _ if channel_id.is_none() && !arg.starts_with('-') => {
+                channel_id = Some(ChannelId::new(parse_number_arg(Some(arg))))
+            }
+            _ => exit_with_usage(),
+        }
+    }
+    let Some(channel_id) = channel_id else {
+        exit_with_usage()
+    };
+    let request = BackfillRequest {
+        channel_id,
+        limit,
+        before,
+    };
+
+    let environment = get_environment().expect("Failed to load configuration");
+    let filter = IngestFilter::new(&environment.ingest.filter).expect("Invalid ingestion filter");
+    let database = Database::open(&environment.database.path).expect("Failed to open database");
+    let http = Http::new(environment.discord_token.expose());
+    // Channels the guild does not store new messages from are not backfilled either.
+    let guild_id = match channel_id.to_channel(&http).await {
+        Ok(Channel::Guild(channel)) => channel.guild_id,
+        Ok(_) => {
+            eprintln!("Channel {channel_id} is not in a guild");
+            std::process::exit(1);
+        }
+        Err(err) => {
+            eprintln!("Failed to get channel {channel_id}, {err}");
+            std::process::exit(1);
+        }
+    };
+    let guild_config = GuildConfigs::new(&environment, database.clone())
+        .get(Some(guild_id))
+        .await;
+    if !guild_config.stores_channel(channel_id) {
+        eprintln!(
+            "Messages from channel {channel_id} are not stored, see the ingest-channels setting"
+        );
+        std::process::exit(1);
+    }
+    let opt_outs = OptOutStore::load(database)
+        .await
+        .expect("Failed to load opt-outs");
+    let llm_engine = LlmEngine::new(&environment).expect("Failed to create llm engine");
+    let vec_db_client = VdbHandler::new(&environment, &llm_engine)
+        .await
+        .expect("Failed to initialise vector database client");
+
+    let result = backfill_channel(
+        &http,
+        &llm_engine,
+        &vec_db_client,
+        &filter,
+        &opt_outs,
+        &request,
+        |progress| async move { println!("{progress}") },
+    )
+    .await;
+    match result {
+        Ok(progress) => println!("Backfill finished: {progress}"),
+        Err(err) => {
+            eprintln!("Backfill failed, {err}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Parses a positive number, exiting with the usage when it is missing or invalid.
+fn parse_number_arg(arg: Option<String>) -> u64 {
+    match arg.and_then(|arg| arg.parse().ok()) {
+        Some(value) if value > 0 => value,
+        _ => exit_with_usage(),
+    }
+}
+
+fn exit_with_usage() -> ! {
+    eprintln!("{USAGE}");
+    std::process::exit(2);
+}
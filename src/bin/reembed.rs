@@ -0,0 +1,46 @@
+use chattyrs::environment::get_environment;
+use chattyrs::llm::engine::LlmEngine;
+use chattyrs::vec_db::migration::reembed_collection;
+
+const USAGE: &str = "Usage: reembed <source collection> [--batch-size <count>]";
+
+/// Messages read, embedded and written per batch.
+const DEFAULT_BATCH_SIZE: u32 = 64;
+
+/// Moves the messages of a collection over to the embedding model set in the configuration,
+/// switching `vdb.alias` to the new collection once done. Rerun the same command to resume an
+/// interrupted migration.
+#[tokio::main]
+async fn main() {
+    let mut args = std::env::args().skip(1);
+    let mut source = None;
+    let mut batch_size = DEFAULT_BATCH_SIZE;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--batch-size" => {
+                batch_size = match args.next().and_then(|count| count.parse().ok()) {
+                    Some(count) if count > 0 => count,
+                    _ => exit_with_usage(),
+                }
+            }
+            _ if source.is_none() && !arg.starts_with('-') => source = Some(arg),
+            _ => exit_with_usage(),
+        }
+    }
+    let Some(source) = source else {
+        exit_with_usage()
+    };
+
+    let environment = get_environment().expect("Failed to load configuration");
+    let llm_engine = LlmEngine::new(&environment).expect("Failed to create llm engine");
+
+    if let Err(err) = reembed_collection(&environment, &llm_engine, &source, batch_size).await {
+        eprintln!("Migration failed, {err:#}");
+        std::process::exit(1);
+    }
+}
+
+fn exit_with_usage() -> ! {
+    eprintln!("{USAGE}");
+    std::process::exit(2);
+}
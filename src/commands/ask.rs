@@ -1,44 +1,87 @@
-use serenity::all::{
-    CommandOptionType, CreateCommand, CreateCommandOption, ResolvedOption, ResolvedValue,
-};
+use async_trait::async_trait;
+use serenity::all::{CommandOptionType, CreateCommand, CreateCommandOption, ResolvedValue};
 
 use crate::{
     environment::Environment,
-    llm::{self, engine::LlmEngine},
+    llm::{
+        self,
+        backend::ChatBackend,
+        model::{LlmChat, SystemMessage, UserMessage},
+    },
+    memory,
 };
 
-use super::error::Result;
-
-pub fn register_ask(environment: &Environment) -> CreateCommand {
-    CreateCommand::new("ask")
-        .description(format!("Ask {} a question", environment.bot_name))
-        .add_option(
-            CreateCommandOption::new(
-                CommandOptionType::String,
-                "question",
-                format!(
-                    "The question you would like to ask {}",
-                    environment.bot_name
-                ),
+use super::{error::Result, CommandContext, Reply, ReplyMessage, SlashCommand};
+
+pub struct Ask;
+
+#[async_trait]
+impl SlashCommand for Ask {
+    fn name(&self) -> &str {
+        "ask"
+    }
+
+    fn register(&self, environment: &Environment) -> CreateCommand {
+        CreateCommand::new(self.name())
+            .description(format!("Ask {} a question", environment.bot_name))
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::String,
+                    "question",
+                    format!(
+                        "The question you would like to ask {}",
+                        environment.bot_name
+                    ),
+                )
+                .max_length(300)
+                .required(true),
             )
-            .max_length(300)
-            .required(true),
-        )
-}
-pub async fn run_ask<'a>(
-    options: &'a [ResolvedOption<'_>],
-    llm_engine: &'a LlmEngine,
-) -> Result<String> {
-    let question_response = &options.first().ok_or(Error::MissingQuestion)?.value;
-    match question_response {
-        ResolvedValue::String(question) => Ok((llm_engine
-            .get_completion(question)
-            .await
-            .map(|llm_response| format!("**Question**: *{question}*\n{llm_response}"))
-            .map_err(Error::from)?)
-        .to_string()),
-        _ => Err(Error::MissingQuestion.into()),
     }
+
+    async fn run(&self, ctx: &CommandContext<'_>) -> Result<Reply> {
+        let answer = run_ask(ctx).await?;
+        Ok(ReplyMessage::generated(answer).into())
+    }
+}
+
+async fn run_ask(ctx: &CommandContext<'_>) -> Result<String> {
+    let options = ctx.command.data.options();
+    let question = match &options.first().ok_or(Error::MissingQuestion)?.value {
+        ResolvedValue::String(question) => question.to_string(),
+        _ => Err(Error::MissingQuestion)?,
+    };
+
+    let history = ctx
+        .memory
+        .history(
+            ctx.command.guild_id,
+            ctx.command.channel_id,
+            ctx.discord,
+            ctx.guild_config.memory_size,
+        )
+        .await
+        .map_err(Error::from)?;
+    let llm_context: LlmChat = std::iter::once(
+        SystemMessage {
+            content: ctx.guild_config.prompt(),
+        }
+        .into(),
+    )
+    .chain(history)
+    .chain(std::iter::once(
+        UserMessage {
+            content: question.clone(),
+        }
+        .into(),
+    ))
+    .collect();
+
+    Ok(ctx
+        .llm_engine
+        .get_chat_completion(llm_context)
+        .await
+        .map(|llm_response| format!("**Question**: *{question}*\n{llm_response}"))
+        .map_err(Error::from)?)
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -49,4 +92,6 @@ pub enum Error {
     MissingQuestion,
     #[error("Failed to get completion from Llm Engine, {0})")]
     LlmEngineCompletionFailed(#[from] llm::error::Error),
+    #[error("Failed to retrieve conversation history, {0}")]
+    Memory(#[from] memory::Error),
 }
@@ -0,0 +1,188 @@
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use serenity::all::{
+    ChannelId, ChannelType, CommandInteraction, CommandOptionType, CreateCommand,
+    CreateCommandOption, EditInteractionResponse, MessageId, Permissions, ResolvedValue,
+};
+
+use super::{error::Result, CommandContext, Reply, SlashCommand};
+use crate::{
+    backfill::{backfill_channel, BackfillRequest},
+    environment::Environment,
+};
+
+/// Minimum time between progress edits of the command response.
+const PROGRESS_EDIT_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct Backfill;
+
+#[async_trait]
+impl SlashCommand for Backfill {
+    fn name(&self) -> &str {
+        "backfill"
+    }
+
+    fn register(&self, environment: &Environment) -> CreateCommand {
+        CreateCommand::new(self.name())
+            .description(format!(
+                "Store a channel's earlier messages so {} can recall them",
+                environment.bot_name
+            ))
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .dm_permission(false)
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::Channel,
+                    "channel",
+                    "Channel whose history to store",
+                )
+                .channel_types(vec![
+                    ChannelType::Text,
+                    ChannelType::News,
+                    ChannelType::PublicThread,
+                    ChannelType::PrivateThread,
+                ])
+                .required(true),
+            )
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::Integer,
+                    "limit",
+                    "Most messages to read, the whole history by default",
+                )
+                .min_int_value(1),
+            )
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::String,
+                "before",
+                "Only read messages sent before this message, given as an id or link",
+            ))
+    }
+
+    async fn run(&self, ctx: &CommandContext<'_>) -> Result<Reply> {
+        run_backfill(ctx).await.map(Reply::from)
+    }
+}
+
+/// Starts the backfill in the background, editing the command response as it progresses.
+async fn run_backfill(ctx: &CommandContext<'_>) -> Result<String> {
+    let command = ctx.command;
+    let is_admin = command
+        .member
+        .as_ref()
+        .and_then(|member| member.permissions)
+        .is_some_and(|permissions| permissions.administrator());
+    if !is_admin {
+        return Err(Error::NotAdministrator.into());
+    }
+
+    let mut channel_id = None;
+    let mut limit = None;
+    let mut before = None;
+    for option in command.data.options() {
+        match (option.name, option.value) {
+            ("channel", ResolvedValue::Channel(channel)) => channel_id = Some(channel.id),
+            ("limit", ResolvedValue::Integer(value)) => limit = Some(value.max(1) as usize),
+            ("before", ResolvedValue::String(value)) => before = Some(parse_message_id(value)?),
+            _ => {}
+        }
+    }
+    let request = BackfillRequest {
+        channel_id: channel_id.ok_or(Error::MissingChannel)?,
+        limit,
+        before,
+    };
+    if !ctx.guild_config.stores_channel(request.channel_id) {
+        return Err(Error::ChannelNotStored(request.channel_id).into());
+    }
+
+    let Some(job) = ctx.backfill_jobs.start(request.channel_id).await else {
+        return Ok(format!(
+            "A backfill of <#{}> is already running.",
+            request.channel_id
+        ));
+    };
+
+    let http = ctx.discord.http.clone();
+    let command = command.clone();
+    let llm_engine = ctx.services.llm_engine.clone();
+    let vec_db_client = ctx.services.vec_db_client.clone();
+    let filter = ctx.services.filter.clone();
+    let opt_outs = ctx.opt_outs.clone();
+    let channel_id = request.channel_id;
+    tokio::spawn(async move {
+        let mut last_edit = Instant::now();
+        let result = backfill_channel(
+            &http,
+            llm_engine.as_ref(),
+            &vec_db_client,
+            &filter,
+            &opt_outs,
+            &request,
+            |progress| {
+                let report = (last_edit.elapsed() >= PROGRESS_EDIT_INTERVAL).then(|| {
+                    last_edit = Instant::now();
+                    format!("Backfilling <#{channel_id}>: {progress}")
+                });
+                let http = http.clone();
+                let command = command.clone();
+                let job = &job;
+                async move {
+                    if let Some(report) = report {
+                        job.record_progress(progress).await;
+                        edit_progress(&command, &http, report).await;
+                    }
+                }
+            },
+        )
+        .await;
+
+        job.finish(&result).await;
+        let report = match result {
+            Ok(progress) => format!("Backfill of <#{channel_id}> finished: {progress}"),
+            Err(err) => {
+                println!("Backfill of {channel_id} failed, {err}");
+                format!("Backfill of <#{channel_id}> failed, {err}")
+            }
+        };
+        edit_progress(&command, &http, report).await;
+    });
+
+    Ok(format!("Backfill of <#{channel_id}> started."))
+}
+
+/// Interaction responses can only be edited for 15 minutes, so later reports only reach the log.
+async fn edit_progress(command: &CommandInteraction, http: &serenity::all::Http, report: String) {
+    println!("{report}");
+    if let Err(why) = command
+        .edit_response(http, EditInteractionResponse::new().content(report))
+        .await
+    {
+        println!("Failed to report backfill progress {why:?}");
+    }
+}
+
+/// Accepts a bare message id or a message link, whose last segment is the id.
+fn parse_message_id(value: &str) -> std::result::Result<MessageId, Error> {
+    value
+        .trim()
+        .rsplit('/')
+        .next()
+        .and_then(|id| id.parse::<u64>().ok())
+        .filter(|id| *id != 0)
+        .map(MessageId::new)
+        .ok_or_else(|| Error::InvalidMessageId(value.to_string()))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Only administrators can backfill channels")]
+    NotAdministrator,
+    #[error("Missing channel")]
+    MissingChannel,
+    #[error("Messages from <#{0}> are not stored, see the ingest-channels setting of /config")]
+    ChannelNotStored(ChannelId),
+    #[error("{0} is not a message id or link")]
+    InvalidMessageId(String),
+}
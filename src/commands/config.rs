@@ -0,0 +1,205 @@
+use async_trait::async_trait;
+use serenity::all::{
+    CommandInteraction, CommandOptionType, CreateCommand, CreateCommandOption, Permissions,
+    ResolvedOption, ResolvedValue,
+};
+
+use super::{error::Result, CommandContext, Reply, SlashCommand};
+use crate::{
+    environment::Environment,
+    guild_config::{self, GuildConfig, GuildConfigs, GuildSetting},
+};
+
+/// Longest value accepted, leaving room for the rest of the interaction payload.
+const MAX_VALUE_LENGTH: u16 = 4000;
+
+pub struct Config;
+
+#[async_trait]
+impl SlashCommand for Config {
+    fn name(&self) -> &str {
+        "config"
+    }
+
+    fn register(&self, environment: &Environment) -> CreateCommand {
+        CreateCommand::new(self.name())
+            .description(format!(
+                "Change how {} behaves in this server",
+                environment.bot_name
+            ))
+            .default_member_permissions(Permissions::MANAGE_GUILD)
+            .dm_permission(false)
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::SubCommand,
+                    "get",
+                    "Show this server's settings",
+                )
+                .add_sub_option(setting_option("Setting to show, all when unset")),
+            )
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::SubCommand,
+                    "set",
+                    "Change a setting for this server",
+                )
+                .add_sub_option(setting_option("Setting to change").required(true))
+                .add_sub_option(
+                    CreateCommandOption::new(CommandOptionType::String, "value", "The new value")
+                        .max_length(MAX_VALUE_LENGTH)
+                        .required(true),
+                ),
+            )
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::SubCommand,
+                    "reset",
+                    "Return a setting to its default",
+                )
+                .add_sub_option(setting_option("Setting to reset, all when unset")),
+            )
+    }
+
+    async fn run(&self, ctx: &CommandContext<'_>) -> Result<Reply> {
+        run_config(ctx.command, ctx.guild_configs)
+            .await
+            .map(Reply::from)
+    }
+}
+
+async fn run_config(command: &CommandInteraction, guild_configs: &GuildConfigs) -> Result<String> {
+    let can_manage = command
+        .member
+        .as_ref()
+        .and_then(|member| member.permissions)
+        .is_some_and(|permissions| permissions.manage_guild());
+    if !can_manage {
+        return Err(Error::NotManager.into());
+    }
+    let guild_id = command.guild_id.ok_or(Error::MissingGuildID)?;
+
+    let (subcommand, options) = match command.data.options().into_iter().next() {
+        Some(ResolvedOption {
+            name,
+            value: ResolvedValue::SubCommand(options),
+            ..
+        }) => (name, options),
+        _ => return Err(Error::MissingSubcommand.into()),
+    };
+    let mut setting = None;
+    let mut value = None;
+    for option in options {
+        match (option.name, option.value) {
+            ("setting", ResolvedValue::String(key)) => {
+                setting = Some(
+                    GuildSetting::from_key(key)
+                        .ok_or_else(|| Error::UnknownSetting(key.to_string()))?,
+                )
+            }
+            ("value", ResolvedValue::String(new_value)) => value = Some(new_value),
+            _ => {}
+        }
+    }
+
+    match subcommand {
+        "get" => {
+            let config = guild_configs.get(Some(guild_id)).await;
+            let overridden = guild_configs
+                .overridden(guild_id)
+                .await
+                .map_err(Error::from)?;
+            let settings = match setting {
+                Some(setting) => vec![setting],
+                None => GuildSetting::ALL.to_vec(),
+            };
+            Ok(settings
+                .into_iter()
+                .map(|setting| {
+                    let source = if overridden.contains(&setting) {
+                        ""
+                    } else {
+                        " *(default)*"
+                    };
+                    format!(
+                        "**{}**{source}: {}",
+                        setting.key(),
+                        display_value(&config, setting)
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n"))
+        }
+        "set" => {
+            let setting = setting.ok_or(Error::MissingSetting)?;
+            let value = value.ok_or(Error::MissingValue)?;
+            match guild_configs.set(guild_id, setting, value).await {
+                Ok(config) => Ok(format!(
+                    "**{}** is now: {}",
+                    setting.key(),
+                    display_value(&config, setting)
+                )),
+                // Tell the admin what was wrong with the value instead of failing the command.
+                Err(err @ guild_config::Error::InvalidValue(..)) => Ok(err.to_string()),
+                Err(err) => Err(Error::from(err).into()),
+            }
+        }
+        "reset" => {
+            let changed = guild_configs
+                .reset(guild_id, setting)
+                .await
+                .map_err(Error::from)?;
+            Ok(match (setting, changed) {
+                (Some(setting), true) => format!("**{}** is back to its default.", setting.key()),
+                (Some(setting), false) => format!("**{}** was not changed.", setting.key()),
+                (None, true) => "Every setting is back to its default.".to_string(),
+                (None, false) => "No setting was changed.".to_string(),
+            })
+        }
+        name => Err(Error::UnknownSubcommand(name.to_string()).into()),
+    }
+}
+
+fn setting_option(description: &str) -> CreateCommandOption {
+    GuildSetting::ALL.into_iter().fold(
+        CreateCommandOption::new(CommandOptionType::String, "setting", description),
+        |option, setting| {
+            option.add_string_choice(
+                format!("{}: {}", setting.key(), setting.description()),
+                setting.key(),
+            )
+        },
+    )
+}
+
+fn display_value(config: &GuildConfig, setting: GuildSetting) -> String {
+    let value = config.value(setting);
+    match (setting, value.is_empty()) {
+        (GuildSetting::Model, true) => "backend default".to_string(),
+        (GuildSetting::IngestChannels, true) => "every channel".to_string(),
+        (_, true) => "none".to_string(),
+        (GuildSetting::SystemPrompt | GuildSetting::Persona, false) => {
+            value.lines().map(|line| format!("\n> {line}")).collect()
+        }
+        (_, false) => value,
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Only members who can manage the server can change its settings")]
+    NotManager,
+    #[error("Command missing guild_id. It's likely the command was run from within dms.")]
+    MissingGuildID,
+    #[error("No subcommand was given")]
+    MissingSubcommand,
+    #[error("Unknown subcommand {0}")]
+    UnknownSubcommand(String),
+    #[error("Unknown setting {0}")]
+    UnknownSetting(String),
+    #[error("No setting was given")]
+    MissingSetting,
+    #[error("No value was given")]
+    MissingValue,
+    #[error("{0}")]
+    Config(#[from] guild_config::Error),
+}
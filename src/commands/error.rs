@@ -1,4 +1,5 @@
-use super::{ask, weigh_in};
+use super::{ask, backfill, config, privacy, recall, summarize, weigh_in};
+use crate::llm;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -8,6 +9,20 @@ pub enum Error {
     AskError(#[from] ask::Error),
     #[error("Weigh in command failed, {0}")]
     WeighInError(#[from] weigh_in::Error),
+    #[error("Recall command failed, {0}")]
+    RecallError(#[from] recall::Error),
+    #[error("Summarize command failed, {0}")]
+    SummarizeError(#[from] summarize::Error),
+    #[error("Backfill command failed, {0}")]
+    BackfillError(#[from] backfill::Error),
+    #[error("Config command failed, {0}")]
+    ConfigError(#[from] config::Error),
+    #[error("Privacy command failed, {0}")]
+    PrivacyError(#[from] privacy::Error),
     #[error("Command not implemented")]
     CommandNotImplemented,
+    #[error("Streamed response failed, {0}")]
+    StreamError(#[from] llm::error::Error),
+    #[error("Failed to send response to discord, {0}")]
+    DiscordError(#[from] serenity::Error),
 }
@@ -1,16 +1,116 @@
 mod ask;
+pub mod backfill;
+pub mod config;
 pub mod error;
+pub mod privacy;
+pub mod recall;
+pub mod reply;
+pub mod summarize;
+pub mod sync;
 pub mod weigh_in;
 
-use serenity::all::CreateCommand;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serenity::all::{CommandInteraction, Context, CreateCommand};
 
 use crate::{
-    commands::{ask::register_ask, weigh_in::register_weigh_in},
+    backfill::BackfillJobs,
+    database::Database,
     environment::Environment,
+    guild_config::{GuildConfig, GuildConfigs},
+    ingest::IngestQueue,
+    llm::engine::LlmEngine,
+    memory::ConversationMemory,
+    privacy::OptOutStore,
+    reload::Services,
 };
 
-pub fn get_commands(environment: &Environment) -> Vec<CreateCommand> {
-    vec![register_ask(environment), register_weigh_in(environment)]
+pub use ask::Ask;
+pub use backfill::Backfill;
+pub use config::Config;
+use error::Result;
+pub use privacy::Privacy;
+pub use recall::Recall;
+pub use reply::{Reply, ReplyMessage};
+pub use summarize::Summarize;
+pub use weigh_in::WeighIn;
+
+/// A slash command, registered with Discord under [`SlashCommand::name`].
+#[async_trait]
+pub trait SlashCommand: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn register(&self, environment: &Environment) -> CreateCommand;
+
+    /// Whether the response is only shown to the user running the command.
+    fn ephemeral(&self) -> bool {
+        false
+    }
+
+    /// Runs the command after its response was deferred.
+    async fn run(&self, ctx: &CommandContext<'_>) -> Result<Reply>;
+}
+
+/// What a command may use while it runs.
+pub struct CommandContext<'a> {
+    pub command: &'a CommandInteraction,
+    /// Discord's http client and cache.
+    pub discord: &'a Context,
+    pub services: &'a Services,
+    /// The engine completing with the model the guild chose.
+    pub llm_engine: &'a LlmEngine,
+    pub guild_config: &'a GuildConfig,
+    pub guild_configs: &'a GuildConfigs,
+    pub memory: &'a ConversationMemory,
+    pub ingest: &'a IngestQueue,
+    pub opt_outs: &'a Arc<OptOutStore>,
+    pub backfill_jobs: &'a Arc<BackfillJobs>,
+    pub database: &'a Database,
+}
+
+#[derive(Default)]
+pub struct CommandRegistry {
+    commands: Vec<Box<dyn SlashCommand>>,
 }
 
-pub use ask::run_ask;
+impl CommandRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registry holding every command the bot ships with.
+    pub fn with_default_commands() -> Self {
+        Self::new()
+            .register(Ask)
+            .register(WeighIn)
+            .register(Summarize)
+            .register(Recall)
+            .register(Backfill)
+            .register(Privacy)
+            .register(Config)
+    }
+
+    pub fn register(mut self, command: impl SlashCommand + 'static) -> Self {
+        self.commands.push(Box::new(command));
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn SlashCommand> {
+        self.commands
+            .iter()
+            .find(|command| command.name() == name)
+            .map(|command| command.as_ref())
+    }
+
+    pub fn create_commands(&self, environment: &Environment) -> Vec<CreateCommand> {
+        self.commands
+            .iter()
+            .map(|command| command.register(environment))
+            .collect()
+    }
+}
+
+pub fn get_commands(environment: &Environment) -> Vec<CreateCommand> {
+    CommandRegistry::with_default_commands().create_commands(environment)
+}
@@ -0,0 +1,167 @@
+use async_trait::async_trait;
+use serenity::all::{
+    CommandInteraction, CommandOptionType, CreateAttachment, CreateCommand, CreateCommandOption,
+};
+
+use super::{error::Result, CommandContext, Reply, ReplyMessage, SlashCommand};
+use crate::{
+    environment::Environment,
+    ingest::IngestQueue,
+    memory::ConversationMemory,
+    privacy::{self, export_user, forget_user, OptOutStore},
+    vec_db::db_handler::VdbHandler,
+};
+
+/// Largest file Discord accepts from a bot without boosts.
+const MAX_ATTACHMENT_SIZE: usize = 10 * 1024 * 1024;
+
+/// Running summaries mix everyone's messages, so they cannot be exported or deleted per user.
+const SUMMARY_NOTE: &str =
+    "Running summaries of channel conversations you took part in are not included.";
+
+pub struct Privacy;
+
+#[async_trait]
+impl SlashCommand for Privacy {
+    fn name(&self) -> &str {
+        "privacy"
+    }
+
+    fn register(&self, environment: &Environment) -> CreateCommand {
+        CreateCommand::new(self.name())
+            .description(format!(
+                "Control what {} remembers about you",
+                environment.bot_name
+            ))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "opt-out",
+                "Stop storing your new messages",
+            ))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "opt-in",
+                "Store your new messages again",
+            ))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "export",
+                "Download every message stored about you",
+            ))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "forget-me",
+                "Delete every message stored about you",
+            ))
+    }
+
+    /// The replies are only of interest to the user asking.
+    fn ephemeral(&self) -> bool {
+        true
+    }
+
+    async fn run(&self, ctx: &CommandContext<'_>) -> Result<Reply> {
+        run_privacy(
+            ctx.command,
+            &ctx.services.vec_db_client,
+            ctx.ingest,
+            ctx.memory,
+            ctx.opt_outs,
+        )
+        .await
+        .map(Reply::from)
+    }
+}
+
+async fn run_privacy(
+    command: &CommandInteraction,
+    vec_db_client: &VdbHandler,
+    ingest: &IngestQueue,
+    memory: &ConversationMemory,
+    opt_outs: &OptOutStore,
+) -> Result<ReplyMessage> {
+    let user_id = command.user.id;
+    let subcommand = command
+        .data
+        .options()
+        .into_iter()
+        .next()
+        .map(|option| option.name)
+        .ok_or(Error::MissingSubcommand)?;
+    Ok(match subcommand {
+        "opt-out" => {
+            let changed = opt_outs
+                .set_opted_out(user_id, true)
+                .await
+                .map_err(Error::Privacy)?;
+            // Messages sent just before opting out may still be waiting to be stored.
+            ingest.forget_author(user_id).await;
+            memory.forget_author(user_id).await;
+            ReplyMessage::text(if changed {
+                "Your new messages will no longer be stored. Use `/privacy forget-me` to also delete the ones already stored."
+            } else {
+                "You have already opted out."
+            })
+        }
+        "opt-in" => {
+            let changed = opt_outs
+                .set_opted_out(user_id, false)
+                .await
+                .map_err(Error::Privacy)?;
+            ReplyMessage::text(if changed {
+                "Your new messages will be stored again."
+            } else {
+                "Your messages are already being stored."
+            })
+        }
+        "export" => {
+            let export = export_user(vec_db_client, opt_outs, user_id)
+                .await
+                .map_err(Error::Privacy)?;
+            let json = serde_json::to_vec_pretty(&export).map_err(Error::Serialize)?;
+            if json.len() > MAX_ATTACHMENT_SIZE {
+                ReplyMessage::text(format!(
+                    "The {} messages stored about you are too many to send as one file.",
+                    export.messages.len()
+                ))
+            } else {
+                ReplyMessage::text(format!(
+                    "{} messages are stored about you. {SUMMARY_NOTE}",
+                    export.messages.len()
+                ))
+                .attachment(CreateAttachment::bytes(
+                    json,
+                    format!("privacy-export-{user_id}.json"),
+                ))
+            }
+        }
+        "forget-me" => {
+            ingest.forget_author(user_id).await;
+            memory.forget_author(user_id).await;
+            forget_user(vec_db_client, user_id)
+                .await
+                .map_err(Error::Privacy)?;
+            let note = if opt_outs.is_opted_out(user_id) {
+                ""
+            } else {
+                " New messages will still be stored unless you use `/privacy opt-out`."
+            };
+            ReplyMessage::text(format!(
+                "Every message stored about you was deleted. {SUMMARY_NOTE}{note}"
+            ))
+        }
+        name => return Err(Error::UnknownSubcommand(name.to_string()).into()),
+    })
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("No subcommand was given")]
+    MissingSubcommand,
+    #[error("Unknown subcommand {0}")]
+    UnknownSubcommand(String),
+    #[error("{0}")]
+    Privacy(#[from] privacy::Error),
+    #[error("Failed to serialize export, {0}")]
+    Serialize(serde_json::Error),
+}
@@ -0,0 +1,180 @@
+use async_trait::async_trait;
+use serenity::all::{
+    CommandOptionType, CreateButton, CreateCommand, CreateCommandOption, CreateEmbed, ResolvedValue,
+};
+
+use super::{error::Result, CommandContext, Reply, ReplyMessage, SlashCommand};
+use crate::{
+    environment::Environment,
+    llm::{self, backend::EmbedBackend},
+    permissions::{self, can_read, retain_readable, Reader, SEARCH_OVERFETCH},
+    vec_db::db_handler::SearchFilter,
+};
+
+const DEFAULT_RESULT_COUNT: u64 = 5;
+const MAX_RESULT_COUNT: u64 = 10;
+/// Longest excerpt of each message shown, keeping the results within one Discord message.
+const MAX_EXCERPT_LENGTH: usize = 150;
+
+pub struct Recall;
+
+#[async_trait]
+impl SlashCommand for Recall {
+    fn name(&self) -> &str {
+        "recall"
+    }
+
+    fn register(&self, environment: &Environment) -> CreateCommand {
+        CreateCommand::new(self.name())
+            .description(format!(
+                "Search what {} remembers of this server",
+                environment.bot_name
+            ))
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::String,
+                    "query",
+                    "What the messages you are looking for are about",
+                )
+                .max_length(300)
+                .required(true),
+            )
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::Channel,
+                "channel",
+                "Only search messages sent in this channel",
+            ))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::User,
+                "author",
+                "Only search messages sent by this user",
+            ))
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::Integer,
+                    "limit",
+                    "How many messages to return",
+                )
+                .min_int_value(1)
+                .max_int_value(MAX_RESULT_COUNT),
+            )
+    }
+
+    async fn run(&self, ctx: &CommandContext<'_>) -> Result<Reply> {
+        run_recall(ctx).await.map(Reply::from)
+    }
+}
+
+async fn run_recall(ctx: &CommandContext<'_>) -> Result<ReplyMessage> {
+    let command = ctx.command;
+    let guild_id = command.guild_id.ok_or(Error::MissingGuildID)?;
+    let member = command.member.as_deref().ok_or(Error::MissingGuildID)?;
+    let mut query = None;
+    let mut filter = SearchFilter {
+        guild_id: guild_id.get(),
+        channel_id: None,
+        author_id: None,
+        limit: DEFAULT_RESULT_COUNT,
+    };
+    for option in command.data.options() {
+        match (option.name, option.value) {
+            ("query", ResolvedValue::String(value)) => query = Some(value),
+            // Discord resolves the channel with the member's permissions in it.
+            ("channel", ResolvedValue::Channel(channel)) => {
+                if !channel.permissions.is_some_and(can_read) {
+                    return Ok(ReplyMessage::text(
+                        "You can only search channels you can read the history of.",
+                    ));
+                }
+                filter.channel_id = Some(channel.id.get())
+            }
+            ("author", ResolvedValue::User(user, _)) => filter.author_id = Some(user.id.get()),
+            ("limit", ResolvedValue::Integer(limit)) => {
+                filter.limit = (limit.max(1) as u64).min(MAX_RESULT_COUNT)
+            }
+            _ => {}
+        }
+    }
+    let query = query.ok_or(Error::MissingQuery)?;
+    let limit = filter.limit as usize;
+    filter.limit *= SEARCH_OVERFETCH;
+
+    let embedding = ctx
+        .services
+        .llm_engine
+        .get_embed(query)
+        .await
+        .map_err(Error::from)?;
+    let results = ctx
+        .services
+        .vec_db_client
+        .search_vectors(embedding, &filter)
+        .await
+        .map_err(Error::VectorDB)?;
+    let results = retain_readable(
+        &ctx.discord.cache,
+        &ctx.discord.http,
+        Reader { guild_id, member },
+        results,
+        |result| result.vector.channel_id,
+        limit,
+    )
+    .await
+    .map_err(Error::from)?;
+    if results.is_empty() {
+        return Ok(ReplyMessage::text(format!(
+            "**Recall**: *{query}*\nNo matching messages found."
+        )));
+    }
+
+    let mut embed = CreateEmbed::new()
+        .title("Recall")
+        .description(format!("*{query}*"));
+    let mut buttons = Vec::new();
+    for (index, result) in results.into_iter().enumerate() {
+        let point = result.vector;
+        let author = point
+            .author_name
+            .clone()
+            .unwrap_or("Unknown author".to_string());
+        let sent_at = point
+            .timestamp
+            .map(|timestamp| format!("<t:{timestamp}:f>"))
+            .unwrap_or("unknown time".to_string());
+        embed = embed.field(
+            format!("{}. {} · {:.2}", index + 1, author, result.score),
+            format!("{}\n> {}", sent_at, excerpt(&point.message)),
+            false,
+        );
+        if let Some(link) = point.link() {
+            buttons.push(CreateButton::new_link(link).label(format!("Jump to {}", index + 1)));
+        }
+    }
+
+    Ok(buttons
+        .into_iter()
+        .fold(ReplyMessage::default().embed(embed), ReplyMessage::button))
+}
+
+fn excerpt(message: &str) -> String {
+    let single_line = message.replace('\n', " ");
+    if single_line.chars().count() <= MAX_EXCERPT_LENGTH {
+        return single_line;
+    }
+    let truncated: String = single_line.chars().take(MAX_EXCERPT_LENGTH).collect();
+    format!("{truncated}…")
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Missing query")]
+    MissingQuery,
+    #[error("failed to embed query, {0}")]
+    LlmError(#[from] llm::error::Error),
+    #[error("Failed to retrieve response from vector database client.\n{0}")]
+    VectorDB(anyhow::Error),
+    #[error("Command missing guild_id. It's likely the command was run from within dms.")]
+    MissingGuildID,
+    #[error("{0}")]
+    Permissions(#[from] permissions::Error),
+}
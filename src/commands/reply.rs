@@ -0,0 +1,68 @@
+use serenity::all::{CreateAttachment, CreateButton, CreateEmbed};
+
+use crate::llm::backend::ChatStream;
+
+/// What a command answers with, sent by the handler.
+pub enum Reply {
+    Message(ReplyMessage),
+    /// A completion written into the response as it is generated.
+    Stream(ChatStream),
+}
+
+/// A response message. Text too long for a Discord message is moved into an embed, or into a
+/// markdown attachment if it is too long for an embed as well.
+#[derive(Default)]
+pub struct ReplyMessage {
+    pub content: String,
+    pub embeds: Vec<CreateEmbed>,
+    pub attachments: Vec<CreateAttachment>,
+    /// Shown in rows under the message.
+    pub buttons: Vec<CreateButton>,
+    /// Whether the model wrote the content, which adds a footer naming the model and how long
+    /// the answer took.
+    pub generated: bool,
+}
+
+impl ReplyMessage {
+    pub fn text(content: impl Into<String>) -> Self {
+        Self {
+            content: content.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Content written by the model.
+    pub fn generated(content: impl Into<String>) -> Self {
+        Self {
+            generated: true,
+            ..Self::text(content)
+        }
+    }
+
+    pub fn embed(mut self, embed: CreateEmbed) -> Self {
+        self.embeds.push(embed);
+        self
+    }
+
+    pub fn attachment(mut self, attachment: CreateAttachment) -> Self {
+        self.attachments.push(attachment);
+        self
+    }
+
+    pub fn button(mut self, button: CreateButton) -> Self {
+        self.buttons.push(button);
+        self
+    }
+}
+
+impl From<ReplyMessage> for Reply {
+    fn from(message: ReplyMessage) -> Self {
+        Reply::Message(message)
+    }
+}
+
+impl From<String> for Reply {
+    fn from(text: String) -> Self {
+        ReplyMessage::text(text).into()
+    }
+}
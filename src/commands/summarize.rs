@@ -0,0 +1,412 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommand, CreateCommandOption,
+    MessagePagination, ResolvedValue, Timestamp,
+};
+
+use super::{error::Result, CommandContext, Reply, ReplyMessage, SlashCommand};
+use crate::{
+    backfill::HISTORY_PAGE_SIZE,
+    environment::Environment,
+    llm::{
+        self,
+        backend::ChatBackend,
+        model::{SystemMessage, UserMessage},
+    },
+    memory::estimate_tokens,
+};
+
+const DEFAULT_MESSAGE_COUNT: usize = 100;
+const MAX_MESSAGE_COUNT: usize = 1000;
+/// Longest `since` accepted, reaching back before Discord existed.
+const MAX_SINCE: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
+const MAP_PROMPT: &str = "Summarise the following Discord conversation as a short bulleted list. Name who said what. Each message starts with its number in square brackets, cite the key messages a bullet is based on using those numbers, e.g. [12]. Reply with the bullet points only.";
+const REDUCE_PROMPT: &str = "Combine the following partial summaries of one Discord conversation into a single short bulleted list, merging overlapping points. Keep the names and the message numbers in square brackets, e.g. [12]. Reply with the bullet points only.";
+
+pub struct Summarize;
+
+#[async_trait]
+impl SlashCommand for Summarize {
+    fn name(&self) -> &str {
+        "summarize"
+    }
+
+    fn register(&self, environment: &Environment) -> CreateCommand {
+        CreateCommand::new(self.name())
+            .description(format!(
+                "Ask {} to summarise what was said in this channel",
+                environment.bot_name
+            ))
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::Integer,
+                    "count",
+                    "How many of the latest messages to summarise",
+                )
+                .min_int_value(1)
+                .max_int_value(MAX_MESSAGE_COUNT as u64),
+            )
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::String,
+                "since",
+                "Summarise messages sent within this duration, e.g. 30m, 2h or 1d",
+            ))
+    }
+
+    async fn run(&self, ctx: &CommandContext<'_>) -> Result<Reply> {
+        run_summarize(ctx).await.map(Reply::from)
+    }
+}
+
+async fn run_summarize(ctx: &CommandContext<'_>) -> Result<ReplyMessage> {
+    let llm_engine = ctx.llm_engine;
+    let mut count = None;
+    let mut since = None;
+    for option in ctx.command.data.options() {
+        match (option.name, option.value) {
+            ("count", ResolvedValue::Integer(value)) => {
+                count = Some((value.max(1) as usize).min(MAX_MESSAGE_COUNT))
+            }
+            ("since", ResolvedValue::String(value)) => {
+                since =
+                    Some(parse_duration(value).ok_or(Error::InvalidDuration(value.to_string()))?)
+            }
+            _ => {}
+        }
+    }
+    let count = match (count, since) {
+        (Some(count), _) => count,
+        (None, Some(_)) => MAX_MESSAGE_COUNT,
+        (None, None) => DEFAULT_MESSAGE_COUNT,
+    };
+    let cutoff = since.map(|since| Timestamp::now().unix_timestamp() - since.as_secs() as i64);
+
+    let messages = get_channel_history(ctx.command, ctx.discord, count, cutoff).await?;
+    if messages.is_empty() {
+        return Ok(ReplyMessage::text("There is nothing to summarise."));
+    }
+
+    let links: Vec<String> = messages.iter().map(|message| message.link()).collect();
+    let lines: Vec<String> = messages
+        .iter()
+        .enumerate()
+        .map(|(index, message)| {
+            format!(
+                "[{}] ({}) {}: {}",
+                index + 1,
+                message.timestamp.format("%d/%m/%Y %H:%M"),
+                message.author.name,
+                message.content
+            )
+        })
+        .collect();
+
+    // Leave half of the context window for the instructions and the model's answer.
+    let chunk_budget = ctx.services.environment.llm.context_window / 2;
+    let mut summaries = Vec::new();
+    for chunk in chunk_by_tokens(lines, chunk_budget, 1) {
+        summaries.push(summarise(llm_engine, MAP_PROMPT, chunk).await?);
+    }
+    // Every reduce chunk merges at least two summaries, so each pass shrinks the list even when
+    // the summaries are too large to share a chunk within the budget.
+    while summaries.len() > 1 {
+        let mut reduced = Vec::new();
+        for chunk in chunk_by_tokens(summaries, chunk_budget, 2) {
+            reduced.push(summarise(llm_engine, REDUCE_PROMPT, chunk).await?);
+        }
+        summaries = reduced;
+    }
+    let summary = summaries
+        .pop()
+        .ok_or(llm::error::Error::EmptyResponseError)?;
+
+    Ok(ReplyMessage::generated(format!(
+        "**Summary of the last {} messages**\n{}",
+        messages.len(),
+        link_references(&summary, &links)
+    )))
+}
+
+/// Pages back through the channel history until `count` messages are found or a message older
+/// than `cutoff` is reached, returning the messages oldest first.
+async fn get_channel_history(
+    command: &CommandInteraction,
+    ctx: &Context,
+    count: usize,
+    cutoff: Option<i64>,
+) -> Result<Vec<serenity::all::Message>> {
+    let mut history = Vec::new();
+    let mut before = None;
+    'paging: while history.len() < count {
+        let page_size = (count - history.len()).min(HISTORY_PAGE_SIZE as usize) as u8;
+        let page = ctx
+            .http
+            .get_messages(
+                command.channel_id,
+                before.map(MessagePagination::Before),
+                Some(page_size),
+            )
+            .await
+            .map_err(Error::from)?;
+        let Some(oldest) = page.last() else {
+            break;
+        };
+        before = Some(oldest.id);
+        let page_len = page.len();
+
+        for message in page {
+            if cutoff.is_some_and(|cutoff| message.timestamp.unix_timestamp() < cutoff) {
+                break 'paging;
+            }
+            if !message.content.trim().is_empty() {
+                history.push(message);
+            }
+        }
+        if page_len < page_size as usize {
+            break;
+        }
+    }
+    history.reverse();
+    Ok(history)
+}
+
+async fn summarise(
+    llm_engine: &dyn ChatBackend,
+    prompt: &str,
+    lines: Vec<String>,
+) -> Result<String> {
+    let llm_context = vec![
+        SystemMessage {
+            content: prompt.to_string(),
+        }
+        .into(),
+        UserMessage {
+            content: lines.join("\n"),
+        }
+        .into(),
+    ];
+    Ok(llm_engine
+        .get_chat_completion(llm_context)
+        .await
+        .map_err(Error::from)?)
+}
+
+/// Groups lines so the estimated token count of each group stays within `budget`, unless that
+/// would leave a group with fewer than `min_lines` lines. Only the last group can be smaller.
+fn chunk_by_tokens(lines: Vec<String>, budget: usize, min_lines: usize) -> Vec<Vec<String>> {
+    let mut chunks: Vec<Vec<String>> = Vec::new();
+    let mut chunk_tokens = 0;
+    for line in lines {
+        let tokens = estimate_tokens(&line);
+        match chunks.last_mut() {
+            Some(chunk) if chunk_tokens + tokens <= budget || chunk.len() < min_lines => {
+                chunk_tokens += tokens;
+                chunk.push(line);
+            }
+            _ => {
+                chunk_tokens = tokens;
+                chunks.push(vec![line]);
+            }
+        }
+    }
+    chunks
+}
+
+/// Turns message references like `[12]` into markdown links to the message.
+fn link_references(summary: &str, links: &[String]) -> String {
+    let mut linked = String::with_capacity(summary.len());
+    let mut rest = summary;
+    while let Some(start) = rest.find('[') {
+        linked.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let reference = rest[1..].find(']').and_then(|end| {
+            let index = rest[1..=end].parse::<usize>().ok()?;
+            let link = links.get(index.checked_sub(1)?)?;
+            Some((end + 2, link))
+        });
+        match reference {
+            Some((length, link)) if !rest[length..].starts_with('(') => {
+                linked.push_str(&format!("[{}](<{}>)", &rest[..length], link));
+                rest = &rest[length..];
+            }
+            _ => {
+                linked.push('[');
+                rest = &rest[1..];
+            }
+        }
+    }
+    linked.push_str(rest);
+    linked
+}
+
+/// Parses durations such as `45s`, `30m`, `2h`, `1d`, `1w` or combinations like `1h30m`, up to
+/// [`MAX_SINCE`].
+fn parse_duration(value: &str) -> Option<Duration> {
+    let mut total_secs: u64 = 0;
+    let mut number = String::new();
+    for character in value.trim().chars() {
+        if character.is_ascii_digit() {
+            number.push(character);
+            continue;
+        }
+        let unit_secs = match character.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return None,
+        };
+        let amount: u64 = number.parse().ok()?;
+        total_secs = total_secs.checked_add(amount.checked_mul(unit_secs)?)?;
+        number.clear();
+    }
+    if !number.is_empty() || total_secs == 0 || total_secs > MAX_SINCE.as_secs() {
+        return None;
+    }
+    Some(Duration::from_secs(total_secs))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to retrieve channel history, {0}")]
+    GetChannelFailed(#[from] serenity::Error),
+    #[error("failed to retrieve response from llm, {0}")]
+    LlmError(#[from] llm::error::Error),
+    #[error("Invalid duration {0}, expected something like 30m, 2h or 1d")]
+    InvalidDuration(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(contents: &[&str]) -> Vec<String> {
+        contents.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn chunks_stay_within_budget() {
+        // Four characters per token.
+        let chunks = chunk_by_tokens(lines(&["aaaa", "bbbb", "cccc", "dddd", "eeee"]), 2, 1);
+        assert_eq!(
+            chunks,
+            [
+                lines(&["aaaa", "bbbb"]),
+                lines(&["cccc", "dddd"]),
+                lines(&["eeee"])
+            ]
+        );
+    }
+
+    #[test]
+    fn oversized_line_gets_a_chunk_of_its_own() {
+        let long = "x".repeat(40);
+        let chunks = chunk_by_tokens(lines(&["aaaa", &long, "bbbb"]), 2, 1);
+        assert_eq!(
+            chunks,
+            [lines(&["aaaa"]), lines(&[&long]), lines(&["bbbb"])]
+        );
+    }
+
+    #[test]
+    fn reduce_chunks_always_merge_summaries() {
+        // Every summary is over the budget on its own, which used to leave one per chunk and
+        // the reduce loop running forever.
+        let summary = "s".repeat(40);
+        let summaries = vec![summary; 5];
+        let chunks = chunk_by_tokens(summaries, 8, 2);
+        assert_eq!(chunks.iter().map(Vec::len).collect::<Vec<_>>(), [2, 2, 1]);
+
+        let mut count = 7;
+        let mut passes = 0;
+        while count > 1 {
+            count = chunk_by_tokens(vec!["s".repeat(40); count], 8, 2).len();
+            passes += 1;
+        }
+        assert_eq!(passes, 3);
+    }
+
+    #[test]
+    fn no_lines_make_no_chunks() {
+        assert!(chunk_by_tokens(Vec::new(), 10, 2).is_empty());
+    }
+
+    #[test]
+    fn references_become_jump_links() {
+        let links = lines(&["https://discord.com/1", "https://discord.com/2"]);
+        assert_eq!(
+            link_references("- Alice asked [1], Bob answered [2]", &links),
+            "- Alice asked [[1]](<https://discord.com/1>), Bob answered [[2]](<https://discord.com/2>)"
+        );
+    }
+
+    #[test]
+    fn unknown_references_and_existing_links_are_left_alone() {
+        let links = lines(&["https://discord.com/1"]);
+        for summary in [
+            "see [0] and [3]",
+            "a [link](https://example.com)",
+            "an [1](https://example.com) link",
+            "[not a number] and [",
+            "unclosed [1",
+        ] {
+            assert_eq!(link_references(summary, &links), summary);
+        }
+    }
+
+    #[test]
+    fn references_keep_multibyte_text_intact() {
+        let links = lines(&["https://discord.com/1"]);
+        assert_eq!(
+            link_references("Zoë said 👋 [1]", &links),
+            "Zoë said 👋 [[1]](<https://discord.com/1>)"
+        );
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("45s"), Some(Duration::from_secs(45)));
+        assert_eq!(parse_duration("30m"), Some(Duration::from_secs(30 * 60)));
+        assert_eq!(
+            parse_duration(" 2H "),
+            Some(Duration::from_secs(2 * 60 * 60))
+        );
+        assert_eq!(
+            parse_duration("1d12h"),
+            Some(Duration::from_secs(36 * 60 * 60))
+        );
+        assert_eq!(
+            parse_duration("1w"),
+            Some(Duration::from_secs(7 * 24 * 60 * 60))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_durations() {
+        for value in ["", "0m", "10", "h", "1x", "1h30", "-1h", "1.5h"] {
+            assert_eq!(parse_duration(value), None, "{value}");
+        }
+    }
+
+    #[test]
+    fn rejects_durations_past_the_limit() {
+        assert_eq!(
+            parse_duration("5200w"),
+            Some(Duration::from_secs(5200 * 7 * 24 * 60 * 60))
+        );
+        assert_eq!(parse_duration("99999999999w"), None);
+        assert_eq!(parse_duration("99999999999999999999s"), None);
+        assert_eq!(
+            parse_duration(&format!("{}s", MAX_SINCE.as_secs())),
+            Some(MAX_SINCE)
+        );
+        assert_eq!(
+            parse_duration(&format!("{}s", MAX_SINCE.as_secs() + 1)),
+            None
+        );
+    }
+}
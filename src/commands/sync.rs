@@ -0,0 +1,341 @@
+use serde_json::{Map, Value};
+use serenity::{
+    all::{ApplicationId, CommandId, CreateCommand, GuildId},
+    http::Http,
+};
+
+use super::get_commands;
+use crate::environment::{CommandOptions, Environment};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Top level command fields compared when syncing. Everything else, such as ids and versions,
+/// is set by Discord.
+const COMPARED_FIELDS: [&str; 6] = [
+    "name",
+    "description",
+    "options",
+    "default_member_permissions",
+    "dm_permission",
+    "nsfw",
+];
+
+/// Where commands are registered.
+#[derive(Debug, Clone, Copy)]
+enum CommandScope {
+    /// Every guild and DMs, taking up to an hour to reach every client.
+    Global,
+    /// A single guild, updated instantly.
+    Guild(GuildId),
+}
+
+/// What a sync changed.
+#[derive(Debug, Default)]
+struct SyncReport {
+    created: usize,
+    updated: usize,
+    deleted: usize,
+    unchanged: usize,
+}
+
+/// Sets the application id on `http`, from the configuration or else the application the
+/// token belongs to.
+pub async fn resolve_application_id(
+    http: &Http,
+    options: &CommandOptions,
+) -> Result<ApplicationId> {
+    let application_id = match options.application_id {
+        Some(application_id) => ApplicationId::new(application_id),
+        None => http.get_current_application_info().await?.id,
+    };
+    http.set_application_id(application_id);
+    Ok(application_id)
+}
+
+/// Brings the registered slash commands in line with `get_commands`, creating, editing and
+/// deleting only the commands that differ. With `commands.dev_guilds` set the commands are
+/// registered in those guilds instead of globally, and global commands are left alone.
+pub async fn sync_commands(http: &Http, environment: &Environment) -> Result<()> {
+    let application_id = resolve_application_id(http, &environment.commands).await?;
+    println!("Registering commands for application {application_id}");
+    let commands = get_commands(environment);
+    let scopes = if environment.commands.dev_guilds.is_empty() {
+        vec![CommandScope::Global]
+    } else {
+        environment
+            .commands
+            .dev_guilds
+            .iter()
+            .map(|guild_id| CommandScope::Guild(GuildId::new(*guild_id)))
+            .collect()
+    };
+    for scope in scopes {
+        let report = sync_scope(http, scope, &commands).await?;
+        println!(
+            "Synced {scope} commands, {} created, {} updated, {} deleted, {} unchanged",
+            report.created, report.updated, report.deleted, report.unchanged
+        );
+    }
+    Ok(())
+}
+
+async fn sync_scope(
+    http: &Http,
+    scope: CommandScope,
+    commands: &[CreateCommand],
+) -> Result<SyncReport> {
+    let mut existing = match scope {
+        CommandScope::Global => http.get_global_commands().await?,
+        CommandScope::Guild(guild_id) => http.get_guild_commands(guild_id).await?,
+    };
+    let mut report = SyncReport::default();
+    for command in commands {
+        let wanted = comparable(serde_json::to_value(command)?, scope);
+        let name = wanted
+            .get("name")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let position = existing
+            .iter()
+            .position(|registered| registered.name == name);
+        let Some(registered) = position.map(|position| existing.swap_remove(position)) else {
+            create_command(http, scope, command).await?;
+            report.created += 1;
+            continue;
+        };
+        if comparable(serde_json::to_value(&registered)?, scope) == wanted {
+            report.unchanged += 1;
+        } else {
+            edit_command(http, scope, registered.id, command).await?;
+            report.updated += 1;
+        }
+    }
+    // Whatever is left was removed from the bot.
+    for registered in existing {
+        println!("Deleting {scope} command {}", registered.name);
+        match scope {
+            CommandScope::Global => http.delete_global_command(registered.id).await?,
+            CommandScope::Guild(guild_id) => {
+                http.delete_guild_command(guild_id, registered.id).await?
+            }
+        }
+        report.deleted += 1;
+    }
+    Ok(report)
+}
+
+async fn create_command(http: &Http, scope: CommandScope, command: &CreateCommand) -> Result<()> {
+    match scope {
+        CommandScope::Global => http.create_global_command(command).await?,
+        CommandScope::Guild(guild_id) => http.create_guild_command(guild_id, command).await?,
+    };
+    Ok(())
+}
+
+async fn edit_command(
+    http: &Http,
+    scope: CommandScope,
+    command_id: CommandId,
+    command: &CreateCommand,
+) -> Result<()> {
+    match scope {
+        CommandScope::Global => http.edit_global_command(command_id, command).await?,
+        CommandScope::Guild(guild_id) => {
+            http.edit_guild_command(guild_id, command_id, command)
+                .await?
+        }
+    };
+    Ok(())
+}
+
+/// The fields of a command Discord would store, with the defaults Discord fills in removed so
+/// a command built by the bot compares equal to the same command fetched back.
+fn comparable(command: Value, scope: CommandScope) -> Value {
+    let Value::Object(mut fields) = command else {
+        return command;
+    };
+    // Commands are usable in DMs unless disabled, and guild commands never are.
+    match scope {
+        CommandScope::Global => {
+            let dm_permission = fields.remove("dm_permission");
+            fields.insert(
+                "dm_permission".to_string(),
+                dm_permission
+                    .filter(|value| !value.is_null())
+                    .unwrap_or(Value::Bool(true)),
+            );
+        }
+        CommandScope::Guild(_) => {
+            fields.remove("dm_permission");
+        }
+    }
+    let fields: Map<String, Value> = COMPARED_FIELDS
+        .into_iter()
+        .filter_map(|field| {
+            let value = fields.remove(field)?;
+            Some((field.to_string(), value))
+        })
+        .collect();
+    strip_defaults(Value::Object(fields)).unwrap_or(Value::Null)
+}
+
+/// Removes unset, empty and false values and localizations, and reads every number as a float,
+/// returning `None` when nothing is left of `value`.
+fn strip_defaults(value: Value) -> Option<Value> {
+    match value {
+        Value::Null | Value::Bool(false) => None,
+        Value::Number(number) => number.as_f64().map(Value::from),
+        Value::Array(values) => {
+            let values: Vec<Value> = values.into_iter().filter_map(strip_defaults).collect();
+            (!values.is_empty()).then_some(Value::Array(values))
+        }
+        Value::Object(fields) => {
+            let fields: Map<String, Value> = fields
+                .into_iter()
+                .filter(|(key, _)| !key.ends_with("_localizations") && !key.ends_with("_localized"))
+                .filter_map(|(key, value)| Some((key, strip_defaults(value)?)))
+                .collect();
+            (!fields.is_empty()).then_some(Value::Object(fields))
+        }
+        value => Some(value),
+    }
+}
+
+impl std::fmt::Display for CommandScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommandScope::Global => write!(f, "global"),
+            CommandScope::Guild(guild_id) => write!(f, "guild {guild_id}"),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Discord request failed, {0}")]
+    Discord(#[from] serenity::Error),
+    #[error("Failed to serialize command, {0}")]
+    Serialize(#[from] serde_json::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use serenity::all::{CommandOptionType, CreateCommandOption, Permissions};
+
+    use super::*;
+
+    fn command() -> CreateCommand {
+        CreateCommand::new("recall")
+            .description("Search stored messages")
+            .default_member_permissions(Permissions::MANAGE_GUILD)
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::String, "query", "What to look for")
+                    .required(true),
+            )
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::Integer, "limit", "Results to show")
+                    .min_int_value(1)
+                    .max_int_value(10),
+            )
+    }
+
+    /// `command` as Discord returns it once registered.
+    fn registered() -> Value {
+        json!({
+            "id": "1200000000000000000",
+            "application_id": "1100000000000000000",
+            "version": "1200000000000000001",
+            "type": 1,
+            "name": "recall",
+            "name_localizations": null,
+            "description": "Search stored messages",
+            "description_localized": "Search stored messages",
+            "default_member_permissions": "32",
+            "dm_permission": true,
+            "nsfw": false,
+            "options": [
+                {
+                    "type": 3,
+                    "name": "query",
+                    "description": "What to look for",
+                    "required": true,
+                    "description_localizations": {}
+                },
+                {
+                    "type": 4,
+                    "name": "limit",
+                    "description": "Results to show",
+                    "min_value": 1.0,
+                    "max_value": 10
+                }
+            ]
+        })
+    }
+
+    fn wanted(scope: CommandScope) -> Value {
+        comparable(serde_json::to_value(command()).unwrap(), scope)
+    }
+
+    #[test]
+    fn a_registered_command_compares_equal() {
+        for scope in [CommandScope::Global, CommandScope::Guild(GuildId::new(1))] {
+            assert_eq!(comparable(registered(), scope), wanted(scope));
+        }
+    }
+
+    #[test]
+    fn changes_to_compared_fields_are_noticed() {
+        let scope = CommandScope::Global;
+        let mut changed = registered();
+        changed["description"] = json!("Search old messages");
+        assert_ne!(comparable(changed, scope), wanted(scope));
+
+        let mut changed = registered();
+        changed["options"][1]["max_value"] = json!(20);
+        assert_ne!(comparable(changed, scope), wanted(scope));
+
+        let mut changed = registered();
+        changed["options"][0]["required"] = json!(false);
+        assert_ne!(comparable(changed, scope), wanted(scope));
+    }
+
+    #[test]
+    fn dm_permission_only_matters_globally() {
+        let mut disabled = registered();
+        disabled["dm_permission"] = json!(false);
+        assert_ne!(
+            comparable(disabled.clone(), CommandScope::Global),
+            wanted(CommandScope::Global)
+        );
+
+        let guild = CommandScope::Guild(GuildId::new(1));
+        assert_eq!(comparable(disabled, guild), wanted(guild));
+
+        // Discord leaves the field unset for commands usable in DMs.
+        let mut unset = registered();
+        unset["dm_permission"] = Value::Null;
+        assert_eq!(
+            comparable(unset, CommandScope::Global),
+            wanted(CommandScope::Global)
+        );
+    }
+
+    #[test]
+    fn strips_unset_values_and_localizations() {
+        assert_eq!(
+            strip_defaults(json!({
+                "name": "ask",
+                "count": 2,
+                "enabled": false,
+                "missing": null,
+                "choices": [],
+                "nested": { "empty": null },
+                "name_localizations": { "fr": "demander" },
+                "list": [null, false, 1.5],
+            })),
+            Some(json!({ "name": "ask", "count": 2.0, "list": [1.5] }))
+        );
+        assert_eq!(strip_defaults(json!({ "choices": [null] })), None);
+    }
+}
@@ -1,91 +1,49 @@
-use serde_json::error;
-use serenity::all::{CommandInteraction, CreateCommand};
+use async_trait::async_trait;
+use serenity::all::CreateCommand;
 
-use super::error::*;
+use super::{error::*, CommandContext, Reply, ReplyMessage, SlashCommand};
 use crate::{
     environment::Environment,
     llm::{
         self,
-        engine::LlmEngine,
-        model::{LlmMessage, SystemMessage, UserMessage},
+        backend::{ChatBackend, ChatStream},
+        model::{LlmChat, LlmMessage, SystemMessage},
     },
-    vec_db::db_handler::VdbHandler,
+    memory,
+    permissions::Reader,
+    rag::{self, find_near_messages, generate_relevant_message_prompt},
 };
 
-pub fn register_weigh_in(environment: &Environment) -> CreateCommand {
-    CreateCommand::new("weigh-in").description(format!(
-        "Ask {} to comment on recent messages",
-        environment.bot_name
-    ))
-}
-pub async fn run_weigh_in<'a>(
-    command: &CommandInteraction,
-    llm_engine: &'a LlmEngine,
-    vec_db_client: &'a VdbHandler,
-    http_client: &serenity::http::Http,
-    environment: &Environment,
-) -> Result<String> {
-    let channel_id = command.channel_id;
-    let latest_messages = http_client
-        .get_messages(
-            channel_id,
-            None,
-            Some(
-                environment
-                    .memory
-                    .max_message_count
-                    .try_into()
-                    .expect("Max memory count could not be parsed into u8"),
-            ),
-        )
-        .await
-        .map_err(Error::from)?;
+pub struct WeighIn;
 
-    let user_messages: UserMessage = latest_messages
-        .into_iter()
-        .rev()
-        .filter(|message| !message.author.bot)
-        .fold(
-            UserMessage {
-                content: String::new(),
-            },
-            |mut acc, message| {
-                let user_message = format!(
-                    "({}) {} said: `{}`\n",
-                    message.timestamp.format("%d/%m/%Y %H:%M"),
-                    message.author.name,
-                    message.content
-                );
-                acc.content.push_str(&user_message);
-                acc
-            },
-        );
-    let relevant_messages = find_near_messages(
-        &user_messages.content,
-        command
-            .guild_id
-            .ok_or(Error::MissingGuildID)?
-            .get()
-            .to_string()
-            .as_str(),
-        llm_engine,
-        vec_db_client,
-    )
-    .await?;
+#[async_trait]
+impl SlashCommand for WeighIn {
+    fn name(&self) -> &str {
+        "weigh-in"
+    }
 
-    let compiled_user_messages = user_messages.into();
+    fn register(&self, environment: &Environment) -> CreateCommand {
+        CreateCommand::new(self.name()).description(format!(
+            "Ask {} to comment on recent messages",
+            environment.bot_name
+        ))
+    }
 
-    let system_message = SystemMessage {
-        content: environment.llm.system_prompt.to_string()
-            + "\n"
-            + generate_relevant_message_prompt(relevant_messages)
-                .unwrap_or("".to_string())
-                .as_str(),
+    async fn run(&self, ctx: &CommandContext<'_>) -> Result<Reply> {
+        if ctx.services.environment.llm.stream {
+            stream_weigh_in(ctx).await.map(Reply::Stream)
+        } else {
+            let response = run_weigh_in(ctx).await?;
+            Ok(ReplyMessage::generated(response).into())
+        }
     }
-    .into();
-    let llm_context = vec![system_message, compiled_user_messages];
+}
+
+async fn run_weigh_in(ctx: &CommandContext<'_>) -> Result<String> {
+    let llm_context = build_weigh_in_context(ctx).await?;
 
-    Ok(llm_engine
+    Ok(ctx
+        .llm_engine
         .get_chat_completion(llm_context)
         .await
         .and_then(|str_response| {
@@ -98,36 +56,74 @@ pub async fn run_weigh_in<'a>(
         .map_err(Error::from)?)
 }
 
-async fn find_near_messages<'a>(
-    message: &'a str,
-    guild_id: &str,
-    llm_engine: &'a LlmEngine,
-    vec_db_client: &'a VdbHandler,
-) -> Result<Vec<String>> {
-    let message = message.to_string();
-    let embedding = llm_engine.get_embed(message).await.map_err(Error::from)?;
-    let close_messages = vec_db_client
-        .get_close_vectors(embedding, guild_id)
+/// Same as [`run_weigh_in`], but yields the response as the model generates it.
+async fn stream_weigh_in(ctx: &CommandContext<'_>) -> Result<ChatStream> {
+    let llm_context = build_weigh_in_context(ctx).await?;
+
+    Ok(ctx
+        .llm_engine
+        .stream_chat_completion(llm_context)
         .await
-        .map_err(Error::VectorDB)?;
-    Ok(close_messages
-        .into_iter()
-        .map(|point| point.message)
-        .collect())
+        .map_err(Error::from)?)
 }
 
-fn generate_relevant_message_prompt(messages: Vec<String>) -> Option<String> {
-    Some(format!( "Using RAG retrieval, the following messages may or may not contain relevant information of messages that were sent in the past.\nRETRIEVED_MESSAGES\n{}\nEND_OF_RETRIEVED_MESSAGES", messages.into_iter().reduce(|acc, msg| acc + "\n" + &msg)?))
+async fn build_weigh_in_context(ctx: &CommandContext<'_>) -> Result<LlmChat> {
+    let guild_config = ctx.guild_config;
+    let history = ctx
+        .memory
+        .history(
+            ctx.command.guild_id,
+            ctx.command.channel_id,
+            ctx.discord,
+            guild_config.memory_size,
+        )
+        .await
+        .map_err(Error::from)?;
+
+    let guild_id = ctx.command.guild_id.ok_or(Error::MissingGuildID)?;
+    let member = ctx.command.member.as_deref().ok_or(Error::MissingGuildID)?;
+    let relevant_messages = if guild_config.rag {
+        let recent_user_messages = history
+            .iter()
+            .filter_map(|message| match message {
+                LlmMessage::UserMessage(message) => Some(message.content.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        find_near_messages(
+            &recent_user_messages,
+            Reader { guild_id, member },
+            guild_config.rag_top_k,
+            ctx.discord,
+            ctx.llm_engine,
+            &ctx.services.vec_db_client,
+        )
+        .await
+        .map_err(Error::from)?
+    } else {
+        vec![]
+    };
+
+    let system_message = SystemMessage {
+        content: guild_config.prompt()
+            + "\n"
+            + generate_relevant_message_prompt(relevant_messages)
+                .unwrap_or("".to_string())
+                .as_str(),
+    }
+    .into();
+    Ok(std::iter::once(system_message).chain(history).collect())
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
-    #[error("failed to retrieve latest_messages, {0}")]
-    GetChannelFailed(#[from] serenity::Error),
+    #[error("failed to retrieve conversation history, {0}")]
+    Memory(#[from] memory::Error),
     #[error("failed to retrieve response from llm, {0}")]
     LlmError(#[from] llm::error::Error),
-    #[error("Failed to retrieve response from vector database client.\n{0}")]
-    VectorDB(anyhow::Error),
+    #[error("Failed to retrieve relevant messages, {0}")]
+    Rag(#[from] rag::Error),
     #[error("Command missing guild_id. It's likely the command was run from within dms.")]
     MissingGuildID,
 }
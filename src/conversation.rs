@@ -0,0 +1,146 @@
+use serenity::all::{Context, Http, Message, UserId};
+
+use crate::{
+    environment::Environment,
+    guild_config::GuildConfig,
+    llm::{
+        self,
+        backend::ChatBackend,
+        engine::LlmEngine,
+        model::{LlmChat, SystemMessage},
+    },
+    memory::to_llm_message,
+    permissions::{message_author, Reader},
+    rag::{self, find_near_messages, generate_relevant_message_prompt},
+    tools::{ToolContext, ToolRegistry},
+    vec_db::db_handler::VdbHandler,
+};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// How many messages of a reply chain, including the triggering message, are sent as context.
+const MAX_REPLY_CHAIN_LENGTH: usize = 10;
+
+/// Whether the bot should answer `message`, either because it was mentioned or because the
+/// message replies to one of the bot's own messages. Messages from bots, including our own, are
+/// never answered.
+pub fn should_reply(message: &Message, bot_id: UserId) -> bool {
+    if message.author.bot {
+        return false;
+    }
+    message.mentions_user_id(bot_id)
+        || message
+            .referenced_message
+            .as_ref()
+            .is_some_and(|referenced| referenced.author.id == bot_id)
+}
+
+/// Generates the bot's answer to `message`, using its reply chain as the conversation. When
+/// `llm.tools` is enabled the model may call the registry's tools before answering.
+pub async fn generate_reply(
+    message: &Message,
+    ctx: &Context,
+    llm_engine: &LlmEngine,
+    vec_db_client: &VdbHandler,
+    tools: &ToolRegistry,
+    environment: &Environment,
+    guild_config: &GuildConfig,
+) -> Result<String> {
+    let bot_id = ctx.cache.current_user().id;
+    let reply_chain = get_reply_chain(message, &ctx.http).await?;
+
+    // The reply is posted where the author asked, so it may only quote what they can read.
+    let author = message_author(message);
+    let relevant_messages = match (message.guild_id, &author) {
+        (Some(guild_id), Some(member)) if guild_config.rag => {
+            find_near_messages(
+                &message.content,
+                Reader { guild_id, member },
+                guild_config.rag_top_k,
+                ctx,
+                llm_engine,
+                vec_db_client,
+            )
+            .await?
+        }
+        _ => vec![],
+    };
+
+    let system_message = SystemMessage {
+        content: guild_config.prompt()
+            + "\n"
+            + generate_relevant_message_prompt(relevant_messages)
+                .unwrap_or("".to_string())
+                .as_str(),
+    }
+    .into();
+
+    let llm_context: LlmChat = std::iter::once(system_message)
+        .chain(
+            reply_chain
+                .iter()
+                .map(|message| to_llm_message(message, bot_id, &ctx.cache)),
+        )
+        .collect();
+
+    let completion = if environment.llm.tools {
+        let tool_context = ToolContext {
+            guild_id: message.guild_id,
+            channel_id: message.channel_id,
+            member: author.as_ref(),
+            http_client: &ctx.http,
+            cache: &ctx.cache,
+            embed_engine: llm_engine,
+            vec_db_client,
+            search_limit: guild_config.rag_top_k,
+        };
+        llm_engine
+            .get_chat_completion_with_tools(llm_context, tools, &tool_context)
+            .await
+    } else {
+        llm_engine.get_chat_completion(llm_context).await
+    };
+
+    completion
+        .and_then(|str_response| {
+            if str_response.chars().count().lt(&1_usize) {
+                Err(llm::error::Error::EmptyResponseError)
+            } else {
+                Ok(str_response)
+            }
+        })
+        .map_err(Error::from)
+}
+
+/// Walks up the replies starting at `message`, returning the chain oldest message first.
+async fn get_reply_chain(message: &Message, http_client: &Http) -> Result<Vec<Message>> {
+    let mut chain = vec![message.clone()];
+    while chain.len() < MAX_REPLY_CHAIN_LENGTH {
+        let current = chain.last().expect("Reply chain is never empty");
+        let parent = match (&current.referenced_message, &current.message_reference) {
+            (Some(referenced), _) => *referenced.clone(),
+            (None, Some(reference)) => match reference.message_id {
+                Some(message_id) => {
+                    http_client
+                        .get_message(reference.channel_id, message_id)
+                        .await?
+                }
+                None => break,
+            },
+            (None, None) => break,
+        };
+        chain.push(parent);
+    }
+    chain.reverse();
+    Ok(chain)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to retrieve reply chain, {0}")]
+    ReplyChainFailed(#[from] serenity::Error),
+    #[error("failed to retrieve response from llm, {0}")]
+    LlmError(#[from] llm::error::Error),
+    #[error("Failed to retrieve relevant messages, {0}")]
+    Rag(#[from] rag::Error),
+}
@@ -0,0 +1,368 @@
+use rusqlite::{params, OptionalExtension};
+use serenity::all::{ChannelId, GuildId, MessageId, UserId};
+
+use super::{Database, Result};
+
+/// A message remembered as part of its channel's conversation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConversationTurn {
+    pub message_id: MessageId,
+    pub author_id: UserId,
+    /// Whether the bot sent the message.
+    pub from_bot: bool,
+    pub content: String,
+}
+
+impl Database {
+    /// The running summary of the channel's earlier conversation, if one was written.
+    pub async fn conversation_summary(&self, channel_id: ChannelId) -> Result<Option<String>> {
+        self.call(move |connection| {
+            connection
+                .query_row(
+                    "SELECT summary FROM conversation_summaries WHERE channel_id = ?",
+                    [channel_id.get()],
+                    |row| row.get(0),
+                )
+                .optional()
+        })
+        .await
+    }
+
+    /// Saves the channel's running summary. `guild_id` is `None` for direct messages.
+    pub async fn save_conversation_summary(
+        &self,
+        guild_id: Option<GuildId>,
+        channel_id: ChannelId,
+        summary: String,
+    ) -> Result<()> {
+        self.call(move |connection| {
+            connection.execute(
+                "INSERT INTO conversation_summaries (channel_id, guild_id, summary, updated_at)
+                 VALUES (?, ?, ?, unixepoch())
+                 ON CONFLICT (channel_id) DO UPDATE
+                 SET guild_id = COALESCE(excluded.guild_id, guild_id),
+                 summary = excluded.summary, updated_at = excluded.updated_at",
+                params![channel_id.get(), guild_id.map(GuildId::get), summary],
+            )
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// The channel's saved turns, oldest first.
+    pub async fn conversation_turns(&self, channel_id: ChannelId) -> Result<Vec<ConversationTurn>> {
+        self.call(move |connection| {
+            connection
+                .prepare(
+                    "SELECT message_id, author_id, from_bot, content FROM conversation_turns
+                     WHERE channel_id = ? ORDER BY message_id",
+                )?
+                .query_map([channel_id.get()], |row| {
+                    Ok(ConversationTurn {
+                        message_id: MessageId::new(row.get(0)?),
+                        author_id: UserId::new(row.get(1)?),
+                        from_bot: row.get(2)?,
+                        content: row.get(3)?,
+                    })
+                })?
+                .collect()
+        })
+        .await
+    }
+
+    /// Saves a turn of the channel's conversation, replacing it if the message was saved before.
+    pub async fn save_conversation_turn(
+        &self,
+        guild_id: Option<GuildId>,
+        channel_id: ChannelId,
+        turn: ConversationTurn,
+    ) -> Result<()> {
+        self.call(move |connection| {
+            connection.execute(
+                "INSERT INTO conversation_turns
+                 (message_id, channel_id, guild_id, author_id, from_bot, content)
+                 VALUES (?, ?, ?, ?, ?, ?)
+                 ON CONFLICT (message_id) DO UPDATE SET content = excluded.content",
+                params![
+                    turn.message_id.get(),
+                    channel_id.get(),
+                    guild_id.map(GuildId::get),
+                    turn.author_id.get(),
+                    turn.from_bot,
+                    turn.content
+                ],
+            )
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Deletes the channel's turns older than `message_id`, once they left its history.
+    pub async fn delete_conversation_turns_before(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+    ) -> Result<()> {
+        self.call(move |connection| {
+            connection.execute(
+                "DELETE FROM conversation_turns WHERE channel_id = ? AND message_id < ?",
+                params![channel_id.get(), message_id.get()],
+            )
+        })
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete_conversation_turns(&self, message_ids: Vec<MessageId>) -> Result<()> {
+        self.call(move |connection| {
+            let transaction = connection.transaction()?;
+            {
+                let mut delete =
+                    transaction.prepare("DELETE FROM conversation_turns WHERE message_id = ?")?;
+                for message_id in message_ids {
+                    delete.execute([message_id.get()])?;
+                }
+            }
+            transaction.commit()
+        })
+        .await
+    }
+
+    pub async fn delete_author_conversation_turns(&self, user_id: UserId) -> Result<()> {
+        self.call(move |connection| {
+            connection.execute(
+                "DELETE FROM conversation_turns WHERE author_id = ?",
+                [user_id.get()],
+            )
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Deletes the channel's summary and turns.
+    pub async fn delete_conversation(&self, channel_id: ChannelId) -> Result<()> {
+        self.call(move |connection| {
+            let transaction = connection.transaction()?;
+            transaction.execute(
+                "DELETE FROM conversation_summaries WHERE channel_id = ?",
+                [channel_id.get()],
+            )?;
+            transaction.execute(
+                "DELETE FROM conversation_turns WHERE channel_id = ?",
+                [channel_id.get()],
+            )?;
+            transaction.commit()
+        })
+        .await
+    }
+
+    /// Deletes the summaries and turns of every channel of the guild, returning how many
+    /// summaries there were.
+    pub async fn delete_guild_conversations(&self, guild_id: GuildId) -> Result<usize> {
+        self.call(move |connection| {
+            let transaction = connection.transaction()?;
+            let deleted = transaction.execute(
+                "DELETE FROM conversation_summaries WHERE guild_id = ?",
+                [guild_id.get()],
+            )?;
+            transaction.execute(
+                "DELETE FROM conversation_turns WHERE guild_id = ?",
+                [guild_id.get()],
+            )?;
+            transaction.commit()?;
+            Ok(deleted)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn saving_replaces_the_summary() {
+        let database = Database::in_memory().unwrap();
+        let channel_id = ChannelId::new(10);
+        assert_eq!(
+            database.conversation_summary(channel_id).await.unwrap(),
+            None
+        );
+
+        database
+            .save_conversation_summary(Some(GuildId::new(1)), channel_id, "first".to_string())
+            .await
+            .unwrap();
+        database
+            .save_conversation_summary(None, channel_id, "second".to_string())
+            .await
+            .unwrap();
+        assert_eq!(
+            database.conversation_summary(channel_id).await.unwrap(),
+            Some("second".to_string())
+        );
+
+        database.delete_conversation(channel_id).await.unwrap();
+        assert_eq!(
+            database.conversation_summary(channel_id).await.unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn deleting_a_guild_keeps_other_summaries() {
+        let database = Database::in_memory().unwrap();
+        for (guild_id, channel_id) in [(Some(1), 10), (Some(1), 11), (Some(2), 20), (None, 30)] {
+            database
+                .save_conversation_summary(
+                    guild_id.map(GuildId::new),
+                    ChannelId::new(channel_id),
+                    "summary".to_string(),
+                )
+                .await
+                .unwrap();
+        }
+        // A later save that does not know the guild keeps the recorded one.
+        database
+            .save_conversation_summary(None, ChannelId::new(11), "updated".to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            database
+                .delete_guild_conversations(GuildId::new(1))
+                .await
+                .unwrap(),
+            2
+        );
+        for (channel_id, kept) in [(10, false), (11, false), (20, true), (30, true)] {
+            assert_eq!(
+                database
+                    .conversation_summary(ChannelId::new(channel_id))
+                    .await
+                    .unwrap()
+                    .is_some(),
+                kept
+            );
+        }
+    }
+
+    fn turn(message_id: u64, author_id: u64, content: &str) -> ConversationTurn {
+        ConversationTurn {
+            message_id: MessageId::new(message_id),
+            author_id: UserId::new(author_id),
+            from_bot: false,
+            content: content.to_string(),
+        }
+    }
+
+    fn turn_ids(turns: Vec<ConversationTurn>) -> Vec<u64> {
+        turns.iter().map(|turn| turn.message_id.get()).collect()
+    }
+
+    #[tokio::test]
+    async fn turns_are_saved_per_channel_in_order() {
+        let database = Database::in_memory().unwrap();
+        let (channel_id, guild_id) = (ChannelId::new(10), Some(GuildId::new(1)));
+        for (message_id, author_id) in [(3, 1), (1, 2), (2, 1)] {
+            database
+                .save_conversation_turn(guild_id, channel_id, turn(message_id, author_id, "hi"))
+                .await
+                .unwrap();
+        }
+        database
+            .save_conversation_turn(guild_id, ChannelId::new(11), turn(4, 1, "elsewhere"))
+            .await
+            .unwrap();
+        // An edit replaces the saved content.
+        database
+            .save_conversation_turn(guild_id, channel_id, turn(2, 1, "edited"))
+            .await
+            .unwrap();
+
+        let turns = database.conversation_turns(channel_id).await.unwrap();
+        assert_eq!(turn_ids(turns.clone()), [1, 2, 3]);
+        assert_eq!(turns[1], turn(2, 1, "edited"));
+
+        database
+            .delete_conversation_turns_before(channel_id, MessageId::new(2))
+            .await
+            .unwrap();
+        assert_eq!(
+            turn_ids(database.conversation_turns(channel_id).await.unwrap()),
+            [2, 3]
+        );
+        database
+            .delete_conversation_turns(vec![MessageId::new(3)])
+            .await
+            .unwrap();
+        assert_eq!(
+            turn_ids(database.conversation_turns(channel_id).await.unwrap()),
+            [2]
+        );
+    }
+
+    #[tokio::test]
+    async fn deleting_removes_turns_by_author_channel_and_guild() {
+        let database = Database::in_memory().unwrap();
+        for (guild_id, channel_id, message_id, author_id) in [
+            (1, 10, 1, 1),
+            (1, 10, 2, 2),
+            (1, 11, 3, 1),
+            (2, 20, 4, 1),
+            (2, 21, 5, 1),
+        ] {
+            database
+                .save_conversation_turn(
+                    Some(GuildId::new(guild_id)),
+                    ChannelId::new(channel_id),
+                    turn(message_id, author_id, "hi"),
+                )
+                .await
+                .unwrap();
+        }
+
+        database
+            .delete_author_conversation_turns(UserId::new(2))
+            .await
+            .unwrap();
+        assert_eq!(
+            turn_ids(
+                database
+                    .conversation_turns(ChannelId::new(10))
+                    .await
+                    .unwrap()
+            ),
+            [1]
+        );
+        database
+            .delete_conversation(ChannelId::new(11))
+            .await
+            .unwrap();
+        assert!(database
+            .conversation_turns(ChannelId::new(11))
+            .await
+            .unwrap()
+            .is_empty());
+        database
+            .delete_guild_conversations(GuildId::new(2))
+            .await
+            .unwrap();
+        for channel_id in [20, 21] {
+            assert!(database
+                .conversation_turns(ChannelId::new(channel_id))
+                .await
+                .unwrap()
+                .is_empty());
+        }
+        assert_eq!(
+            turn_ids(
+                database
+                    .conversation_turns(ChannelId::new(10))
+                    .await
+                    .unwrap()
+            ),
+            [1]
+        );
+    }
+}
@@ -0,0 +1,124 @@
+use rusqlite::{params, OptionalExtension};
+use serenity::all::GuildId;
+
+use super::{Database, Result};
+
+impl Database {
+    pub async fn guild_setting(&self, guild_id: GuildId, key: &str) -> Result<Option<String>> {
+        let key = key.to_string();
+        self.call(move |connection| {
+            connection
+                .query_row(
+                    "SELECT value FROM guild_settings WHERE guild_id = ? AND key = ?",
+                    params![guild_id.get(), key],
+                    |row| row.get(0),
+                )
+                .optional()
+        })
+        .await
+    }
+
+    /// Every setting the guild has changed, ordered by key.
+    pub async fn guild_settings(&self, guild_id: GuildId) -> Result<Vec<(String, String)>> {
+        self.call(move |connection| {
+            connection
+                .prepare("SELECT key, value FROM guild_settings WHERE guild_id = ? ORDER BY key")?
+                .query_map([guild_id.get()], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect()
+        })
+        .await
+    }
+
+    pub async fn set_guild_setting(&self, guild_id: GuildId, key: &str, value: &str) -> Result<()> {
+        let (key, value) = (key.to_string(), value.to_string());
+        self.call(move |connection| {
+            connection.execute(
+                "INSERT INTO guild_settings (guild_id, key, value, updated_at)
+                 VALUES (?, ?, ?, unixepoch())
+                 ON CONFLICT (guild_id, key) DO UPDATE
+                 SET value = excluded.value, updated_at = excluded.updated_at",
+                params![guild_id.get(), key, value],
+            )
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Returns the setting to its default, returning whether the guild had changed it.
+    pub async fn reset_guild_setting(&self, guild_id: GuildId, key: &str) -> Result<bool> {
+        let key = key.to_string();
+        self.call(move |connection| {
+            connection.execute(
+                "DELETE FROM guild_settings WHERE guild_id = ? AND key = ?",
+                params![guild_id.get(), key],
+            )
+        })
+        .await
+        .map(|deleted| deleted > 0)
+    }
+
+    /// Returns every setting of the guild to its default, returning whether it had changed any.
+    pub async fn reset_guild_settings(&self, guild_id: GuildId) -> Result<bool> {
+        self.call(move |connection| {
+            connection.execute(
+                "DELETE FROM guild_settings WHERE guild_id = ?",
+                [guild_id.get()],
+            )
+        })
+        .await
+        .map(|deleted| deleted > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn setting_and_resetting() {
+        let database = Database::in_memory().unwrap();
+        let guild_id = GuildId::new(1);
+        let other_guild_id = GuildId::new(2);
+        assert_eq!(database.guild_setting(guild_id, "rag").await.unwrap(), None);
+
+        database
+            .set_guild_setting(guild_id, "rag", "true")
+            .await
+            .unwrap();
+        database
+            .set_guild_setting(guild_id, "rag", "false")
+            .await
+            .unwrap();
+        database
+            .set_guild_setting(guild_id, "model", "small")
+            .await
+            .unwrap();
+        database
+            .set_guild_setting(other_guild_id, "rag", "true")
+            .await
+            .unwrap();
+        assert_eq!(
+            database.guild_setting(guild_id, "rag").await.unwrap(),
+            Some("false".to_string())
+        );
+        assert_eq!(
+            database.guild_settings(guild_id).await.unwrap(),
+            [
+                ("model".to_string(), "small".to_string()),
+                ("rag".to_string(), "false".to_string())
+            ]
+        );
+
+        assert!(database.reset_guild_setting(guild_id, "rag").await.unwrap());
+        assert!(!database.reset_guild_setting(guild_id, "rag").await.unwrap());
+        assert_eq!(database.guild_setting(guild_id, "rag").await.unwrap(), None);
+
+        assert!(database.reset_guild_settings(guild_id).await.unwrap());
+        assert!(!database.reset_guild_settings(guild_id).await.unwrap());
+        assert!(database.guild_settings(guild_id).await.unwrap().is_empty());
+        assert_eq!(
+            database.guild_settings(other_guild_id).await.unwrap().len(),
+            1
+        );
+    }
+}
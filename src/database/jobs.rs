@@ -0,0 +1,249 @@
+use rusqlite::{
+    params,
+    types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef},
+    OptionalExtension, Row, ToSql,
+};
+
+use super::{Database, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobStatus {
+    Running,
+    Finished,
+    Failed,
+    /// The bot stopped while the job was running.
+    Interrupted,
+}
+
+/// A long running task, such as a backfill, as last recorded.
+#[derive(Debug, Clone)]
+pub struct JobRecord {
+    pub id: i64,
+    pub kind: String,
+    /// What the job works on, e.g. the channel being backfilled.
+    pub target: u64,
+    pub status: JobStatus,
+    /// Last progress report, or the final one once the job finished.
+    pub progress: Option<String>,
+    pub error: Option<String>,
+    /// Unix timestamps in seconds.
+    pub started_at: i64,
+    pub updated_at: i64,
+}
+
+impl Database {
+    /// Records a job of `kind` starting on `target`, returning its id.
+    pub async fn start_job(&self, kind: &str, target: u64) -> Result<i64> {
+        let kind = kind.to_string();
+        self.call(move |connection| {
+            connection.execute(
+                "INSERT INTO jobs (kind, target, status, started_at, updated_at)
+                 VALUES (?, ?, ?, unixepoch(), unixepoch())",
+                params![kind, target, JobStatus::Running],
+            )?;
+            Ok(connection.last_insert_rowid())
+        })
+        .await
+    }
+
+    pub async fn update_job_progress(&self, id: i64, progress: String) -> Result<()> {
+        self.call(move |connection| {
+            connection.execute(
+                "UPDATE jobs SET progress = ?, updated_at = unixepoch() WHERE id = ?",
+                params![progress, id],
+            )
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Records the job's outcome, its final progress report or the error it failed with.
+    pub async fn finish_job(
+        &self,
+        id: i64,
+        outcome: std::result::Result<String, String>,
+    ) -> Result<()> {
+        let (status, progress, error) = match outcome {
+            Ok(progress) => (JobStatus::Finished, Some(progress), None),
+            Err(error) => (JobStatus::Failed, None, Some(error)),
+        };
+        self.call(move |connection| {
+            connection.execute(
+                "UPDATE jobs SET status = ?, progress = COALESCE(?, progress), error = ?,
+                 updated_at = unixepoch() WHERE id = ?",
+                params![status, progress, error, id],
+            )
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Marks jobs left running by a previous run of the bot as interrupted, returning them.
+    pub async fn interrupt_running_jobs(&self) -> Result<Vec<JobRecord>> {
+        self.call(|connection| {
+            connection
+                .prepare(
+                    "UPDATE jobs SET status = ?, updated_at = unixepoch() WHERE status = ?
+                     RETURNING *",
+                )?
+                .query_map(
+                    params![JobStatus::Interrupted, JobStatus::Running],
+                    JobRecord::from_row,
+                )?
+                .collect()
+        })
+        .await
+    }
+
+    /// The most recently started job of `kind` on `target`.
+    pub async fn latest_job(&self, kind: &str, target: u64) -> Result<Option<JobRecord>> {
+        let kind = kind.to_string();
+        self.call(move |connection| {
+            connection
+                .query_row(
+                    "SELECT * FROM jobs WHERE kind = ? AND target = ? ORDER BY id DESC LIMIT 1",
+                    params![kind, target],
+                    JobRecord::from_row,
+                )
+                .optional()
+        })
+        .await
+    }
+}
+
+impl JobRecord {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get("id")?,
+            kind: row.get("kind")?,
+            target: row.get("target")?,
+            status: row.get("status")?,
+            progress: row.get("progress")?,
+            error: row.get("error")?,
+            started_at: row.get("started_at")?,
+            updated_at: row.get("updated_at")?,
+        })
+    }
+}
+
+impl JobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobStatus::Running => "running",
+            JobStatus::Finished => "finished",
+            JobStatus::Failed => "failed",
+            JobStatus::Interrupted => "interrupted",
+        }
+    }
+}
+
+impl ToSql for JobStatus {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.as_str().into())
+    }
+}
+
+impl FromSql for JobStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "running" => Ok(JobStatus::Running),
+            "finished" => Ok(JobStatus::Finished),
+            "failed" => Ok(JobStatus::Failed),
+            "interrupted" => Ok(JobStatus::Interrupted),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn records_a_job_until_it_finishes() {
+        let database = Database::in_memory().unwrap();
+        assert!(database.latest_job("backfill", 10).await.unwrap().is_none());
+
+        let id = database.start_job("backfill", 10).await.unwrap();
+        database
+            .update_job_progress(id, "read 100".to_string())
+            .await
+            .unwrap();
+        let job = database.latest_job("backfill", 10).await.unwrap().unwrap();
+        assert_eq!(job.id, id);
+        assert_eq!(job.status, JobStatus::Running);
+        assert_eq!(job.progress.as_deref(), Some("read 100"));
+
+        database
+            .finish_job(id, Ok("read 200".to_string()))
+            .await
+            .unwrap();
+        let job = database.latest_job("backfill", 10).await.unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Finished);
+        assert_eq!(job.progress.as_deref(), Some("read 200"));
+        assert_eq!(job.error, None);
+    }
+
+    #[tokio::test]
+    async fn a_failed_job_keeps_its_last_progress() {
+        let database = Database::in_memory().unwrap();
+        let id = database.start_job("backfill", 10).await.unwrap();
+        database
+            .update_job_progress(id, "read 100".to_string())
+            .await
+            .unwrap();
+        database
+            .finish_job(id, Err("missing access".to_string()))
+            .await
+            .unwrap();
+
+        let job = database.latest_job("backfill", 10).await.unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Failed);
+        assert_eq!(job.progress.as_deref(), Some("read 100"));
+        assert_eq!(job.error.as_deref(), Some("missing access"));
+    }
+
+    #[tokio::test]
+    async fn latest_job_is_per_kind_and_target() {
+        let database = Database::in_memory().unwrap();
+        let first = database.start_job("backfill", 10).await.unwrap();
+        let second = database.start_job("backfill", 10).await.unwrap();
+        let other_target = database.start_job("backfill", 11).await.unwrap();
+        database.start_job("reembed", 10).await.unwrap();
+
+        assert!(first < second);
+        let latest = |target| {
+            let database = database.clone();
+            async move { database.latest_job("backfill", target).await.unwrap() }
+        };
+        assert_eq!(latest(10).await.unwrap().id, second);
+        assert_eq!(latest(11).await.unwrap().id, other_target);
+        assert!(latest(12).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn interrupts_only_running_jobs() {
+        let database = Database::in_memory().unwrap();
+        let finished = database.start_job("backfill", 10).await.unwrap();
+        database
+            .finish_job(finished, Ok("done".to_string()))
+            .await
+            .unwrap();
+        let running = database.start_job("backfill", 11).await.unwrap();
+
+        let interrupted = database.interrupt_running_jobs().await.unwrap();
+        assert_eq!(interrupted.len(), 1);
+        assert_eq!(interrupted[0].id, running);
+        assert_eq!(interrupted[0].status, JobStatus::Interrupted);
+        assert_eq!(
+            database
+                .latest_job("backfill", 10)
+                .await
+                .unwrap()
+                .unwrap()
+                .status,
+            JobStatus::Finished
+        );
+        assert!(database.interrupt_running_jobs().await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,116 @@
+use rusqlite::Connection;
+
+use super::{Error, Result};
+
+/// Schema changes in the order they were made. The database records how many it has applied in
+/// `user_version`, so only append to this list, never edit an entry that has shipped.
+const MIGRATIONS: &[&str] = &[
+    // 1: initial schema.
+    "
+    CREATE TABLE guild_settings (
+        guild_id INTEGER NOT NULL,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        updated_at INTEGER NOT NULL,
+        PRIMARY KEY (guild_id, key)
+    );
+    CREATE TABLE opt_outs (
+        user_id INTEGER PRIMARY KEY,
+        opted_out_at INTEGER NOT NULL
+    );
+    CREATE TABLE conversation_summaries (
+        channel_id INTEGER PRIMARY KEY,
+        summary TEXT NOT NULL,
+        updated_at INTEGER NOT NULL
+    );
+    CREATE TABLE usage_counters (
+        day TEXT NOT NULL,
+        guild_id INTEGER NOT NULL,
+        user_id INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        count INTEGER NOT NULL,
+        PRIMARY KEY (day, guild_id, user_id, name)
+    );
+    CREATE TABLE jobs (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        kind TEXT NOT NULL,
+        target INTEGER NOT NULL,
+        status TEXT NOT NULL,
+        progress TEXT,
+        error TEXT,
+        started_at INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL
+    );
+    CREATE INDEX jobs_kind_target ON jobs (kind, target);
+    ",
+    // 2: conversation summaries record their guild, so they are purged with it. Summaries saved
+    // before, and those of direct messages, have none.
+    "
+    ALTER TABLE conversation_summaries ADD COLUMN guild_id INTEGER;
+    CREATE INDEX conversation_summaries_guild ON conversation_summaries (guild_id);
+    ",
+    // 3: the turns of conversation histories, so they survive a restart.
+    "
+    CREATE TABLE conversation_turns (
+        message_id INTEGER PRIMARY KEY,
+        channel_id INTEGER NOT NULL,
+        guild_id INTEGER,
+        author_id INTEGER NOT NULL,
+        from_bot INTEGER NOT NULL,
+        content TEXT NOT NULL
+    );
+    CREATE INDEX conversation_turns_channel ON conversation_turns (channel_id, message_id);
+    CREATE INDEX conversation_turns_guild ON conversation_turns (guild_id);
+    CREATE INDEX conversation_turns_author ON conversation_turns (author_id);
+    ",
+];
+
+/// Applies every migration the database has not seen yet, each in its own transaction.
+pub(super) fn migrate(connection: &mut Connection) -> Result<()> {
+    let applied: usize = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if applied > MIGRATIONS.len() {
+        return Err(Error::UnknownSchema {
+            found: applied,
+            supported: MIGRATIONS.len(),
+        });
+    }
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(applied) {
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", version + 1)?;
+        transaction.commit()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user_version(connection: &Connection) -> usize {
+        connection
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn migrating_twice_changes_nothing() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        migrate(&mut connection).unwrap();
+        assert_eq!(user_version(&connection), MIGRATIONS.len());
+        migrate(&mut connection).unwrap();
+        assert_eq!(user_version(&connection), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn refuses_a_newer_schema() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        connection
+            .pragma_update(None, "user_version", MIGRATIONS.len() + 1)
+            .unwrap();
+        assert!(matches!(
+            migrate(&mut connection),
+            Err(Error::UnknownSchema { .. })
+        ));
+    }
+}
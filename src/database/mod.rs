@@ -0,0 +1,85 @@
+pub mod conversations;
+pub mod guild_settings;
+pub mod jobs;
+mod migrations;
+pub mod opt_outs;
+pub mod usage;
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use rusqlite::Connection;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The bot's own state, such as guild settings and opt-outs, kept in a SQLite file. Every table
+/// is reached through the repository methods in this module's submodules.
+#[derive(Clone)]
+pub struct Database {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl Database {
+    /// Opens the database at `path`, creating the file and its directory if needed and bringing
+    /// the schema up to date.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            std::fs::create_dir_all(parent)
+                .map_err(|err| Error::CreateDirectory(parent.to_path_buf(), err))?;
+        }
+        let connection =
+            Connection::open(path).map_err(|err| Error::Open(path.to_path_buf(), err))?;
+        // Lets readers carry on while a write is in progress.
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        Self::from_connection(connection)
+    }
+
+    /// A database that lives only as long as the process, for tests.
+    pub fn in_memory() -> Result<Self> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(mut connection: Connection) -> Result<Self> {
+        connection.pragma_update(None, "foreign_keys", true)?;
+        migrations::migrate(&mut connection)?;
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    /// Runs `query` on a blocking thread, so the async runtime is not held up by disk access.
+    async fn call<T, F>(&self, query: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut connection = connection.lock().map_err(|_| Error::Poisoned)?;
+            Ok(query(&mut connection)?)
+        })
+        .await?
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Failed to create directory {}, {1}", .0.display())]
+    CreateDirectory(PathBuf, std::io::Error),
+    #[error("Failed to open database at {}, {1}", .0.display())]
+    Open(PathBuf, rusqlite::Error),
+    #[error("Database query failed, {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("Database schema version {found} is newer than this build supports ({supported})")]
+    UnknownSchema { found: usize, supported: usize },
+    #[error("Database connection poisoned")]
+    Poisoned,
+    #[error("Database task failed, {0}")]
+    TaskFailed(#[from] tokio::task::JoinError),
+}
@@ -0,0 +1,52 @@
+use serenity::all::UserId;
+
+use super::{Database, Result};
+
+impl Database {
+    pub async fn opted_out_users(&self) -> Result<Vec<UserId>> {
+        self.call(|connection| {
+            connection
+                .prepare("SELECT user_id FROM opt_outs")?
+                .query_map([], |row| row.get::<_, u64>(0))?
+                .map(|user_id| user_id.map(UserId::new))
+                .collect()
+        })
+        .await
+    }
+
+    /// Records the user's choice, returning whether it changed anything.
+    pub async fn set_opted_out(&self, user_id: UserId, opted_out: bool) -> Result<bool> {
+        self.call(move |connection| {
+            if opted_out {
+                connection.execute(
+                    "INSERT OR IGNORE INTO opt_outs (user_id, opted_out_at) VALUES (?, unixepoch())",
+                    [user_id.get()],
+                )
+            } else {
+                connection.execute("DELETE FROM opt_outs WHERE user_id = ?", [user_id.get()])
+            }
+        })
+        .await
+        .map(|changed| changed > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn opting_out_and_back_in() {
+        let database = Database::in_memory().unwrap();
+        let user_id = UserId::new(1);
+        assert!(database.opted_out_users().await.unwrap().is_empty());
+
+        assert!(database.set_opted_out(user_id, true).await.unwrap());
+        assert!(!database.set_opted_out(user_id, true).await.unwrap());
+        assert_eq!(database.opted_out_users().await.unwrap(), [user_id]);
+
+        assert!(database.set_opted_out(user_id, false).await.unwrap());
+        assert!(!database.set_opted_out(user_id, false).await.unwrap());
+        assert!(database.opted_out_users().await.unwrap().is_empty());
+    }
+}
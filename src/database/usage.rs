@@ -0,0 +1,102 @@
+use rusqlite::params;
+use serenity::all::{GuildId, UserId};
+
+use super::{Database, Result};
+
+/// How often something was used over a span of days.
+#[derive(Debug, Clone)]
+pub struct UsageCount {
+    pub name: String,
+    pub count: u64,
+}
+
+impl Database {
+    /// Counts one use of `name`, such as a command, by the user on the current UTC day. Uses in
+    /// direct messages are counted under guild 0.
+    pub async fn record_usage(
+        &self,
+        guild_id: Option<GuildId>,
+        user_id: UserId,
+        name: &str,
+    ) -> Result<()> {
+        let name = name.to_string();
+        self.call(move |connection| {
+            connection.execute(
+                "INSERT INTO usage_counters (day, guild_id, user_id, name, count)
+                 VALUES (date('now'), ?, ?, ?, 1)
+                 ON CONFLICT (day, guild_id, user_id, name) DO UPDATE SET count = count + 1",
+                params![guild_id.map_or(0, GuildId::get), user_id.get(), name],
+            )
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Uses in the guild over the last `days` days including today, most used first.
+    pub async fn usage_counts(
+        &self,
+        guild_id: Option<GuildId>,
+        days: u32,
+    ) -> Result<Vec<UsageCount>> {
+        self.call(move |connection| {
+            connection
+                .prepare(
+                    "SELECT name, SUM(count) AS total FROM usage_counters
+                     WHERE guild_id = ? AND day > date('now', ?)
+                     GROUP BY name ORDER BY total DESC, name",
+                )?
+                .query_map(
+                    params![guild_id.map_or(0, GuildId::get), format!("-{days} days")],
+                    |row| {
+                        Ok(UsageCount {
+                            name: row.get(0)?,
+                            count: row.get(1)?,
+                        })
+                    },
+                )?
+                .collect()
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts(counts: Vec<UsageCount>) -> Vec<(String, u64)> {
+        counts
+            .into_iter()
+            .map(|usage| (usage.name, usage.count))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn counts_uses_per_guild() {
+        let database = Database::in_memory().unwrap();
+        let guild_id = Some(GuildId::new(1));
+        for (guild_id, user_id, name) in [
+            (guild_id, 1, "ask"),
+            (guild_id, 2, "ask"),
+            (guild_id, 1, "reply"),
+            (guild_id, 1, "ask"),
+            (Some(GuildId::new(2)), 1, "recall"),
+            (None, 1, "privacy"),
+        ] {
+            database
+                .record_usage(guild_id, UserId::new(user_id), name)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            counts(database.usage_counts(guild_id, 7).await.unwrap()),
+            [("ask".to_string(), 3), ("reply".to_string(), 1)]
+        );
+        assert_eq!(
+            counts(database.usage_counts(None, 1).await.unwrap()),
+            [("privacy".to_string(), 1)]
+        );
+        assert!(database.usage_counts(guild_id, 0).await.unwrap().is_empty());
+    }
+}
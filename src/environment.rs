@@ -1,40 +1,259 @@
+use std::{collections::HashMap, fmt};
+
 use dotenv::dotenv;
+use reqwest::Url;
 use serde::Deserialize;
 
-use super::error::Result;
+use crate::{
+    guild_config::{MAX_MEMORY_SIZE, MAX_RAG_TOP_K},
+    ingest::filter::IngestFilter,
+    llm::backend::LlmBackendKind,
+    vec_db::store::{VectorDistance, VectorStoreKind},
+};
+
+use super::error::{Error, Result};
+
+/// Directory holding the configuration files, watched for changes while the bot runs.
+pub const CONFIG_DIR: &str = "config";
+/// Profile layered over the defaults when `RUN_MODE` is not set.
+const DEFAULT_RUN_MODE: &str = "development";
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Environment {
-    pub discord_token: String,
+    pub discord_token: Secret,
     pub bot_name: String,
     pub llm: LlmOptions,
     pub memory: MemoryOptions,
+    pub rag: RagOptions,
     pub vdb: VectorDBOptions,
+    pub ingest: IngestOptions,
+    pub database: DatabaseOptions,
+    #[serde(default)]
+    pub commands: CommandOptions,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct LlmOptions {
+    #[serde(default)]
+    pub backend: LlmBackendKind,
     pub model: Option<String>,
     pub base_url: Option<String>,
+    /// Bearer token sent to OpenAI compatible servers.
+    pub api_key: Option<Secret>,
     pub system_prompt: String,
     pub embed_model: String,
+    /// Number of tokens the model can attend to, used to split long inputs.
+    pub context_window: usize,
+    /// Stream responses into Discord as they are generated.
+    #[serde(default)]
+    pub stream: bool,
+    /// Let the model call tools when replying to mentions. Requires a model with tool support.
+    #[serde(default)]
+    pub tools: bool,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, PartialEq)]
 pub struct VectorDBOptions {
+    #[serde(default)]
+    pub backend: VectorStoreKind,
+    /// Url of the qdrant server.
     pub base_url: String,
+    /// File the local backend keeps its vectors in.
+    #[serde(default = "default_vector_store_path")]
+    pub path: String,
+    /// Length of the embedding vectors, probed from the embedding model when unset.
+    pub dimension: Option<u64>,
+    #[serde(default)]
+    pub distance: VectorDistance,
+    /// Collection to store vectors in, named after the embedding model and dimension when unset.
+    pub collection: Option<String>,
+    /// Alias the bot reads the collection through, switched over by the reembed binary.
+    pub alias: Option<String>,
+}
+
+fn default_vector_store_path() -> String {
+    "data/vectors.sqlite".to_string()
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct IngestOptions {
+    /// Messages waiting to be stored before new messages have to wait for room.
+    pub queue_capacity: usize,
+    /// How long a new message waits for room in a full queue before it is dropped.
+    pub enqueue_timeout_ms: u64,
+    /// Most messages embedded and stored together.
+    pub batch_size: usize,
+    /// How long to wait for more messages to fill a batch.
+    pub batch_window_ms: u64,
+    /// Retries of a failed batch before its messages are given up on.
+    pub max_retries: u32,
+    pub filter: FilterOptions,
+}
+
+/// Which messages are stored in the vector database.
+#[derive(Debug, Deserialize, Clone)]
+pub struct FilterOptions {
+    /// Messages shorter than this many characters are not stored.
+    pub min_length: usize,
+    #[serde(default)]
+    pub ignore_bots: bool,
+    /// Messages starting with one of these, such as other bots' command prefixes, are skipped.
+    #[serde(default)]
+    pub ignored_prefixes: Vec<String>,
+    #[serde(default)]
+    pub ignored_channels: Vec<u64>,
+    /// Messages from members with one of these roles are not stored.
+    #[serde(default)]
+    pub ignored_roles: Vec<u64>,
+    /// Regular expressions for content that must never be stored, such as secrets.
+    #[serde(default)]
+    pub deny_patterns: Vec<String>,
+    /// Guilds messages are stored from, every guild when empty.
+    #[serde(default)]
+    pub allowed_guilds: Vec<u64>,
+    /// Per guild id, the only channels messages are stored from. Guilds not listed store
+    /// messages from every channel.
+    #[serde(default)]
+    pub allowed_channels: HashMap<String, Vec<u64>>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct MemoryOptions {
+    /// Most recent messages sent as context, fetched when a channel's history is first needed.
+    /// Guilds can override it with `/config`.
     pub max_message_count: usize,
+    /// Approximate number of tokens of channel history sent to the model.
+    pub token_budget: usize,
+    /// Summarise turns trimmed from the history into a running summary.
+    #[serde(default)]
+    pub summarize_evicted: bool,
+}
+
+/// Retrieval of stored messages related to the conversation when replying.
+#[derive(Debug, Deserialize, Clone)]
+pub struct RagOptions {
+    pub enabled: bool,
+    /// Number of related messages added to the prompt.
+    pub top_k: u64,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct DatabaseOptions {
+    /// SQLite file holding the bot's state, such as guild settings and opt-outs.
+    pub path: String,
+}
+
+/// How slash commands are registered with Discord.
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+pub struct CommandOptions {
+    /// Application the commands belong to, fetched from Discord with the token when unset.
+    pub application_id: Option<u64>,
+    /// Guilds to register the commands in instead of globally. Guild commands update
+    /// instantly, global ones can take up to an hour.
+    #[serde(default)]
+    pub dev_guilds: Vec<u64>,
+}
+
+/// A configuration value kept out of logs, shown as `<redacted>` when debug printed.
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+impl Environment {
+    /// Checks the values deserializing cannot, reporting every problem at once.
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+        if self.discord_token.expose().trim().is_empty() {
+            problems.push("discord_token is empty, set DISCORD_TOKEN".to_string());
+        }
+        if self.bot_name.trim().is_empty() {
+            problems.push("bot_name is empty, set BOT_NAME".to_string());
+        }
+        if let Some(base_url) = &self.llm.base_url {
+            problems.extend(check_url("llm.base_url", base_url));
+        }
+        if self.vdb.backend == VectorStoreKind::Qdrant {
+            problems.extend(check_url("vdb.base_url", &self.vdb.base_url));
+        }
+        if self.llm.context_window == 0 {
+            problems.push("llm.context_window must be above 0".to_string());
+        }
+        if !(1..=MAX_MEMORY_SIZE).contains(&self.memory.max_message_count) {
+            problems.push(format!(
+                "memory.max_message_count must be between 1 and {MAX_MEMORY_SIZE}, Discord returns at most {MAX_MEMORY_SIZE} messages at once, got {}",
+                self.memory.max_message_count
+            ));
+        }
+        if self.memory.token_budget == 0 {
+            problems.push("memory.token_budget must be above 0".to_string());
+        }
+        if !(1..=MAX_RAG_TOP_K).contains(&self.rag.top_k) {
+            problems.push(format!(
+                "rag.top_k must be between 1 and {MAX_RAG_TOP_K}, got {}",
+                self.rag.top_k
+            ));
+        }
+        if self.ingest.queue_capacity == 0 {
+            problems.push("ingest.queue_capacity must be above 0".to_string());
+        }
+        if self.ingest.batch_size == 0 {
+            problems.push("ingest.batch_size must be above 0".to_string());
+        }
+        if let Err(err) = IngestFilter::new(&self.ingest.filter) {
+            problems.push(format!("ingest.filter is invalid, {err}"));
+        }
+        if self.commands.application_id == Some(0) {
+            problems.push("commands.application_id must not be 0".to_string());
+        }
+        if self.commands.dev_guilds.contains(&0) {
+            problems.push("commands.dev_guilds must not contain 0".to_string());
+        }
+        if self.database.path.trim().is_empty() {
+            problems.push("database.path is empty".to_string());
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::InvalidConfig(problems))
+        }
+    }
+}
+
+fn check_url(name: &str, value: &str) -> Option<String> {
+    match Url::parse(value) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => None,
+        Ok(url) => Some(format!(
+            "{name} must be an http or https url, got scheme {}",
+            url.scheme()
+        )),
+        Err(err) => Some(format!("{name} is not a valid url ({value}), {err}")),
+    }
 }
 
+/// Loads `config/default`, then the `RUN_MODE` profile and `config/local` when they exist,
+/// then `__` separated environment variables, each overriding the ones before it.
 pub fn get_environment() -> Result<Environment> {
     dotenv().ok();
-    Ok(config::Config::builder()
-        .add_source(config::File::with_name("config/default"))
+    let run_mode = std::env::var("RUN_MODE").unwrap_or_else(|_| DEFAULT_RUN_MODE.to_string());
+    let environment: Environment = config::Config::builder()
+        .add_source(config::File::with_name(&format!("{CONFIG_DIR}/default")))
+        .add_source(config::File::with_name(&format!("{CONFIG_DIR}/{run_mode}")).required(false))
+        .add_source(config::File::with_name(&format!("{CONFIG_DIR}/local")).required(false))
         .add_source(config::Environment::default().separator("__"))
         .build()?
-        .try_deserialize()?)
+        .try_deserialize()?;
+    environment.validate()?;
+    Ok(environment)
 }
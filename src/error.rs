@@ -1,4 +1,4 @@
-use crate::llm;
+use crate::{database, ingest::filter, llm, privacy};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -9,6 +9,16 @@ pub enum Error {
         #[from]
         source: config::ConfigError,
     },
+    #[error("Invalid configuration:\n  {}", .0.join("\n  "))]
+    InvalidConfig(Vec<String>),
     #[error("Llm engine failed, {0}")]
     Llm(#[from] llm::error::Error),
+    #[error("Invalid ingestion filter, {0}")]
+    Filter(#[from] filter::Error),
+    #[error("Failed to load privacy settings, {0}")]
+    Privacy(#[from] privacy::Error),
+    #[error("Failed to access database, {0}")]
+    Database(#[from] database::Error),
+    #[error("Failed to open vector database, {0}")]
+    VectorDB(anyhow::Error),
 }
@@ -0,0 +1,526 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use arc_swap::ArcSwap;
+use serenity::all::{ChannelId, GuildId};
+
+use crate::{
+    backfill::HISTORY_PAGE_SIZE,
+    database::{self, Database},
+    environment::Environment,
+};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Conversation memory is seeded with a single history request.
+pub const MAX_MEMORY_SIZE: usize = HISTORY_PAGE_SIZE as usize;
+pub const MAX_RAG_TOP_K: u64 = 50;
+const MAX_PERSONA_LENGTH: usize = 100;
+
+/// Commands that stay available whatever a guild disables, so admins can undo it and users can
+/// always manage their data.
+const ALWAYS_ENABLED_COMMANDS: [&str; 2] = ["config", "privacy"];
+
+/// A setting guilds can override with `/config`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GuildSetting {
+    SystemPrompt,
+    Persona,
+    Model,
+    MemorySize,
+    Rag,
+    RagTopK,
+    DisabledCommands,
+    IngestChannels,
+}
+
+/// How the bot behaves in one guild: the guild's overrides on top of `config/default.toml`.
+#[derive(Debug, Clone)]
+pub struct GuildConfig {
+    pub system_prompt: String,
+    /// Name and character the bot plays, none by default.
+    pub persona: Option<String>,
+    /// Chat model, the configured `llm.model` unless overridden.
+    pub model: Option<String>,
+    /// Most recent messages sent to the model as conversation history.
+    pub memory_size: usize,
+    pub rag: bool,
+    /// Number of related stored messages added to the prompt.
+    pub rag_top_k: u64,
+    pub disabled_commands: HashSet<String>,
+    /// The only channels new messages are stored from, every channel when unset.
+    pub ingest_channels: Option<HashSet<ChannelId>>,
+}
+
+/// Every guild's configuration, read from the database and cached until changed or until the
+/// defaults are reloaded.
+pub struct GuildConfigs {
+    database: Database,
+    defaults: ArcSwap<GuildConfig>,
+    cache: Mutex<HashMap<GuildId, Arc<GuildConfig>>>,
+}
+
+impl GuildSetting {
+    pub const ALL: [GuildSetting; 8] = [
+        GuildSetting::SystemPrompt,
+        GuildSetting::Persona,
+        GuildSetting::Model,
+        GuildSetting::MemorySize,
+        GuildSetting::Rag,
+        GuildSetting::RagTopK,
+        GuildSetting::DisabledCommands,
+        GuildSetting::IngestChannels,
+    ];
+
+    /// Name of the setting in `/config` and the database.
+    pub fn key(&self) -> &'static str {
+        match self {
+            GuildSetting::SystemPrompt => "system-prompt",
+            GuildSetting::Persona => "persona",
+            GuildSetting::Model => "model",
+            GuildSetting::MemorySize => "memory-size",
+            GuildSetting::Rag => "rag",
+            GuildSetting::RagTopK => "rag-top-k",
+            GuildSetting::DisabledCommands => "disabled-commands",
+            GuildSetting::IngestChannels => "ingest-channels",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            GuildSetting::SystemPrompt => "Instructions the model follows when replying",
+            GuildSetting::Persona => "Name and character the bot plays",
+            GuildSetting::Model => "Chat model used for replies",
+            GuildSetting::MemorySize => "Recent messages sent as conversation history, 1 to 100",
+            GuildSetting::Rag => "Add related stored messages to prompts, on or off",
+            GuildSetting::RagTopK => "Number of related stored messages added, 1 to 50",
+            GuildSetting::DisabledCommands => {
+                "Commands unavailable in this server, e.g. ask, recall"
+            }
+            GuildSetting::IngestChannels => "The only channels new messages are stored from",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        GuildSetting::ALL
+            .into_iter()
+            .find(|setting| setting.key() == key)
+    }
+}
+
+impl GuildConfig {
+    pub fn defaults(environment: &Environment) -> Self {
+        Self {
+            system_prompt: environment.llm.system_prompt.clone(),
+            persona: None,
+            model: environment.llm.model.clone(),
+            memory_size: environment.memory.max_message_count,
+            rag: environment.rag.enabled,
+            rag_top_k: environment.rag.top_k,
+            disabled_commands: HashSet::new(),
+            ingest_channels: None,
+        }
+    }
+
+    /// Parses `value` into the setting, leaving the config unchanged if it is invalid.
+    pub fn apply(&mut self, setting: GuildSetting, value: &str) -> Result<()> {
+        let value = value.trim();
+        let invalid = |reason: &str| Error::InvalidValue(setting.key(), reason.to_string());
+        match setting {
+            GuildSetting::SystemPrompt => {
+                if value.is_empty() {
+                    return Err(invalid("the prompt cannot be empty"));
+                }
+                self.system_prompt = value.to_string();
+            }
+            GuildSetting::Persona => {
+                if value.is_empty() || value.chars().count() > MAX_PERSONA_LENGTH {
+                    return Err(invalid("use 1 to 100 characters"));
+                }
+                self.persona = Some(value.to_string());
+            }
+            GuildSetting::Model => {
+                if value.is_empty() || value.contains(char::is_whitespace) {
+                    return Err(invalid("expected a model name"));
+                }
+                self.model = Some(value.to_string());
+            }
+            GuildSetting::MemorySize => {
+                self.memory_size = value
+                    .parse()
+                    .ok()
+                    .filter(|size| (1..=MAX_MEMORY_SIZE).contains(size))
+                    .ok_or_else(|| invalid("expected a number from 1 to 100"))?;
+            }
+            GuildSetting::Rag => {
+                self.rag = match value.to_lowercase().as_str() {
+                    "on" | "true" | "yes" | "enabled" => true,
+                    "off" | "false" | "no" | "disabled" => false,
+                    _ => return Err(invalid("expected on or off")),
+                };
+            }
+            GuildSetting::RagTopK => {
+                self.rag_top_k = value
+                    .parse()
+                    .ok()
+                    .filter(|top_k| (1..=MAX_RAG_TOP_K).contains(top_k))
+                    .ok_or_else(|| invalid("expected a number from 1 to 50"))?;
+            }
+            GuildSetting::DisabledCommands => {
+                let commands: HashSet<String> = split_list(value)
+                    .map(|command| command.trim_start_matches('/').to_lowercase())
+                    .collect();
+                if let Some(command) = commands
+                    .iter()
+                    .find(|command| ALWAYS_ENABLED_COMMANDS.contains(&command.as_str()))
+                {
+                    return Err(invalid(&format!("/{command} cannot be disabled")));
+                }
+                self.disabled_commands = commands;
+            }
+            GuildSetting::IngestChannels => {
+                let channels = split_list(value)
+                    .map(|channel| {
+                        channel
+                            .trim_start_matches("<#")
+                            .trim_end_matches('>')
+                            .parse::<u64>()
+                            .ok()
+                            .filter(|channel_id| *channel_id != 0)
+                            .map(ChannelId::new)
+                    })
+                    .collect::<Option<HashSet<_>>>()
+                    .filter(|channels| !channels.is_empty())
+                    .ok_or_else(|| invalid("expected channel mentions or ids"))?;
+                self.ingest_channels = Some(channels);
+            }
+        }
+        Ok(())
+    }
+
+    /// The setting's value, in the form [`GuildConfig::apply`] accepts.
+    pub fn value(&self, setting: GuildSetting) -> String {
+        match setting {
+            GuildSetting::SystemPrompt => self.system_prompt.clone(),
+            GuildSetting::Persona => self.persona.clone().unwrap_or_default(),
+            GuildSetting::Model => self.model.clone().unwrap_or_default(),
+            GuildSetting::MemorySize => self.memory_size.to_string(),
+            GuildSetting::Rag => if self.rag { "on" } else { "off" }.to_string(),
+            GuildSetting::RagTopK => self.rag_top_k.to_string(),
+            GuildSetting::DisabledCommands => {
+                let mut commands: Vec<_> = self.disabled_commands.iter().cloned().collect();
+                commands.sort();
+                commands.join(", ")
+            }
+            GuildSetting::IngestChannels => {
+                let mut channels: Vec<_> = self.ingest_channels.iter().flatten().collect();
+                channels.sort();
+                channels
+                    .into_iter()
+                    .map(|channel_id| format!("<#{channel_id}>"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            }
+        }
+    }
+
+    /// The system prompt, introducing the persona if the guild gave the bot one.
+    pub fn prompt(&self) -> String {
+        match &self.persona {
+            Some(persona) => format!("You are {persona}.\n{}", self.system_prompt),
+            None => self.system_prompt.clone(),
+        }
+    }
+
+    pub fn command_enabled(&self, name: &str) -> bool {
+        ALWAYS_ENABLED_COMMANDS.contains(&name) || !self.disabled_commands.contains(name)
+    }
+
+    /// Whether new messages sent in the channel are stored.
+    pub fn stores_channel(&self, channel_id: ChannelId) -> bool {
+        self.ingest_channels
+            .as_ref()
+            .is_none_or(|channels| channels.contains(&channel_id))
+    }
+}
+
+impl GuildConfigs {
+    pub fn new(environment: &Environment, database: Database) -> Self {
+        Self {
+            database,
+            defaults: ArcSwap::from_pointee(GuildConfig::defaults(environment)),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn defaults(&self) -> Arc<GuildConfig> {
+        self.defaults.load_full()
+    }
+
+    /// Replaces the defaults with those of a reloaded configuration. Every guild's
+    /// configuration is read again, as it builds on the defaults.
+    pub fn set_defaults(&self, environment: &Environment) {
+        self.defaults
+            .store(Arc::new(GuildConfig::defaults(environment)));
+        self.cache_lock().clear();
+    }
+
+    /// The guild's configuration, the defaults outside guilds or if it cannot be read.
+    pub async fn get(&self, guild_id: Option<GuildId>) -> Arc<GuildConfig> {
+        let Some(guild_id) = guild_id else {
+            return self.defaults();
+        };
+        if let Some(config) = self.cached(guild_id) {
+            return config;
+        }
+        match self.load(guild_id).await {
+            Ok(config) => {
+                let config = Arc::new(config);
+                self.cache_lock().insert(guild_id, config.clone());
+                config
+            }
+            Err(err) => {
+                println!("Failed to load settings of guild {guild_id}, using defaults, {err}");
+                self.defaults()
+            }
+        }
+    }
+
+    /// Settings the guild has changed from the defaults.
+    pub async fn overridden(&self, guild_id: GuildId) -> Result<HashSet<GuildSetting>> {
+        Ok(self
+            .database
+            .guild_settings(guild_id)
+            .await?
+            .into_iter()
+            .filter_map(|(key, _)| GuildSetting::from_key(&key))
+            .collect())
+    }
+
+    /// Validates and stores the guild's new value for the setting.
+    pub async fn set(
+        &self,
+        guild_id: GuildId,
+        setting: GuildSetting,
+        value: &str,
+    ) -> Result<Arc<GuildConfig>> {
+        let mut config = self.load(guild_id).await?;
+        config.apply(setting, value)?;
+        self.database
+            .set_guild_setting(guild_id, setting.key(), &config.value(setting))
+            .await?;
+        let config = Arc::new(config);
+        self.cache_lock().insert(guild_id, config.clone());
+        Ok(config)
+    }
+
+    /// Returns the setting, or every setting when `None`, to its default. Returns whether the
+    /// guild had changed anything.
+    pub async fn reset(&self, guild_id: GuildId, setting: Option<GuildSetting>) -> Result<bool> {
+        let reset = match setting {
+            Some(setting) => {
+                self.database
+                    .reset_guild_setting(guild_id, setting.key())
+                    .await?
+            }
+            None => self.database.reset_guild_settings(guild_id).await?,
+        };
+        self.cache_lock().remove(&guild_id);
+        Ok(reset)
+    }
+
+    fn cached(&self, guild_id: GuildId) -> Option<Arc<GuildConfig>> {
+        self.cache_lock().get(&guild_id).cloned()
+    }
+
+    fn cache_lock(&self) -> std::sync::MutexGuard<'_, HashMap<GuildId, Arc<GuildConfig>>> {
+        self.cache.lock().expect("Guild config cache poisoned")
+    }
+
+    async fn load(&self, guild_id: GuildId) -> Result<GuildConfig> {
+        let mut config = (*self.defaults()).clone();
+        for (key, value) in self.database.guild_settings(guild_id).await? {
+            // Values were validated when set, so this only skips settings a newer or older
+            // version of the bot stored.
+            let applied = GuildSetting::from_key(&key)
+                .ok_or(Error::UnknownSetting(key.clone()))
+                .and_then(|setting| config.apply(setting, &value));
+            if let Err(err) = applied {
+                println!("Ignoring setting {key} of guild {guild_id}, {err}");
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Items of a list separated by commas or whitespace.
+fn split_list(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|item| !item.is_empty())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Unknown setting {0}")]
+    UnknownSetting(String),
+    #[error("Invalid value for {0}, {1}")]
+    InvalidValue(&'static str, String),
+    #[error("Failed to access guild settings, {0}")]
+    Database(#[from] database::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> GuildConfig {
+        GuildConfig {
+            system_prompt: "Be helpful.".to_string(),
+            persona: None,
+            model: None,
+            memory_size: 20,
+            rag: false,
+            rag_top_k: 5,
+            disabled_commands: HashSet::new(),
+            ingest_channels: None,
+        }
+    }
+
+    fn rejects(setting: GuildSetting, value: &str) {
+        let mut config = config();
+        assert!(
+            matches!(config.apply(setting, value), Err(Error::InvalidValue(key, _)) if key == setting.key()),
+            "{} accepted {value:?}",
+            setting.key()
+        );
+        assert_eq!(config.value(setting), self::config().value(setting));
+    }
+
+    #[test]
+    fn keys_round_trip() {
+        for setting in GuildSetting::ALL {
+            assert_eq!(GuildSetting::from_key(setting.key()), Some(setting));
+        }
+        assert_eq!(GuildSetting::from_key("unknown"), None);
+    }
+
+    #[test]
+    fn applies_text_settings() {
+        let mut config = config();
+        config
+            .apply(GuildSetting::SystemPrompt, "  Answer in French.  ")
+            .unwrap();
+        config
+            .apply(GuildSetting::Persona, "Chatty, a parrot")
+            .unwrap();
+        config.apply(GuildSetting::Model, "gpt-4o-mini").unwrap();
+        assert_eq!(config.system_prompt, "Answer in French.");
+        assert_eq!(config.model.as_deref(), Some("gpt-4o-mini"));
+        assert_eq!(
+            config.prompt(),
+            "You are Chatty, a parrot.\nAnswer in French."
+        );
+
+        rejects(GuildSetting::SystemPrompt, "   ");
+        rejects(GuildSetting::Persona, "");
+        rejects(GuildSetting::Persona, &"a".repeat(MAX_PERSONA_LENGTH + 1));
+        config
+            .apply(GuildSetting::Persona, &"é".repeat(MAX_PERSONA_LENGTH))
+            .unwrap();
+        rejects(GuildSetting::Model, "two words");
+        rejects(GuildSetting::Model, "");
+    }
+
+    #[test]
+    fn applies_numbers_within_limits() {
+        let mut config = config();
+        config.apply(GuildSetting::MemorySize, "1").unwrap();
+        assert_eq!(config.memory_size, 1);
+        config
+            .apply(GuildSetting::MemorySize, &MAX_MEMORY_SIZE.to_string())
+            .unwrap();
+        assert_eq!(config.memory_size, MAX_MEMORY_SIZE);
+        config.apply(GuildSetting::RagTopK, " 50 ").unwrap();
+        assert_eq!(config.rag_top_k, MAX_RAG_TOP_K);
+
+        for value in ["0", "101", "-1", "ten", "1.5", ""] {
+            rejects(GuildSetting::MemorySize, value);
+        }
+        for value in ["0", "51", "-1", "many"] {
+            rejects(GuildSetting::RagTopK, value);
+        }
+    }
+
+    #[test]
+    fn applies_switches() {
+        let mut config = config();
+        for (value, expected) in [
+            ("on", true),
+            ("OFF", false),
+            ("Yes", true),
+            ("disabled", false),
+        ] {
+            config.apply(GuildSetting::Rag, value).unwrap();
+            assert_eq!(config.rag, expected);
+        }
+        rejects(GuildSetting::Rag, "maybe");
+    }
+
+    #[test]
+    fn disables_commands_except_the_essential_ones() {
+        let mut config = config();
+        config
+            .apply(GuildSetting::DisabledCommands, "/Ask, recall  summarize")
+            .unwrap();
+        assert_eq!(
+            config.value(GuildSetting::DisabledCommands),
+            "ask, recall, summarize"
+        );
+        assert!(!config.command_enabled("ask"));
+        assert!(config.command_enabled("weigh-in"));
+
+        rejects(GuildSetting::DisabledCommands, "ask, /privacy");
+        rejects(GuildSetting::DisabledCommands, "config");
+
+        config.apply(GuildSetting::DisabledCommands, "").unwrap();
+        assert!(config.disabled_commands.is_empty());
+    }
+
+    #[test]
+    fn limits_ingestion_to_listed_channels() {
+        let mut config = config();
+        assert!(config.stores_channel(ChannelId::new(12)));
+
+        config
+            .apply(GuildSetting::IngestChannels, "<#11>, 10")
+            .unwrap();
+        assert_eq!(config.value(GuildSetting::IngestChannels), "<#10>, <#11>");
+        assert!(config.stores_channel(ChannelId::new(10)));
+        assert!(!config.stores_channel(ChannelId::new(12)));
+
+        for value in ["", "<#0>", "general", "10, <@11>"] {
+            rejects(GuildSetting::IngestChannels, value);
+        }
+    }
+
+    #[test]
+    fn values_apply_back_unchanged() {
+        let mut config = config();
+        config.apply(GuildSetting::Persona, "Chatty").unwrap();
+        config.apply(GuildSetting::Model, "small").unwrap();
+        config.apply(GuildSetting::Rag, "on").unwrap();
+        config
+            .apply(GuildSetting::DisabledCommands, "recall ask")
+            .unwrap();
+        config.apply(GuildSetting::IngestChannels, "10 11").unwrap();
+
+        for setting in GuildSetting::ALL {
+            let mut copy = self::config();
+            copy.apply(setting, &config.value(setting)).unwrap();
+            assert_eq!(copy.value(setting), config.value(setting));
+        }
+    }
+}
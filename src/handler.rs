@@ -1,27 +1,76 @@
-use crate::vec_db::db_handler::VdbHandler;
-use crate::{commands::run_ask, llm::engine::LlmEngine};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::backfill::BackfillJobs;
+use crate::ingest::IngestQueue;
+
+use crate::conversation::{generate_reply, should_reply};
+use crate::database::Database;
+use crate::guild_config::{GuildConfig, GuildConfigs};
+use crate::llm::{
+    self,
+    backend::{ChatBackend, ChatStream, EmbedBackend},
+};
+use crate::memory::ConversationMemory;
+use crate::privacy::OptOutStore;
+use crate::reload::{restart_required, Services, SharedServices};
+use crate::tools::ToolRegistry;
 use crate::{
     commands::{
         error::{Error, Result},
-        weigh_in::run_weigh_in,
+        CommandContext, CommandRegistry, Reply, ReplyMessage,
     },
     environment::Environment,
+    llm::engine::LlmEngine,
+};
+use arc_swap::ArcSwap;
+use futures::StreamExt;
+use serenity::all::{
+    Attachment, ChannelId, CreateActionRow, CreateAttachment, CreateEmbed, CreateEmbedFooter,
+    EditInteractionResponse, Guild, GuildChannel, GuildId, Message, MessageId, MessageUpdateEvent,
+    PartialGuildChannel, UnavailableGuild, UserId,
 };
-use serenity::all::Message;
 use serenity::{
     all::{
         CommandInteraction, Context, CreateInteractionResponse, CreateInteractionResponseFollowup,
-        CreateInteractionResponseMessage, EventHandler, Http, Interaction, Ready,
+        CreateInteractionResponseMessage, EventHandler, Interaction, Ready,
     },
     async_trait,
-    prelude::*,
 };
 
+const DISCORD_MESSAGE_LIMIT: usize = 2000;
+const EMBED_DESCRIPTION_LIMIT: usize = 4096;
+/// Discord shows at most five buttons in a row and five rows under a message.
+const BUTTONS_PER_ROW: usize = 5;
+const MAX_BUTTON_ROWS: usize = 5;
+/// Name of the file a reply too long for an embed is sent as.
+const LONG_REPLY_FILE_NAME: &str = "response.md";
+/// Minimum time between edits of a streamed response, keeping under Discord's edit rate limits.
+const STREAM_EDIT_INTERVAL: Duration = Duration::from_millis(1500);
+
+const COMMAND_FAILED_MESSAGE: &str = "Command failed to execute, please try again later";
+
+/// The message a streamed response is currently being written into.
+enum StreamTarget {
+    /// The deferred interaction response.
+    Response,
+    Followup(MessageId),
+    /// The current message is full, the next flush starts a new followup.
+    NextFollowup,
+}
+
 pub struct Handler {
-    llm_engine: LlmEngine,
-    http_client: serenity::http::Http,
-    environment: Environment,
-    vec_db_client: VdbHandler,
+    services: SharedServices,
+    memory: ConversationMemory,
+    tools: ToolRegistry,
+    commands: CommandRegistry,
+    backfill_jobs: Arc<BackfillJobs>,
+    ingest: IngestQueue,
+    opt_outs: Arc<OptOutStore>,
+    database: Database,
+    guild_configs: GuildConfigs,
 }
 
 #[async_trait]
@@ -31,70 +80,190 @@ impl EventHandler for Handler {
     // Event handlers are dispatched through a threadpool, and so multiple events can be
     // dispatched simultaneously.
     async fn message(&self, ctx: Context, msg: Message) {
-        println!("Recieved message, finding embedding");
-        let embedding = match self.llm_engine.get_embed(&msg.content).await {
-            Ok(embedding) => embedding,
-            Err(err) => {
-                println!("Failed to retrieve embedding, {}", err);
-                return;
+        let services = self.services();
+        let guild_config = self.guild_configs.get(msg.guild_id).await;
+        if guild_config.stores_channel(msg.channel_id) && !self.opt_outs.is_opted_out(msg.author.id)
+        {
+            self.ingest.push(msg.clone()).await;
+        }
+
+        if let Err(err) = self
+            .memory
+            .record(&msg, &ctx, services.llm_engine.as_ref())
+            .await
+        {
+            println!("Failed to add message to conversation memory, {}", err);
+        }
+
+        if should_reply(&msg, ctx.cache.current_user().id) {
+            self.record_usage(msg.guild_id, msg.author.id, "reply")
+                .await;
+            self.reply_to_message(&msg, &ctx, &services, &guild_config)
+                .await;
+        }
+    }
+
+    // Edited messages are re-embedded so searches match what the message says now.
+    async fn message_update(
+        &self,
+        ctx: Context,
+        _: Option<Message>,
+        new: Option<Message>,
+        event: MessageUpdateEvent,
+    ) {
+        if let Some(content) = event.content.as_deref() {
+            self.update_message_in_vec_db(event.id, content, event.attachments.as_deref())
+                .await;
+        }
+        // The new version of the message is only available for cached messages.
+        if let Some(new) = new {
+            if let Err(err) = self
+                .memory
+                .record(&new, &ctx, self.services().llm_engine.as_ref())
+                .await
+            {
+                println!("Failed to update message in conversation memory, {}", err);
             }
-        };
+        }
+    }
 
-        println!("Adding message to vec db");
-        let guild_id = if let Some(guild_id) = msg.guild_id {
-            guild_id
-        } else {
-            println!("Error, missing guild id to add to vector database");
-            return;
-        };
+    // Deleted messages must not be retrievable, so they are removed from the vector database
+    // and the conversation memory.
+    async fn message_delete(
+        &self,
+        _: Context,
+        channel_id: ChannelId,
+        deleted_message_id: MessageId,
+        _: Option<GuildId>,
+    ) {
+        self.delete_messages_from_vec_db(channel_id, vec![deleted_message_id])
+            .await;
+    }
 
-        self.vec_db_client
-            .add_vector(embedding, msg.content, msg.id.get(), guild_id.get())
-            .await
-            .map_err(|err| println!("Error adding vector to database, {}", err))
-            .ok();
+    async fn message_delete_bulk(
+        &self,
+        _: Context,
+        channel_id: ChannelId,
+        multiple_deleted_messages_ids: Vec<MessageId>,
+        _: Option<GuildId>,
+    ) {
+        self.delete_messages_from_vec_db(channel_id, multiple_deleted_messages_ids)
+            .await;
     }
 
-    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
-        if let Interaction::Command(command) = interaction {
-            let content: Result<String> = match command.data.name.as_str() {
-                "ask" => {
-                    self.send_defer_message(&command, &ctx).await;
-                    run_ask(&command.data.options(), &self.llm_engine).await
-                }
-                "weigh-in" => {
-                    self.send_defer_message(&command, &ctx).await;
-                    run_weigh_in(
-                        &command,
-                        &self.llm_engine,
-                        &self.vec_db_client,
-                        &self.http_client,
-                        &self.environment,
-                    )
-                    .await
-                }
-                _ => Err(Error::CommandNotImplemented),
-            };
+    async fn channel_delete(&self, _: Context, channel: GuildChannel, _: Option<Vec<Message>>) {
+        self.purge_channel(channel.id).await;
+    }
 
-            println!("{:?}", content);
+    async fn thread_delete(
+        &self,
+        _: Context,
+        thread: PartialGuildChannel,
+        _: Option<GuildChannel>,
+    ) {
+        self.purge_channel(thread.id).await;
+    }
 
-            let response_message = match &content {
-                Ok(reply) => reply,
-                Err(err) => {
-                    println!("Interaction execution failed, reason: {}", err);
-                    "Command failed to execute, please try again later"
-                }
-            };
+    // Also sent when a guild becomes unavailable during an outage, in which case nothing is
+    // purged. Otherwise the bot was removed from the guild and forgets everything said there.
+    async fn guild_delete(&self, _: Context, incomplete: UnavailableGuild, _: Option<Guild>) {
+        if incomplete.unavailable {
+            return;
+        }
+        println!("Removed from guild {}, purging its messages", incomplete.id);
+        self.ingest.forget_guild(incomplete.id).await;
+        self.memory.forget_guild(incomplete.id).await;
+        if let Err(err) = self.guild_configs.reset(incomplete.id, None).await {
+            println!(
+                "Failed to remove settings of guild {}, {}",
+                incomplete.id, err
+            );
+        }
+        if let Err(err) = self
+            .services()
+            .vec_db_client
+            .delete_guild(incomplete.id.get())
+            .await
+        {
+            println!(
+                "Failed to purge guild {} from vector database, {}",
+                incomplete.id, err
+            );
+        }
+    }
 
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        let Interaction::Command(command) = interaction else {
+            return;
+        };
+        self.record_usage(command.guild_id, command.user.id, &command.data.name)
+            .await;
+        let guild_config = self.guild_configs.get(command.guild_id).await;
+        let slash_command = match self.commands.get(&command.data.name) {
+            Some(slash_command) if guild_config.command_enabled(slash_command.name()) => {
+                slash_command
+            }
+            Some(_) => {
+                self.send_ephemeral_message(
+                    "This command is disabled in this server.",
+                    &command,
+                    &ctx,
+                )
+                .await;
+                return;
+            }
+            None => {
+                println!(
+                    "Interaction execution failed, reason: {}",
+                    Error::CommandNotImplemented
+                );
+                self.send_ephemeral_message(COMMAND_FAILED_MESSAGE, &command, &ctx)
+                    .await;
+                return;
+            }
+        };
+
+        let ephemeral = slash_command.ephemeral();
+        self.send_defer_message(&command, &ctx, ephemeral).await;
+        let services = self.services();
+        let llm_engine = engine_for(&services, &guild_config);
+        let started = Instant::now();
+        let command_ctx = CommandContext {
+            command: &command,
+            discord: &ctx,
+            services: &services,
+            llm_engine: &llm_engine,
+            guild_config: &guild_config,
+            guild_configs: &self.guild_configs,
+            memory: &self.memory,
+            ingest: &self.ingest,
+            opt_outs: &self.opt_outs,
+            backfill_jobs: &self.backfill_jobs,
+            database: &self.database,
+        };
+        let sent = match slash_command.run(&command_ctx).await {
+            Ok(Reply::Message(reply)) => {
+                let footer = reply
+                    .generated
+                    .then(|| generation_footer(llm_engine.model(), started));
+                self.send_reply(reply, footer, &command, &ctx, ephemeral)
+                    .await
+                    .map_err(Error::from)
+            }
+            Ok(Reply::Stream(stream)) => {
+                self.send_streamed_response(stream, llm_engine.model(), started, &command, &ctx)
+                    .await
+            }
+            Err(err) => Err(err),
+        };
+        if let Err(err) = sent {
+            println!("Interaction execution failed, reason: {}", err);
+            let failed = ReplyMessage::text(COMMAND_FAILED_MESSAGE);
             if let Err(why) = self
-                .send_message_in_chunks(response_message, &command, &ctx)
+                .send_reply(failed, None, &command, &ctx, ephemeral)
                 .await
             {
                 println!("Sending command response failed {why:?}");
-                if let SerenityError::Model(ModelError::MessageTooLong(size)) = why {
-                    println!("size: {}, {}", size, content.unwrap_or("".to_string()))
-                }
-                let _ = command.delete_response(&ctx.http).await;
             }
         }
     }
@@ -110,44 +279,373 @@ impl EventHandler for Handler {
 }
 
 impl Handler {
-    pub fn new(
-        environment: &Environment,
-        http_client: Http,
-        vec_db_client: VdbHandler,
-    ) -> std::result::Result<Handler, crate::error::Error> {
+    pub async fn new(services: Services, database: Database) -> crate::error::Result<Handler> {
+        let environment = services.environment.clone();
+        let services = Arc::new(ArcSwap::from_pointee(services));
+        let opt_outs = Arc::new(OptOutStore::load(database.clone()).await?);
+        for job in database.interrupt_running_jobs().await? {
+            println!(
+                "{} job {} on {} was interrupted by a restart, last progress: {}",
+                job.kind,
+                job.id,
+                job.target,
+                job.progress.as_deref().unwrap_or("none")
+            );
+        }
         Ok(Handler {
-            llm_engine: LlmEngine::new(environment)?,
-            vec_db_client,
-            http_client,
-            environment: environment.clone(),
+            ingest: IngestQueue::spawn(services.clone()),
+            services,
+            memory: ConversationMemory::new(
+                &environment.memory,
+                database.clone(),
+                opt_outs.clone(),
+            ),
+            tools: ToolRegistry::with_default_tools(),
+            commands: CommandRegistry::with_default_commands(),
+            backfill_jobs: Arc::new(BackfillJobs::new(database.clone())),
+            opt_outs,
+            guild_configs: GuildConfigs::new(&environment, database.clone()),
+            database,
         })
     }
 
-    async fn send_message_in_chunks(
+    /// Switches to a reloaded configuration. Nothing changes if the new configuration cannot
+    /// be applied, such as when a deny pattern is invalid or the new vector database cannot be
+    /// reached.
+    pub async fn reload(&self, environment: Environment) -> crate::error::Result<()> {
+        let current = self.services();
+        let services = current.rebuild(environment).await?;
+        for setting in restart_required(&current.environment, &services.environment) {
+            println!("Changes to {setting} take effect after a restart");
+        }
+        self.memory.reconfigure(&services.environment.memory);
+        self.guild_configs.set_defaults(&services.environment);
+        self.services.store(Arc::new(services));
+        Ok(())
+    }
+
+    /// The current services. Handlers hold on to them until they finish, so a reload never
+    /// changes them midway through an event.
+    fn services(&self) -> Arc<Services> {
+        self.services.load_full()
+    }
+
+    /// Counts a use of a command or feature. Failing to count is not worth failing the use over.
+    async fn record_usage(&self, guild_id: Option<GuildId>, user_id: UserId, name: &str) {
+        if let Err(err) = self.database.record_usage(guild_id, user_id, name).await {
+            println!("Failed to record usage of {}, {}", name, err);
+        }
+    }
+
+    async fn update_message_in_vec_db(
         &self,
-        message: &str,
+        message_id: MessageId,
+        content: &str,
+        attachments: Option<&[Attachment]>,
+    ) {
+        // A message still in the ingestion queue is stored with its new content.
+        if self
+            .ingest
+            .edit_message(message_id, content, attachments)
+            .await
+        {
+            return;
+        }
+        let services = self.services();
+        let stored = match services
+            .vec_db_client
+            .get_vectors(vec![message_id.get()])
+            .await
+        {
+            Ok(stored) => stored,
+            Err(err) => {
+                println!("Failed to look up edited message {}, {}", message_id, err);
+                return;
+            }
+        };
+        // Messages that were never stored stay that way.
+        let Some(mut stored) = stored.into_iter().next() else {
+            return;
+        };
+        if stored.message == content {
+            return;
+        }
+        // An edit can turn a stored message into one the filter rejects, such as a pasted secret.
+        if !services.filter.allows_content(content) {
+            if let Err(err) = services
+                .vec_db_client
+                .delete_messages(vec![message_id.get()])
+                .await
+            {
+                println!("Failed to remove edited message {}, {}", message_id, err);
+            }
+            return;
+        }
+
+        let embedding = match services.llm_engine.get_embed(content).await {
+            Ok(embedding) => embedding,
+            Err(err) => {
+                // A stale embedding must not outlive the edit, so drop it rather than keep it.
+                println!("Failed to embed edited message {}, {}", message_id, err);
+                if let Err(err) = services
+                    .vec_db_client
+                    .delete_messages(vec![message_id.get()])
+                    .await
+                {
+                    println!("Failed to remove edited message {}, {}", message_id, err);
+                }
+                return;
+            }
+        };
+        stored.apply_edit(embedding, content, attachments);
+        if let Err(err) = services.vec_db_client.add_vectors(vec![stored]).await {
+            println!("Failed to update edited message {}, {}", message_id, err);
+        }
+    }
+
+    async fn delete_messages_from_vec_db(
+        &self,
+        channel_id: ChannelId,
+        message_ids: Vec<MessageId>,
+    ) {
+        self.memory.forget(channel_id, &message_ids).await;
+        self.ingest.delete_messages(&message_ids).await;
+        if let Err(err) = self
+            .services()
+            .vec_db_client
+            .delete_messages(
+                message_ids
+                    .iter()
+                    .map(|message_id| message_id.get())
+                    .collect(),
+            )
+            .await
+        {
+            println!(
+                "Failed to remove deleted messages from vector database, {}",
+                err
+            );
+        }
+    }
+
+    async fn purge_channel(&self, channel_id: ChannelId) {
+        self.memory.forget_channel(channel_id).await;
+        self.ingest.forget_channel(channel_id).await;
+        if let Err(err) = self
+            .services()
+            .vec_db_client
+            .delete_channel(channel_id.get())
+            .await
+        {
+            println!(
+                "Failed to purge channel {} from vector database, {}",
+                channel_id, err
+            );
+        }
+    }
+
+    async fn reply_to_message(
+        &self,
+        msg: &Message,
+        ctx: &Context,
+        services: &Services,
+        guild_config: &GuildConfig,
+    ) {
+        let typing = msg.channel_id.start_typing(&ctx.http);
+        let reply = generate_reply(
+            msg,
+            ctx,
+            engine_for(services, guild_config).as_ref(),
+            &services.vec_db_client,
+            &self.tools,
+            &services.environment,
+            guild_config,
+        )
+        .await;
+        typing.stop();
+
+        let mut reply = match reply {
+            Ok(reply) => reply,
+            Err(err) => {
+                println!("Failed to generate reply, {}", err);
+                return;
+            }
+        };
+
+        while !reply.trim().is_empty() {
+            let rest = reply.split_off(find_split_point(&reply, DISCORD_MESSAGE_LIMIT));
+            if let Err(why) = msg.reply(&ctx.http, &reply).await {
+                println!("Failed to send reply {why:?}");
+                return;
+            }
+            reply = rest.trim_start().to_string();
+        }
+    }
+
+    /// Sends the reply as a followup to the deferred response. Content too long for a message
+    /// moves into an embed, or into a markdown file when it is too long for an embed too.
+    async fn send_reply(
+        &self,
+        reply: ReplyMessage,
+        footer: Option<CreateEmbedFooter>,
         command: &CommandInteraction,
         ctx: &Context,
+        ephemeral: bool,
     ) -> std::result::Result<(), serenity::Error> {
-        let messages: Vec<&str> = message.split("\n\n").collect();
+        let ReplyMessage {
+            content,
+            mut embeds,
+            mut attachments,
+            buttons,
+            ..
+        } = reply;
+        let length = content.chars().count();
+        let mut data = CreateInteractionResponseFollowup::new().ephemeral(ephemeral);
+        if length <= DISCORD_MESSAGE_LIMIT {
+            data = data.content(content.as_str());
+        } else if length <= EMBED_DESCRIPTION_LIMIT {
+            embeds.insert(0, CreateEmbed::new().description(content.as_str()));
+        } else {
+            attachments.push(CreateAttachment::bytes(
+                content.as_bytes(),
+                LONG_REPLY_FILE_NAME,
+            ));
+            data = data.content(format!(
+                "The response is too long for a message, it is attached as {LONG_REPLY_FILE_NAME}."
+            ));
+        }
+        if let Some(footer) = footer {
+            let embed = embeds.pop().unwrap_or_default();
+            embeds.push(embed.footer(footer));
+        }
+        let rows = buttons
+            .chunks(BUTTONS_PER_ROW)
+            .take(MAX_BUTTON_ROWS)
+            .map(|row| CreateActionRow::Buttons(row.to_vec()))
+            .collect();
+        let data = data.embeds(embeds).files(attachments).components(rows);
+
+        let mut sent = command.create_followup(&ctx.http, data).await?;
+        // Private responses are not part of the conversation. Content moved out of the message
+        // is still remembered as what the bot said.
+        if !ephemeral {
+            sent.content = content;
+            self.record_response(&sent, ctx).await;
+        }
+        Ok(())
+    }
 
-        for message in messages {
-            if message.chars().count() > 0 {
-                let data = CreateInteractionResponseFollowup::new().content(message);
-                command.create_followup(&ctx.http, data).await?;
+    /// Writes a streamed completion into the deferred response, editing it as pieces arrive and
+    /// continuing in followup messages once the Discord message length cap is reached.
+    async fn send_streamed_response(
+        &self,
+        mut stream: ChatStream,
+        model: &str,
+        started: Instant,
+        command: &CommandInteraction,
+        ctx: &Context,
+    ) -> Result<()> {
+        let mut target = StreamTarget::Response;
+        let mut content = String::new();
+        let mut last_edit = Instant::now();
+
+        while let Some(chunk) = stream.next().await {
+            content.push_str(&chunk?);
+
+            while content.chars().count() > DISCORD_MESSAGE_LIMIT {
+                let rest = content.split_off(find_split_point(&content, DISCORD_MESSAGE_LIMIT));
+                self.flush_streamed_message(&mut target, &content, None, command, ctx)
+                    .await?;
+                target = StreamTarget::NextFollowup;
+                content = rest.trim_start().to_string();
+                last_edit = Instant::now();
+            }
+
+            if last_edit.elapsed() >= STREAM_EDIT_INTERVAL {
+                self.flush_streamed_message(&mut target, &content, None, command, ctx)
+                    .await?;
+                last_edit = Instant::now();
             }
         }
 
+        if content.trim().is_empty() && matches!(target, StreamTarget::Response) {
+            return Err(llm::error::Error::EmptyResponseError.into());
+        }
+        let footer = generation_footer(model, started);
+        self.flush_streamed_message(&mut target, &content, Some(footer), command, ctx)
+            .await?;
         Ok(())
     }
 
-    async fn send_defer_message(&self, command: &CommandInteraction, ctx: &Context) {
+    async fn flush_streamed_message(
+        &self,
+        target: &mut StreamTarget,
+        content: &str,
+        footer: Option<CreateEmbedFooter>,
+        command: &CommandInteraction,
+        ctx: &Context,
+    ) -> std::result::Result<(), serenity::Error> {
+        if content.trim().is_empty() {
+            return Ok(());
+        }
+        let embeds: Vec<CreateEmbed> = footer
+            .map(|footer| CreateEmbed::new().footer(footer))
+            .into_iter()
+            .collect();
+        let followup = CreateInteractionResponseFollowup::new()
+            .content(content)
+            .embeds(embeds.clone());
+        let sent = match target {
+            StreamTarget::Response => {
+                command
+                    .edit_response(
+                        &ctx.http,
+                        EditInteractionResponse::new()
+                            .content(content)
+                            .embeds(embeds),
+                    )
+                    .await?
+            }
+            StreamTarget::Followup(message_id) => {
+                command
+                    .edit_followup(&ctx.http, *message_id, followup)
+                    .await?
+            }
+            StreamTarget::NextFollowup => {
+                let message = command.create_followup(&ctx.http, followup).await?;
+                *target = StreamTarget::Followup(message.id);
+                message
+            }
+        };
+        self.record_response(&sent, ctx).await;
+        Ok(())
+    }
+
+    /// Keeps the conversation memory in step with command responses, which are edited into
+    /// place and so never arrive as complete messages through the `message` event.
+    async fn record_response(&self, message: &Message, ctx: &Context) {
+        if let Err(err) = self
+            .memory
+            .record(message, ctx, self.services().llm_engine.as_ref())
+            .await
+        {
+            println!("Failed to add response to conversation memory, {}", err);
+        }
+    }
+
+    async fn send_defer_message(
+        &self,
+        command: &CommandInteraction,
+        ctx: &Context,
+        ephemeral: bool,
+    ) {
         if let Err(why) = command
             .create_response(
                 &ctx,
                 CreateInteractionResponse::Defer(
                     CreateInteractionResponseMessage::new()
-                        .content("Working on my response. Please wait"),
+                        .content("Working on my response. Please wait")
+                        .ephemeral(ephemeral),
                 ),
             )
             .await
@@ -155,4 +653,122 @@ impl Handler {
             println!("Failed to defer ask {why:?}");
         }
     }
+
+    async fn send_ephemeral_message(
+        &self,
+        message: &str,
+        command: &CommandInteraction,
+        ctx: &Context,
+    ) {
+        let response = CreateInteractionResponseMessage::new()
+            .content(message)
+            .ephemeral(true);
+        if let Err(why) = command
+            .create_response(&ctx.http, CreateInteractionResponse::Message(response))
+            .await
+        {
+            println!("Sending command response failed {why:?}");
+        }
+    }
+}
+
+/// Notes which model wrote a reply and how long it took.
+fn generation_footer(model: &str, started: Instant) -> CreateEmbedFooter {
+    CreateEmbedFooter::new(format!("{model} · {:.1}s", started.elapsed().as_secs_f32()))
+}
+
+/// The engine completing with the model the guild chose.
+fn engine_for(services: &Services, guild_config: &GuildConfig) -> Arc<LlmEngine> {
+    match &guild_config.model {
+        Some(model) if Some(model) != services.environment.llm.model.as_ref() => {
+            Arc::new(services.llm_engine.with_chat_model(model))
+        }
+        _ => services.llm_engine.clone(),
+    }
+}
+
+/// Byte index to split `content` at so the first part holds at most `limit` characters,
+/// preferring paragraph, line and word boundaries.
+fn find_split_point(content: &str, limit: usize) -> usize {
+    let limit_index = match content.char_indices().nth(limit) {
+        Some((index, _)) => index,
+        None => return content.len(),
+    };
+    let head = &content[..limit_index];
+    ["\n\n", "\n", " "]
+        .into_iter()
+        .find_map(|separator| head.rfind(separator).filter(|index| *index > 0))
+        .unwrap_or(limit_index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_content_is_not_split() {
+        assert_eq!(find_split_point("hello world", DISCORD_MESSAGE_LIMIT), 11);
+        let exact = "a".repeat(DISCORD_MESSAGE_LIMIT);
+        assert_eq!(find_split_point(&exact, DISCORD_MESSAGE_LIMIT), exact.len());
+    }
+
+    #[test]
+    fn limit_counts_characters_not_bytes() {
+        // Exactly at the limit in characters, but far over it in bytes.
+        let content = "é".repeat(DISCORD_MESSAGE_LIMIT);
+        assert_eq!(
+            find_split_point(&content, DISCORD_MESSAGE_LIMIT),
+            content.len()
+        );
+
+        let content = "é".repeat(DISCORD_MESSAGE_LIMIT + 1);
+        let split = find_split_point(&content, DISCORD_MESSAGE_LIMIT);
+        assert!(content.is_char_boundary(split));
+        assert_eq!(content[..split].chars().count(), DISCORD_MESSAGE_LIMIT);
+    }
+
+    #[test]
+    fn unbroken_multibyte_content_splits_on_a_character_boundary() {
+        let content = "🦀".repeat(DISCORD_MESSAGE_LIMIT * 2);
+        let split = find_split_point(&content, DISCORD_MESSAGE_LIMIT);
+        assert_eq!(split, DISCORD_MESSAGE_LIMIT * '🦀'.len_utf8());
+    }
+
+    #[test]
+    fn prefers_paragraph_then_line_then_word_boundaries() {
+        let words = "word ".repeat(DISCORD_MESSAGE_LIMIT / 5);
+        let content = format!("first paragraph\n\nsecond line\n{words}tail");
+        assert_eq!(
+            find_split_point(&content, DISCORD_MESSAGE_LIMIT),
+            "first paragraph".len()
+        );
+
+        let content = format!("first line\n{words}tail");
+        assert_eq!(
+            find_split_point(&content, DISCORD_MESSAGE_LIMIT),
+            "first line".len()
+        );
+
+        let content = format!("{words}tail");
+        let split = find_split_point(&content, DISCORD_MESSAGE_LIMIT);
+        assert_eq!(&content[split..split + 1], " ");
+        assert!(content[..split].chars().count() <= DISCORD_MESSAGE_LIMIT);
+    }
+
+    #[test]
+    fn ignores_boundaries_past_the_limit() {
+        let content = format!("{}\n\nrest", "ü".repeat(DISCORD_MESSAGE_LIMIT + 10));
+        let split = find_split_point(&content, DISCORD_MESSAGE_LIMIT);
+        assert_eq!(split, DISCORD_MESSAGE_LIMIT * 'ü'.len_utf8());
+    }
+
+    #[test]
+    fn leading_separator_is_not_used_as_split() {
+        // Splitting at index 0 would never make progress.
+        let content = format!(" {}", "x".repeat(DISCORD_MESSAGE_LIMIT));
+        assert_eq!(
+            find_split_point(&content, DISCORD_MESSAGE_LIMIT),
+            DISCORD_MESSAGE_LIMIT
+        );
+    }
 }
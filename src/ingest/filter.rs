@@ -0,0 +1,254 @@
+use std::collections::{HashMap, HashSet};
+use std::num::NonZeroU64;
+
+use regex::RegexSet;
+use serenity::all::{ChannelId, GuildId, Message, RoleId};
+
+use crate::environment::FilterOptions;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Decides which messages are worth storing in the vector database, and which must never be.
+pub struct IngestFilter {
+    min_length: usize,
+    ignore_bots: bool,
+    ignored_prefixes: Vec<String>,
+    ignored_channels: HashSet<ChannelId>,
+    ignored_roles: HashSet<RoleId>,
+    deny_list: RegexSet,
+    /// Guilds messages are stored from, every guild when empty.
+    allowed_guilds: HashSet<GuildId>,
+    /// Per guild, the only channels messages are stored from.
+    allowed_channels: HashMap<GuildId, HashSet<ChannelId>>,
+}
+
+impl IngestFilter {
+    pub fn new(options: &FilterOptions) -> Result<Self> {
+        let allowed_channels = options
+            .allowed_channels
+            .iter()
+            .map(|(guild_id, channel_ids)| {
+                let guild_id = guild_id
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|guild_id| *guild_id != 0)
+                    .ok_or_else(|| Error::InvalidGuildId(guild_id.clone()))?;
+                Ok((
+                    GuildId::new(guild_id),
+                    ids("allowed_channels", channel_ids)?,
+                ))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            min_length: options.min_length,
+            ignore_bots: options.ignore_bots,
+            ignored_prefixes: options.ignored_prefixes.clone(),
+            ignored_channels: ids("ignored_channels", &options.ignored_channels)?,
+            ignored_roles: ids("ignored_roles", &options.ignored_roles)?,
+            deny_list: RegexSet::new(&options.deny_patterns)?,
+            allowed_guilds: ids("allowed_guilds", &options.allowed_guilds)?,
+            allowed_channels,
+        })
+    }
+
+    /// Whether `message` may be stored. Messages outside guilds never are.
+    pub fn allows(&self, message: &Message) -> bool {
+        let Some(guild_id) = message.guild_id else {
+            return false;
+        };
+        if self.ignore_bots && message.author.bot {
+            return false;
+        }
+        if !self.allowed_guilds.is_empty() && !self.allowed_guilds.contains(&guild_id) {
+            return false;
+        }
+        if self
+            .allowed_channels
+            .get(&guild_id)
+            .is_some_and(|channels| !channels.contains(&message.channel_id))
+        {
+            return false;
+        }
+        if self.ignored_channels.contains(&message.channel_id) {
+            return false;
+        }
+        if message.member.as_ref().is_some_and(|member| {
+            member
+                .roles
+                .iter()
+                .any(|role| self.ignored_roles.contains(role))
+        }) {
+            return false;
+        }
+        self.allows_content(&message.content)
+    }
+
+    /// The checks that only look at the text, used when an edit changes a stored message.
+    pub fn allows_content(&self, content: &str) -> bool {
+        let content = content.trim();
+        content.chars().count() >= self.min_length.max(1)
+            && !self
+                .ignored_prefixes
+                .iter()
+                .any(|prefix| content.starts_with(prefix.as_str()))
+            && !self.deny_list.is_match(content)
+    }
+}
+
+/// Discord ids are never zero, so a zero in `setting` is a configuration mistake.
+fn ids<T: From<NonZeroU64> + std::hash::Hash + Eq>(
+    setting: &'static str,
+    ids: &[u64],
+) -> Result<HashSet<T>> {
+    ids.iter()
+        .map(|id| {
+            NonZeroU64::new(*id)
+                .map(T::from)
+                .ok_or(Error::ZeroId(setting))
+        })
+        .collect()
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Invalid deny pattern, {0}")]
+    InvalidPattern(#[from] regex::Error),
+    #[error("{0} is not a guild id")]
+    InvalidGuildId(String),
+    #[error("{0} contains 0, which is not a Discord id")]
+    ZeroId(&'static str),
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use serenity::all::{PartialMember, User};
+
+    use super::*;
+
+    fn filter(options: Value) -> Result<IngestFilter> {
+        let mut base = json!({ "min_length": 3 });
+        base.as_object_mut()
+            .unwrap()
+            .extend(options.as_object().unwrap().clone());
+        IngestFilter::new(&serde_json::from_value(base).unwrap())
+    }
+
+    fn message(guild_id: u64, channel_id: u64, content: &str) -> Message {
+        let mut message = Message::default();
+        message.guild_id = Some(GuildId::new(guild_id));
+        message.channel_id = ChannelId::new(channel_id);
+        message.content = content.to_string();
+        message
+    }
+
+    #[test]
+    fn allows_only_guild_messages() {
+        let filter = filter(json!({})).unwrap();
+        assert!(filter.allows(&message(1, 10, "hello")));
+
+        let mut direct = message(1, 10, "hello");
+        direct.guild_id = None;
+        assert!(!filter.allows(&direct));
+    }
+
+    #[test]
+    fn rejects_short_messages() {
+        let filter = filter(json!({})).unwrap();
+        assert!(!filter.allows(&message(1, 10, "hi")));
+        assert!(!filter.allows(&message(1, 10, "  hi  ")));
+        assert!(filter.allows(&message(1, 10, "héé")));
+
+        let unlimited = self::filter(json!({ "min_length": 0 })).unwrap();
+        assert!(!unlimited.allows_content("   "));
+    }
+
+    #[test]
+    fn ignores_bots_when_asked() {
+        let mut bot = User::default();
+        bot.bot = true;
+        let mut from_bot = message(1, 10, "hello");
+        from_bot.author = bot;
+
+        assert!(filter(json!({})).unwrap().allows(&from_bot));
+        assert!(!filter(json!({ "ignore_bots": true }))
+            .unwrap()
+            .allows(&from_bot));
+    }
+
+    #[test]
+    fn rejects_ignored_prefixes_and_deny_patterns() {
+        let filter = filter(json!({
+            "ignored_prefixes": ["!", "?play"],
+            "deny_patterns": ["(?i)password", r"sk-[a-z0-9]{8}"],
+        }))
+        .unwrap();
+
+        assert!(!filter.allows_content("!roll d20"));
+        assert!(!filter.allows_content("  ?play some song"));
+        assert!(filter.allows_content("what is ?play"));
+        assert!(!filter.allows_content("my PassWord is hunter2"));
+        assert!(!filter.allows_content("key: sk-abcd1234"));
+        assert!(filter.allows_content("key: sk-short"));
+    }
+
+    #[test]
+    fn rejects_ignored_channels_and_roles() {
+        let filter = filter(json!({
+            "ignored_channels": [11],
+            "ignored_roles": [5],
+        }))
+        .unwrap();
+        assert!(!filter.allows(&message(1, 11, "hello")));
+
+        let member = |roles: Value| -> Box<PartialMember> {
+            Box::new(serde_json::from_value(json!({ "roles": roles })).unwrap())
+        };
+        let mut muted = message(1, 10, "hello");
+        muted.member = Some(member(json!(["4", "5"])));
+        assert!(!filter.allows(&muted));
+
+        let mut member_message = message(1, 10, "hello");
+        member_message.member = Some(member(json!(["4"])));
+        assert!(filter.allows(&member_message));
+    }
+
+    #[test]
+    fn restricts_to_allowed_guilds_and_channels() {
+        let filter = filter(json!({
+            "allowed_guilds": [1, 2],
+            "allowed_channels": { "1": [10] },
+        }))
+        .unwrap();
+
+        assert!(filter.allows(&message(1, 10, "hello")));
+        assert!(!filter.allows(&message(1, 11, "hello")));
+        assert!(filter.allows(&message(2, 11, "hello")));
+        assert!(!filter.allows(&message(3, 10, "hello")));
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        assert!(matches!(
+            filter(json!({ "allowed_channels": { "guild": [10] } })),
+            Err(Error::InvalidGuildId(_))
+        ));
+        assert!(matches!(
+            filter(json!({ "allowed_channels": { "0": [10] } })),
+            Err(Error::InvalidGuildId(_))
+        ));
+        assert!(matches!(
+            filter(json!({ "deny_patterns": ["("] })),
+            Err(Error::InvalidPattern(_))
+        ));
+        for setting in [
+            json!({ "allowed_channels": { "1": [10, 0] } }),
+            json!({ "ignored_channels": [0] }),
+            json!({ "ignored_roles": [0] }),
+            json!({ "allowed_guilds": [2, 0] }),
+        ] {
+            assert!(matches!(filter(setting), Err(Error::ZeroId(_))));
+        }
+    }
+}
@@ -0,0 +1,738 @@
+pub mod filter;
+
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use serenity::all::{Attachment, ChannelId, GuildId, Message, MessageId, UserId};
+use tokio::{
+    sync::{
+        mpsc::{self, error::SendTimeoutError},
+        Mutex,
+    },
+    time::{sleep, timeout_at, Instant},
+};
+
+use crate::{
+    ingest::filter::IngestFilter,
+    llm::backend::EmbedBackend,
+    reload::{Services, SharedServices},
+    vec_db::vector::DbVector,
+};
+
+/// Wait before the first retry of a failed batch, doubled for every further attempt.
+const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(1);
+/// How long a deletion is remembered after its message left the queue, covering a message
+/// deleted before the event that queues it was handled.
+const DELETION_GRACE: Duration = Duration::from_secs(60);
+
+/// Feeds incoming messages to a background worker that embeds and stores them in batches, so
+/// busy channels cost one embedding request per batch instead of one per message.
+/// The filter, endpoints and batch settings are read from the shared services as they are
+/// needed, so a reloaded configuration applies from the next message or batch.
+///
+/// Deletions, edits and purges are recorded with the queue before they are applied to the vector
+/// database, so a message still waiting in the queue, the batch window or a retry is never
+/// stored after it was deleted or purged, or with the content it had before an edit.
+pub struct IngestQueue {
+    sender: mpsc::Sender<Message>,
+    services: SharedServices,
+    metrics: Arc<IngestMetrics>,
+    pending: Arc<Mutex<PendingChanges>>,
+}
+
+/// Counters describing how the ingestion pipeline is keeping up.
+#[derive(Debug, Default)]
+pub struct IngestMetrics {
+    pub queued: AtomicU64,
+    /// Messages the filter kept out of the vector database.
+    pub filtered: AtomicU64,
+    pub stored: AtomicU64,
+    /// Messages dropped because the queue stayed full.
+    pub dropped: AtomicU64,
+    /// Messages given up on after every retry failed.
+    pub failed: AtomicU64,
+    pub retries: AtomicU64,
+    /// Messages deleted, purged or edited into ones the filter rejects before they were stored.
+    pub withdrawn: AtomicU64,
+}
+
+/// Changes to messages that are on their way through the queue. Messages stay in flight until
+/// their upsert is done, so a change is either settled by the worker or made after the batch
+/// landed.
+#[derive(Default)]
+struct PendingChanges {
+    /// Messages queued or being stored.
+    in_flight: HashMap<MessageId, QueuedMessage>,
+    /// Deleted messages and when they were deleted.
+    deleted: HashMap<MessageId, Instant>,
+    /// The latest edit of each message in flight.
+    edited: HashMap<MessageId, MessageEdit>,
+    /// Authors, channels and guilds whose messages queued up to the given time must not be
+    /// stored.
+    forgotten: HashMap<Origin, Instant>,
+}
+
+/// Where a message came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Origin {
+    Author(UserId),
+    Channel(ChannelId),
+    Guild(GuildId),
+}
+
+struct QueuedMessage {
+    origins: Vec<Origin>,
+    queued_at: Instant,
+}
+
+struct MessageEdit {
+    content: String,
+    /// Unchanged when `None`.
+    attachments: Option<Vec<Attachment>>,
+}
+
+struct IngestWorker {
+    receiver: mpsc::Receiver<Message>,
+    services: SharedServices,
+    metrics: Arc<IngestMetrics>,
+    pending: Arc<Mutex<PendingChanges>>,
+}
+
+impl IngestQueue {
+    /// Starts the worker, which runs until the queue is dropped. The queue capacity is fixed
+    /// once spawned.
+    pub fn spawn(services: SharedServices) -> Self {
+        let capacity = services.load().environment.ingest.queue_capacity;
+        let (sender, receiver) = mpsc::channel(capacity.max(1));
+        let metrics = Arc::new(IngestMetrics::default());
+        let pending = Arc::new(Mutex::new(PendingChanges::default()));
+        let worker = IngestWorker {
+            receiver,
+            services: services.clone(),
+            metrics: metrics.clone(),
+            pending: pending.clone(),
+        };
+        tokio::spawn(worker.run());
+        Self {
+            sender,
+            services,
+            metrics,
+            pending,
+        }
+    }
+
+    /// Queues a message for storage if the filter allows it. When the queue is full this waits
+    /// for room, applying backpressure to the event handler, and drops the message once the wait
+    /// times out.
+    pub async fn push(&self, message: Message) {
+        let enqueue_timeout = {
+            let services = self.services.load();
+            if !services.filter.allows(&message) {
+                self.metrics.filtered.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+            Duration::from_millis(services.environment.ingest.enqueue_timeout_ms)
+        };
+        {
+            let mut pending = self.pending.lock().await;
+            if pending.deleted.contains_key(&message.id) {
+                self.metrics.withdrawn.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+            pending.in_flight.insert(
+                message.id,
+                QueuedMessage {
+                    origins: Origin::of(&message),
+                    queued_at: Instant::now(),
+                },
+            );
+        }
+        let message_id = message.id;
+        match self.sender.send_timeout(message, enqueue_timeout).await {
+            Ok(()) => {
+                self.metrics.queued.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+            Err(SendTimeoutError::Timeout(message)) => {
+                self.metrics.dropped.fetch_add(1, Ordering::Relaxed);
+                println!(
+                    "Ingestion queue full, dropped message {} ({})",
+                    message.id, self.metrics
+                );
+            }
+            Err(SendTimeoutError::Closed(message)) => {
+                println!("Ingestion worker stopped, dropped message {}", message.id);
+            }
+        }
+        self.pending.lock().await.release([message_id]);
+    }
+
+    /// Keeps deleted messages that are still queued from being stored. Call before deleting the
+    /// messages from the vector database.
+    pub async fn delete_messages(&self, message_ids: &[MessageId]) {
+        let now = Instant::now();
+        let mut pending = self.pending.lock().await;
+        // Deletions pile up while no batch is stored, so old ones are dropped here as well.
+        pending.prune(now);
+        for message_id in message_ids {
+            pending.deleted.insert(*message_id, now);
+        }
+    }
+
+    /// Has a queued message stored with its edited content. Returns `false` when the message is
+    /// not queued, in which case any stored vector has to be updated instead.
+    pub async fn edit_message(
+        &self,
+        message_id: MessageId,
+        content: &str,
+        attachments: Option<&[Attachment]>,
+    ) -> bool {
+        let mut pending = self.pending.lock().await;
+        if !pending.in_flight.contains_key(&message_id) {
+            return false;
+        }
+        let attachments = attachments.map(<[Attachment]>::to_vec).or_else(|| {
+            pending
+                .edited
+                .remove(&message_id)
+                .and_then(|edit| edit.attachments)
+        });
+        pending.edited.insert(
+            message_id,
+            MessageEdit {
+                content: content.to_string(),
+                attachments,
+            },
+        );
+        true
+    }
+
+    /// Keeps the messages the user sent so far that are still queued from being stored, for
+    /// when they opt out or ask to be forgotten. Call before deleting their stored messages.
+    pub async fn forget_author(&self, user_id: UserId) {
+        self.forget(Origin::Author(user_id)).await;
+    }
+
+    /// Keeps queued messages of a deleted channel from being stored. Call before purging it.
+    pub async fn forget_channel(&self, channel_id: ChannelId) {
+        self.forget(Origin::Channel(channel_id)).await;
+    }
+
+    /// Keeps queued messages of a guild the bot left from being stored. Call before purging it.
+    pub async fn forget_guild(&self, guild_id: GuildId) {
+        self.forget(Origin::Guild(guild_id)).await;
+    }
+
+    async fn forget(&self, origin: Origin) {
+        self.pending
+            .lock()
+            .await
+            .forgotten
+            .insert(origin, Instant::now());
+    }
+
+    pub fn metrics(&self) -> &IngestMetrics {
+        &self.metrics
+    }
+
+    /// Number of messages waiting for the worker.
+    pub fn len(&self) -> usize {
+        self.sender.max_capacity() - self.sender.capacity()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl IngestWorker {
+    async fn run(mut self) {
+        while let Some(first) = self.receiver.recv().await {
+            let (batch_size, batch_window) = {
+                let options = &self.services.load().environment.ingest;
+                (
+                    options.batch_size.max(1),
+                    Duration::from_millis(options.batch_window_ms),
+                )
+            };
+            let mut batch = vec![first];
+            let deadline = Instant::now() + batch_window;
+            while batch.len() < batch_size {
+                match timeout_at(deadline, self.receiver.recv()).await {
+                    Ok(Some(message)) => batch.push(message),
+                    Ok(None) | Err(_) => break,
+                }
+            }
+            self.store_batch(batch).await;
+        }
+    }
+
+    async fn store_batch(&self, mut batch: Vec<Message>) {
+        // The whole batch goes through the same services, even if they are swapped meanwhile.
+        let services = self.services.load_full();
+        while !batch.is_empty() {
+            batch = self.store_messages(&services, batch).await;
+        }
+        self.pending.lock().await.prune(Instant::now());
+    }
+
+    /// Embeds and stores the messages, returning those edited while they were being embedded so
+    /// they are stored again with their new content.
+    async fn store_messages(&self, services: &Services, batch: Vec<Message>) -> Vec<Message> {
+        let max_retries = services.environment.ingest.max_retries;
+        let batch = self.apply_pending(batch, &services.filter).await;
+        if batch.is_empty() {
+            return Vec::new();
+        }
+        let count = batch.len() as u64;
+        let contents: Vec<String> = batch
+            .iter()
+            .map(|message| message.content.clone())
+            .collect();
+        let embeddings = match self
+            .with_retries("embed", max_retries, || {
+                services.llm_engine.get_embeds(&contents)
+            })
+            .await
+        {
+            Some(embeddings) => embeddings,
+            None => {
+                self.metrics.failed.fetch_add(count, Ordering::Relaxed);
+                self.release(&batch).await;
+                return Vec::new();
+            }
+        };
+
+        let vectors = batch
+            .iter()
+            .zip(embeddings)
+            .filter_map(
+                |(message, embedding)| match DbVector::new(embedding, message) {
+                    Ok(vector) => Some(vector),
+                    Err(err) => {
+                        println!("Skipping message {}, {}", message.id, err);
+                        self.metrics.failed.fetch_add(1, Ordering::Relaxed);
+                        None
+                    }
+                },
+            )
+            .collect::<Vec<_>>();
+
+        // Upserts replace by message id, so retrying a partly applied batch is harmless. Changes
+        // are checked on every attempt, as they may arrive while waiting to retry. The lock is
+        // only held to pick the vectors, so changes made during an upsert are settled after it.
+        let stored = self
+            .with_retries("store", max_retries, || async {
+                let current = self.pending.lock().await.current(&vectors);
+                if !current.is_empty() {
+                    services.vec_db_client.add_vectors(current).await?;
+                }
+                Ok::<_, anyhow::Error>(())
+            })
+            .await;
+        let (withdrawn, edited) = self.pending.lock().await.settle(&vectors);
+        if !withdrawn.is_empty() {
+            // A message deleted during an upsert may have been removed from the vector database
+            // before its vector landed, so it is removed again.
+            let message_ids: Vec<u64> = withdrawn
+                .iter()
+                .map(|message_id| message_id.get())
+                .collect();
+            self.with_retries("withdraw", max_retries, || {
+                services.vec_db_client.delete_messages(message_ids.clone())
+            })
+            .await;
+        }
+        let withdrawn = withdrawn.len() as u64;
+        self.metrics
+            .withdrawn
+            .fetch_add(withdrawn, Ordering::Relaxed);
+        if stored.is_none() {
+            self.metrics
+                .failed
+                .fetch_add(vectors.len() as u64 - withdrawn, Ordering::Relaxed);
+            self.release(&batch).await;
+            return Vec::new();
+        }
+        self.metrics.stored.fetch_add(
+            vectors.len() as u64 - withdrawn - edited.len() as u64,
+            Ordering::Relaxed,
+        );
+        // Messages that could not be turned into vectors are done with as well.
+        let mut retry = Vec::new();
+        let mut skipped = Vec::new();
+        for message in batch {
+            if edited.contains(&message.id) {
+                retry.push(message);
+            } else if !vectors
+                .iter()
+                .any(|vector| vector.message_id == message.id.get())
+            {
+                skipped.push(message.id);
+            }
+        }
+        self.pending.lock().await.release(skipped);
+        retry
+    }
+
+    /// Drops the messages deleted or forgotten since they were queued and brings edited ones up
+    /// to date, dropping those the filter rejects after the edit.
+    async fn apply_pending(&self, batch: Vec<Message>, filter: &IngestFilter) -> Vec<Message> {
+        let mut pending = self.pending.lock().await;
+        let mut current = Vec::with_capacity(batch.len());
+        let mut withdrawn = Vec::new();
+        for mut message in batch {
+            if let Some(edit) = pending.edited.get(&message.id) {
+                message.content = edit.content.clone();
+                if let Some(attachments) = &edit.attachments {
+                    message.attachments = attachments.clone();
+                }
+            }
+            if pending.is_withdrawn(message.id) || !filter.allows_content(&message.content) {
+                withdrawn.push(message.id);
+            } else {
+                current.push(message);
+            }
+        }
+        self.metrics
+            .withdrawn
+            .fetch_add(withdrawn.len() as u64, Ordering::Relaxed);
+        pending.release(withdrawn);
+        current
+    }
+
+    async fn release(&self, batch: &[Message]) {
+        self.pending
+            .lock()
+            .await
+            .release(batch.iter().map(|message| message.id));
+    }
+
+    /// Runs `attempt` until it succeeds, backing off exponentially between tries. Gives up with
+    /// `None` after `max_retries` retries.
+    async fn with_retries<T, E, F, Fut>(
+        &self,
+        action: &str,
+        max_retries: u32,
+        mut attempt: F,
+    ) -> Option<T>
+    where
+        E: std::fmt::Display,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let mut delay = INITIAL_RETRY_DELAY;
+        let mut retries = 0;
+        loop {
+            match attempt().await {
+                Ok(value) => return Some(value),
+                Err(err) if retries < max_retries => {
+                    println!("Failed to {action} ingested messages, retrying in {delay:?}, {err}");
+                    self.metrics.retries.fetch_add(1, Ordering::Relaxed);
+                    sleep(delay).await;
+                    delay *= 2;
+                    retries += 1;
+                }
+                Err(err) => {
+                    println!("Failed to {action} ingested messages, giving up, {err}");
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+impl PendingChanges {
+    /// Whether the message was deleted, or where it came from forgotten, since it was queued.
+    fn is_withdrawn(&self, message_id: MessageId) -> bool {
+        self.deleted.contains_key(&message_id)
+            || self.in_flight.get(&message_id).is_some_and(|queued| {
+                queued.origins.iter().any(|origin| {
+                    self.forgotten
+                        .get(origin)
+                        .is_some_and(|forgotten_at| queued.queued_at <= *forgotten_at)
+                })
+            })
+    }
+
+    /// Whether the message was edited since the vector was embedded.
+    fn edited_since(&self, vector: &DbVector) -> bool {
+        self.edited
+            .get(&MessageId::new(vector.message_id))
+            .is_some_and(|edit| edit.content != vector.message)
+    }
+
+    /// The vectors of messages neither withdrawn nor edited since they were embedded.
+    fn current(&self, vectors: &[DbVector]) -> Vec<DbVector> {
+        vectors
+            .iter()
+            .filter(|vector| {
+                !self.is_withdrawn(MessageId::new(vector.message_id)) && !self.edited_since(vector)
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Releases the messages of stored vectors, returning those withdrawn so far, whose vectors
+    /// may have to be deleted, and those edited, which stay in flight to be stored again.
+    fn settle(&mut self, vectors: &[DbVector]) -> (Vec<MessageId>, HashSet<MessageId>) {
+        let mut withdrawn = Vec::new();
+        let mut edited = HashSet::new();
+        for vector in vectors {
+            let message_id = MessageId::new(vector.message_id);
+            if self.is_withdrawn(message_id) {
+                withdrawn.push(message_id);
+            } else if self.edited_since(vector) {
+                edited.insert(message_id);
+            }
+        }
+        self.release(
+            vectors
+                .iter()
+                .map(|vector| MessageId::new(vector.message_id))
+                .filter(|message_id| !edited.contains(message_id)),
+        );
+        (withdrawn, edited)
+    }
+
+    /// Forgets messages that left the queue, stored or not.
+    fn release(&mut self, message_ids: impl IntoIterator<Item = MessageId>) {
+        for message_id in message_ids {
+            self.in_flight.remove(&message_id);
+            self.edited.remove(&message_id);
+        }
+    }
+
+    /// Drops the changes no message in flight needs any more.
+    fn prune(&mut self, now: Instant) {
+        let in_flight = &self.in_flight;
+        self.deleted.retain(|message_id, deleted_at| {
+            in_flight.contains_key(message_id) || now.duration_since(*deleted_at) < DELETION_GRACE
+        });
+        self.forgotten.retain(|origin, forgotten_at| {
+            in_flight
+                .values()
+                .any(|queued| queued.queued_at <= *forgotten_at && queued.origins.contains(origin))
+        });
+    }
+}
+
+impl Origin {
+    fn of(message: &Message) -> Vec<Origin> {
+        [
+            Some(Origin::Author(message.author.id)),
+            Some(Origin::Channel(message.channel_id)),
+            message.guild_id.map(Origin::Guild),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+impl std::fmt::Display for IngestMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} queued, {} filtered, {} stored, {} dropped, {} failed, {} retries, {} withdrawn",
+            self.queued.load(Ordering::Relaxed),
+            self.filtered.load(Ordering::Relaxed),
+            self.stored.load(Ordering::Relaxed),
+            self.dropped.load(Ordering::Relaxed),
+            self.failed.load(Ordering::Relaxed),
+            self.retries.load(Ordering::Relaxed),
+            self.withdrawn.load(Ordering::Relaxed)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AUTHOR: UserId = UserId::new(1);
+    const CHANNEL: ChannelId = ChannelId::new(2);
+    const GUILD: GuildId = GuildId::new(3);
+
+    fn queue(pending: &mut PendingChanges, message_id: u64) -> MessageId {
+        let message_id = MessageId::new(message_id);
+        pending.in_flight.insert(
+            message_id,
+            QueuedMessage {
+                origins: vec![
+                    Origin::Author(AUTHOR),
+                    Origin::Channel(CHANNEL),
+                    Origin::Guild(GUILD),
+                ],
+                queued_at: Instant::now(),
+            },
+        );
+        message_id
+    }
+
+    fn vector(message_id: MessageId, message: &str) -> DbVector {
+        DbVector {
+            vector: vec![0.0],
+            message: message.to_string(),
+            message_id: message_id.get(),
+            guild_id: GUILD.get(),
+            channel_id: Some(CHANNEL.get()),
+            author_id: Some(AUTHOR.get()),
+            timestamp: None,
+            author_name: None,
+            reply_to_id: None,
+            has_attachments: false,
+            has_images: false,
+        }
+    }
+
+    #[test]
+    fn deleted_messages_are_withdrawn() {
+        let mut pending = PendingChanges::default();
+        let message_id = queue(&mut pending, 10);
+        assert!(!pending.is_withdrawn(message_id));
+
+        pending.deleted.insert(message_id, Instant::now());
+        assert!(pending.is_withdrawn(message_id));
+    }
+
+    #[test]
+    fn forgetting_withdraws_messages_queued_before() {
+        for origin in [
+            Origin::Author(AUTHOR),
+            Origin::Channel(CHANNEL),
+            Origin::Guild(GUILD),
+        ] {
+            let mut pending = PendingChanges::default();
+            let before = queue(&mut pending, 10);
+            pending.forgotten.insert(origin, Instant::now());
+            assert!(pending.is_withdrawn(before), "{origin:?}");
+
+            // Messages sent after someone was forgotten are stored as usual.
+            std::thread::sleep(Duration::from_millis(2));
+            let after = queue(&mut pending, 11);
+            assert!(!pending.is_withdrawn(after), "{origin:?}");
+        }
+    }
+
+    #[test]
+    fn forgetting_someone_else_keeps_the_message() {
+        let mut pending = PendingChanges::default();
+        let message_id = queue(&mut pending, 10);
+        pending
+            .forgotten
+            .insert(Origin::Author(UserId::new(99)), Instant::now());
+        assert!(!pending.is_withdrawn(message_id));
+    }
+
+    #[test]
+    fn edits_after_embedding_are_detected() {
+        let mut pending = PendingChanges::default();
+        let message_id = queue(&mut pending, 10);
+        assert!(!pending.edited_since(&vector(message_id, "original")));
+
+        pending.edited.insert(
+            message_id,
+            MessageEdit {
+                content: "edited".to_string(),
+                attachments: None,
+            },
+        );
+        assert!(pending.edited_since(&vector(message_id, "original")));
+        assert!(!pending.edited_since(&vector(message_id, "edited")));
+    }
+
+    #[test]
+    fn settling_keeps_edited_messages_in_flight() {
+        let mut pending = PendingChanges::default();
+        let stored = queue(&mut pending, 10);
+        let deleted = queue(&mut pending, 11);
+        let edited = queue(&mut pending, 12);
+        let vectors = [
+            vector(stored, "original"),
+            vector(deleted, "original"),
+            vector(edited, "original"),
+        ];
+        assert_eq!(pending.current(&vectors).len(), 3);
+
+        // Changed while the vectors were being stored.
+        pending.deleted.insert(deleted, Instant::now());
+        pending.edited.insert(
+            edited,
+            MessageEdit {
+                content: "edited".to_string(),
+                attachments: None,
+            },
+        );
+        assert_eq!(
+            pending
+                .current(&vectors)
+                .iter()
+                .map(|vector| vector.message_id)
+                .collect::<Vec<_>>(),
+            [10]
+        );
+
+        let (withdrawn, edited_ids) = pending.settle(&vectors);
+        assert_eq!(withdrawn, [deleted]);
+        assert_eq!(edited_ids, HashSet::from([edited]));
+        assert_eq!(pending.in_flight.keys().collect::<Vec<_>>(), [&edited]);
+        assert!(pending.edited.contains_key(&edited));
+    }
+
+    #[test]
+    fn release_forgets_the_message_and_its_edit() {
+        let mut pending = PendingChanges::default();
+        let message_id = queue(&mut pending, 10);
+        pending.edited.insert(
+            message_id,
+            MessageEdit {
+                content: "edited".to_string(),
+                attachments: None,
+            },
+        );
+
+        pending.release([message_id]);
+        assert!(pending.in_flight.is_empty());
+        assert!(pending.edited.is_empty());
+    }
+
+    #[test]
+    fn prune_keeps_changes_for_messages_in_flight() {
+        let mut pending = PendingChanges::default();
+        let queued = queue(&mut pending, 10);
+        let deleted_at = Instant::now();
+        let later = deleted_at + DELETION_GRACE * 2;
+        pending.deleted.insert(queued, deleted_at);
+        pending.deleted.insert(MessageId::new(11), deleted_at);
+        pending.deleted.insert(MessageId::new(12), later);
+        pending
+            .forgotten
+            .insert(Origin::Author(AUTHOR), Instant::now());
+        pending
+            .forgotten
+            .insert(Origin::Author(UserId::new(99)), Instant::now());
+
+        pending.prune(later);
+        // Recent deletions are kept in case their message is about to be queued.
+        let mut deleted: Vec<_> = pending.deleted.keys().map(|id| id.get()).collect();
+        deleted.sort();
+        assert_eq!(deleted, [10, 12]);
+        assert_eq!(
+            pending.forgotten.keys().collect::<Vec<_>>(),
+            [&Origin::Author(AUTHOR)]
+        );
+
+        pending.release([queued]);
+        pending.prune(later);
+        assert!(pending.forgotten.is_empty());
+        assert_eq!(pending.deleted.len(), 1);
+    }
+}
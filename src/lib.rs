@@ -1,6 +1,17 @@
+pub mod backfill;
 pub mod commands;
+pub mod conversation;
+pub mod database;
 pub mod environment;
 pub mod error;
+pub mod guild_config;
 pub mod handler;
+pub mod ingest;
 pub mod llm;
+pub mod memory;
+pub mod permissions;
+pub mod privacy;
+pub mod rag;
+pub mod reload;
+pub mod tools;
 pub mod vec_db;
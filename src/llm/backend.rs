@@ -0,0 +1,70 @@
+use std::pin::Pin;
+
+use async_trait::async_trait;
+use futures::{stream, Stream};
+use serde::Deserialize;
+
+use super::{
+    error::Result,
+    model::{AssistantMessage, LlmChat, ToolDefinition},
+};
+
+/// Which provider the [`LlmEngine`](super::engine::LlmEngine) talks to.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LlmBackendKind {
+    #[default]
+    Ollama,
+    /// Any server speaking the OpenAI chat completions and embeddings protocol.
+    OpenAi,
+}
+
+/// Incremental pieces of a chat completion, in the order the model produced them.
+pub type ChatStream = Pin<Box<dyn Stream<Item = Result<String>> + Send>>;
+
+#[async_trait]
+pub trait ChatBackend: Send + Sync {
+    /// Name of the model used for completions.
+    fn model(&self) -> &str;
+
+    /// The same backend completing with `model` instead.
+    fn with_model(&self, model: &str) -> Box<dyn ChatBackend>;
+
+    async fn get_completion(&self, question: &str) -> Result<String>;
+
+    async fn get_chat_completion(&self, messages: LlmChat) -> Result<String>;
+
+    /// Chat completion offering `tools` to the model, the returned message either answers or
+    /// requests tool calls. Backends without tool support answer without them.
+    async fn get_tool_chat_completion(
+        &self,
+        messages: LlmChat,
+        _tools: &[ToolDefinition],
+    ) -> Result<AssistantMessage> {
+        Ok(AssistantMessage::new(
+            self.get_chat_completion(messages).await?,
+        ))
+    }
+
+    /// Streams the chat completion as it is generated. Backends without streaming support
+    /// return the whole completion as a single piece.
+    async fn stream_chat_completion(&self, messages: LlmChat) -> Result<ChatStream> {
+        let completion = self.get_chat_completion(messages).await;
+        Ok(Box::pin(stream::once(async move { completion })))
+    }
+}
+
+#[async_trait]
+pub trait EmbedBackend: Send + Sync {
+    async fn get_embed(&self, message: &str) -> Result<Vec<f32>>;
+
+    /// Embeds several messages, returning their embeddings in the same order. Backends whose
+    /// API accepts batches should send them in a single request.
+    async fn get_embeds(&self, messages: &[String]) -> Result<Vec<Vec<f32>>> {
+        let mut embeddings = Vec::with_capacity(messages.len());
+        for message in messages {
+            embeddings.push(self.get_embed(message).await?);
+        }
+        Ok(embeddings)
+    }
+}
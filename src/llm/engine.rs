@@ -1,120 +1,136 @@
-use std::time::Duration;
+use std::sync::Arc;
+
+use async_trait::async_trait;
 
 use super::{
+    backend::{ChatBackend, ChatStream, EmbedBackend, LlmBackendKind},
     error::{Error, Result},
-    model::{AssistantMessage, LlmChat},
+    model::{AssistantMessage, LlmChat, ToolDefinition, ToolMessage},
+    ollama::OllamaBackend,
+    openai::OpenAiBackend,
+};
+
+use crate::{
+    environment::Environment,
+    tools::{ToolContext, ToolRegistry},
 };
-use reqwest::{Client, ClientBuilder};
-use serde::{Deserialize, Serialize};
-use serde_json::json;
 
-use crate::environment::Environment;
+/// Upper bound on model turns spent calling tools before giving up on a final answer.
+const MAX_TOOL_ROUNDS: usize = 5;
 
+/// Front door to the language model, dispatching to the chat and embedding backends selected
+/// by `llm.backend` in the configuration.
 pub struct LlmEngine {
-    base_url: String,
-    model: String,
-    embed_model: String,
-    http_client: Client,
+    chat: Arc<dyn ChatBackend>,
+    embed: Arc<dyn EmbedBackend>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct LlmCompletionResponse {
-    model: String,
-    created_at: String,
-    response: String,
-}
+impl LlmEngine {
+    pub fn new(environment: &Environment) -> Result<LlmEngine> {
+        match environment.llm.backend {
+            LlmBackendKind::Ollama => {
+                let chat = OllamaBackend::new(&environment.llm)?;
+                let embed = OllamaBackend::new(&environment.llm)?;
+                Ok(Self::from_backends(chat, embed))
+            }
+            LlmBackendKind::OpenAi => {
+                let chat = OpenAiBackend::new(&environment.llm)?;
+                let embed = OpenAiBackend::new(&environment.llm)?;
+                Ok(Self::from_backends(chat, embed))
+            }
+        }
+    }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct LlmChatResponse {
-    model: String,
-    created_at: String,
-    message: AssistantMessage,
-}
+    pub fn from_backends(
+        chat: impl ChatBackend + 'static,
+        embed: impl EmbedBackend + 'static,
+    ) -> LlmEngine {
+        LlmEngine {
+            chat: Arc::new(chat),
+            embed: Arc::new(embed),
+        }
+    }
+
+    /// An engine completing with `model` instead, sharing this engine's embedding backend.
+    pub fn with_chat_model(&self, model: &str) -> LlmEngine {
+        LlmEngine {
+            chat: self.chat.with_model(model).into(),
+            embed: self.embed.clone(),
+        }
+    }
+
+    /// Runs the chat with the registry's tools available, executing the tool calls the model
+    /// requests and feeding back their results until it gives a final answer.
+    pub async fn get_chat_completion_with_tools(
+        &self,
+        mut messages: LlmChat,
+        tools: &ToolRegistry,
+        ctx: &ToolContext<'_>,
+    ) -> Result<String> {
+        let definitions = tools.definitions();
+        for _ in 0..MAX_TOOL_ROUNDS {
+            let response = self
+                .get_tool_chat_completion(messages.clone(), &definitions)
+                .await?;
+            let tool_calls = match &response.tool_calls {
+                Some(tool_calls) if !tool_calls.is_empty() => tool_calls.clone(),
+                _ => return Ok(response.content),
+            };
 
-#[derive(Debug, Serialize, Deserialize)]
-struct LlmEmbedResponse {
-    embedding: Vec<f32>,
+            messages.push(response.into());
+            for tool_call in tool_calls {
+                println!("Calling tool {}", tool_call.function.name);
+                messages.push(
+                    ToolMessage {
+                        content: tools.call(&tool_call, ctx).await,
+                        tool_call_id: tool_call.id,
+                    }
+                    .into(),
+                );
+            }
+        }
+        Err(Error::ToolRoundsExceeded(MAX_TOOL_ROUNDS))
+    }
 }
 
-impl LlmEngine {
-    pub fn new(environment: &Environment) -> Result<LlmEngine> {
-        Ok(LlmEngine {
-            model: environment
-                .llm
-                .model
-                .clone()
-                .unwrap_or("llama3".to_string()),
-            embed_model: environment.llm.embed_model.clone(),
-            base_url: environment
-                .llm
-                .base_url
-                .clone()
-                .unwrap_or("http://localhost:11434/api".to_string()),
-            http_client: ClientBuilder::default()
-                .timeout(Duration::from_secs(60))
-                .build()?,
-        })
+#[async_trait]
+impl ChatBackend for LlmEngine {
+    fn model(&self) -> &str {
+        self.chat.model()
     }
 
-    pub async fn get_embed(&self, message: impl ToString) -> Result<Vec<f32>> {
-        let message = message.to_string();
-        let payload = json!({
-            "model": self.embed_model,
-            "prompt": message,
-        });
-
-        self.http_client
-            .post(format!("{}/embeddings", self.base_url))
-            .json(&payload)
-            .send()
-            .await
-            .map_err(|err| Error::HTTPRequestFailed(err.to_string()))?
-            .json::<LlmEmbedResponse>()
-            .await
-            .map_err(|err| Error::HTTPResponseParseFailed(err.to_string()))
-            .map(|res| res.embedding)
+    fn with_model(&self, model: &str) -> Box<dyn ChatBackend> {
+        Box::new(self.with_chat_model(model))
     }
 
-    pub async fn get_completion(&self, question: &str) -> Result<String> {
-        let payload = json!({
-            "model": self.model,
-            "prompt": question,
-            "stream": false,
-            "options": {
-                "seed": 123,
-                "top_k": 20,
-                "top_p": 0.9,
-                "temperature": 0
-            }
-        });
-        self.http_client
-            .post(self.base_url.clone() + "/generate")
-            .json(&payload)
-            .send()
-            .await
-            .map_err(|err| Error::HTTPRequestFailed(err.to_string()))?
-            .json::<LlmCompletionResponse>()
-            .await
-            .map_err(|err| Error::HTTPResponseParseFailed(err.to_string()))
-            .map(|res| res.response)
+    async fn get_completion(&self, question: &str) -> Result<String> {
+        self.chat.get_completion(question).await
+    }
+
+    async fn get_chat_completion(&self, messages: LlmChat) -> Result<String> {
+        self.chat.get_chat_completion(messages).await
+    }
+
+    async fn get_tool_chat_completion(
+        &self,
+        messages: LlmChat,
+        tools: &[ToolDefinition],
+    ) -> Result<AssistantMessage> {
+        self.chat.get_tool_chat_completion(messages, tools).await
+    }
+
+    async fn stream_chat_completion(&self, messages: LlmChat) -> Result<ChatStream> {
+        self.chat.stream_chat_completion(messages).await
+    }
+}
+
+#[async_trait]
+impl EmbedBackend for LlmEngine {
+    async fn get_embed(&self, message: &str) -> Result<Vec<f32>> {
+        self.embed.get_embed(message).await
     }
 
-    pub async fn get_chat_completion(&self, messages: LlmChat) -> Result<String> {
-        let payload = json!({
-            "model": self.model,
-            "messages": messages,
-            "stream": false
-        });
-        println!("{}", payload);
-        self.http_client
-            .post(self.base_url.clone() + "/chat")
-            .json(&payload)
-            .send()
-            .await
-            .map_err(|err| Error::HTTPRequestFailed(err.to_string()))?
-            .json::<LlmChatResponse>()
-            .await
-            .map_err(|err| Error::HTTPResponseParseFailed(err.to_string()))
-            .map(|res| res.message.content)
+    async fn get_embeds(&self, messages: &[String]) -> Result<Vec<Vec<f32>>> {
+        self.embed.get_embeds(messages).await
     }
 }
@@ -4,10 +4,14 @@ pub type Result<T> = std::result::Result<T, Error>;
 pub enum Error {
     #[error("Failed to create HTTP Client, {0}")]
     HTTPClientBuildFailed(#[from] reqwest::Error),
-    #[error("Failed to get http response from ollama, {0}")]
+    #[error("Failed to get http response from llm backend, {0}")]
     HTTPRequestFailed(String),
-    #[error("Failed to parse http response from ollama, {0}")]
+    #[error("Failed to parse http response from llm backend, {0}")]
     HTTPResponseParseFailed(String),
     #[error("Empty response returned from LLM")]
     EmptyResponseError,
+    #[error("Expected {expected} embeddings from llm backend, got {found}")]
+    EmbeddingCountMismatch { expected: usize, found: usize },
+    #[error("Model kept calling tools after {0} rounds without answering")]
+    ToolRoundsExceeded(usize),
 }
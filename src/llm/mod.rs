@@ -1,3 +1,7 @@
+pub mod backend;
 pub mod engine;
 pub mod error;
 pub mod model;
+pub mod ollama;
+pub mod openai;
+pub mod stream;
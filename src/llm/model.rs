@@ -1,21 +1,63 @@
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
-#[derive(Serialize, Debug, Deserialize)]
+#[derive(Serialize, Debug, Deserialize, Clone)]
 pub struct UserMessage {
     pub content: String,
 }
 
-#[derive(Serialize, Debug, Deserialize)]
+#[derive(Serialize, Debug, Deserialize, Clone)]
 pub struct AssistantMessage {
+    #[serde(default)]
     pub content: String,
+    /// Tools the model asked to have called before it gives its answer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
 }
 
-#[derive(Serialize, Debug, Deserialize)]
+#[derive(Serialize, Debug, Deserialize, Clone)]
 pub struct SystemMessage {
     pub content: String,
 }
 
-#[derive(Serialize, Debug, Deserialize)]
+/// The result of a tool call, sent back to the model.
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct ToolMessage {
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct ToolCall {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub function: ToolCallFunction,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct ToolCallFunction {
+    pub name: String,
+    pub arguments: Value,
+}
+
+/// A tool offered to the model, in the function calling format shared by Ollama and OpenAI.
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct ToolDefinition {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub function: FunctionDefinition,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct FunctionDefinition {
+    pub name: String,
+    pub description: String,
+    /// JSON schema of the arguments object.
+    pub parameters: Value,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
 #[serde(tag = "role")]
 pub enum LlmMessage {
     #[serde(rename = "user")]
@@ -24,6 +66,19 @@ pub enum LlmMessage {
     AssistantMessage(AssistantMessage),
     #[serde(rename = "system")]
     SystemMessage(SystemMessage),
+    #[serde(rename = "tool")]
+    ToolMessage(ToolMessage),
+}
+
+impl LlmMessage {
+    pub fn content(&self) -> &str {
+        match self {
+            Self::UserMessage(message) => &message.content,
+            Self::AssistantMessage(message) => &message.content,
+            Self::SystemMessage(message) => &message.content,
+            Self::ToolMessage(message) => &message.content,
+        }
+    }
 }
 
 impl From<AssistantMessage> for LlmMessage {
@@ -32,6 +87,12 @@ impl From<AssistantMessage> for LlmMessage {
     }
 }
 
+impl From<ToolMessage> for LlmMessage {
+    fn from(value: ToolMessage) -> Self {
+        Self::ToolMessage(value)
+    }
+}
+
 impl From<UserMessage> for LlmMessage {
     fn from(value: UserMessage) -> Self {
         Self::UserMessage(value)
@@ -44,4 +105,26 @@ impl From<SystemMessage> for LlmMessage {
     }
 }
 
+impl AssistantMessage {
+    pub fn new(content: impl ToString) -> Self {
+        Self {
+            content: content.to_string(),
+            tool_calls: None,
+        }
+    }
+}
+
+impl ToolDefinition {
+    pub fn function(name: impl ToString, description: impl ToString, parameters: Value) -> Self {
+        Self {
+            kind: "function".to_string(),
+            function: FunctionDefinition {
+                name: name.to_string(),
+                description: description.to_string(),
+                parameters,
+            },
+        }
+    }
+}
+
 pub type LlmChat = Vec<LlmMessage>;
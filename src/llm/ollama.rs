@@ -0,0 +1,225 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::{future, TryStreamExt};
+use reqwest::{Client, ClientBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::{
+    backend::{ChatBackend, ChatStream, EmbedBackend},
+    error::{Error, Result},
+    model::{AssistantMessage, LlmChat, ToolDefinition},
+    stream::response_lines,
+};
+use crate::environment::LlmOptions;
+
+#[derive(Clone)]
+pub struct OllamaBackend {
+    base_url: String,
+    model: String,
+    embed_model: String,
+    http_client: Client,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LlmCompletionResponse {
+    model: String,
+    created_at: String,
+    response: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LlmChatResponse {
+    model: String,
+    created_at: String,
+    message: AssistantMessage,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LlmChatChunk {
+    message: AssistantMessage,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LlmEmbedResponse {
+    embedding: Vec<f32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LlmBatchEmbedResponse {
+    embeddings: Vec<Vec<f32>>,
+}
+
+impl OllamaBackend {
+    pub fn new(options: &LlmOptions) -> Result<OllamaBackend> {
+        Ok(OllamaBackend {
+            model: options.model.clone().unwrap_or("llama3".to_string()),
+            embed_model: options.embed_model.clone(),
+            base_url: options
+                .base_url
+                .clone()
+                .unwrap_or("http://localhost:11434/api".to_string()),
+            http_client: ClientBuilder::default()
+                .timeout(Duration::from_secs(60))
+                .build()?,
+        })
+    }
+}
+
+#[async_trait]
+impl EmbedBackend for OllamaBackend {
+    async fn get_embed(&self, message: &str) -> Result<Vec<f32>> {
+        let payload = json!({
+            "model": self.embed_model,
+            "prompt": message,
+        });
+
+        self.http_client
+            .post(format!("{}/embeddings", self.base_url))
+            .json(&payload)
+            .send()
+            .await
+            .map_err(|err| Error::HTTPRequestFailed(err.to_string()))?
+            .json::<LlmEmbedResponse>()
+            .await
+            .map_err(|err| Error::HTTPResponseParseFailed(err.to_string()))
+            .map(|res| res.embedding)
+    }
+
+    async fn get_embeds(&self, messages: &[String]) -> Result<Vec<Vec<f32>>> {
+        if messages.is_empty() {
+            return Ok(vec![]);
+        }
+        let payload = json!({
+            "model": self.embed_model,
+            "input": messages,
+        });
+
+        let embeddings = self
+            .http_client
+            .post(format!("{}/embed", self.base_url))
+            .json(&payload)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|err| Error::HTTPRequestFailed(err.to_string()))?
+            .json::<LlmBatchEmbedResponse>()
+            .await
+            .map_err(|err| Error::HTTPResponseParseFailed(err.to_string()))?
+            .embeddings;
+        if embeddings.len() != messages.len() {
+            return Err(Error::EmbeddingCountMismatch {
+                expected: messages.len(),
+                found: embeddings.len(),
+            });
+        }
+        Ok(embeddings)
+    }
+}
+
+#[async_trait]
+impl ChatBackend for OllamaBackend {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn with_model(&self, model: &str) -> Box<dyn ChatBackend> {
+        Box::new(OllamaBackend {
+            model: model.to_string(),
+            ..self.clone()
+        })
+    }
+
+    async fn get_completion(&self, question: &str) -> Result<String> {
+        let payload = json!({
+            "model": self.model,
+            "prompt": question,
+            "stream": false,
+            "options": {
+                "seed": 123,
+                "top_k": 20,
+                "top_p": 0.9,
+                "temperature": 0
+            }
+        });
+        self.http_client
+            .post(self.base_url.clone() + "/generate")
+            .json(&payload)
+            .send()
+            .await
+            .map_err(|err| Error::HTTPRequestFailed(err.to_string()))?
+            .json::<LlmCompletionResponse>()
+            .await
+            .map_err(|err| Error::HTTPResponseParseFailed(err.to_string()))
+            .map(|res| res.response)
+    }
+
+    async fn get_chat_completion(&self, messages: LlmChat) -> Result<String> {
+        let payload = json!({
+            "model": self.model,
+            "messages": messages,
+            "stream": false
+        });
+        println!("{}", payload);
+        self.http_client
+            .post(self.base_url.clone() + "/chat")
+            .json(&payload)
+            .send()
+            .await
+            .map_err(|err| Error::HTTPRequestFailed(err.to_string()))?
+            .json::<LlmChatResponse>()
+            .await
+            .map_err(|err| Error::HTTPResponseParseFailed(err.to_string()))
+            .map(|res| res.message.content)
+    }
+
+    async fn get_tool_chat_completion(
+        &self,
+        messages: LlmChat,
+        tools: &[ToolDefinition],
+    ) -> Result<AssistantMessage> {
+        let payload = json!({
+            "model": self.model,
+            "messages": messages,
+            "tools": tools,
+            "stream": false
+        });
+        self.http_client
+            .post(self.base_url.clone() + "/chat")
+            .json(&payload)
+            .send()
+            .await
+            .map_err(|err| Error::HTTPRequestFailed(err.to_string()))?
+            .json::<LlmChatResponse>()
+            .await
+            .map_err(|err| Error::HTTPResponseParseFailed(err.to_string()))
+            .map(|res| res.message)
+    }
+
+    async fn stream_chat_completion(&self, messages: LlmChat) -> Result<ChatStream> {
+        let payload = json!({
+            "model": self.model,
+            "messages": messages,
+            "stream": true
+        });
+        let response = self
+            .http_client
+            .post(self.base_url.clone() + "/chat")
+            .json(&payload)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|err| Error::HTTPRequestFailed(err.to_string()))?;
+
+        Ok(Box::pin(
+            response_lines(response)
+                .try_filter(|line| future::ready(!line.is_empty()))
+                .and_then(|line| async move {
+                    serde_json::from_str::<LlmChatChunk>(&line)
+                        .map(|chunk| chunk.message.content)
+                        .map_err(|err| Error::HTTPResponseParseFailed(err.to_string()))
+                }),
+        ))
+    }
+}
@@ -0,0 +1,645 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::{future, TryStreamExt};
+use reqwest::{Client, ClientBuilder, RequestBuilder, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::{
+    backend::{ChatBackend, ChatStream, EmbedBackend},
+    error::{Error, Result},
+    model::{
+        AssistantMessage, LlmChat, LlmMessage, ToolCall, ToolCallFunction, ToolDefinition,
+        UserMessage,
+    },
+    stream::response_lines,
+};
+use crate::environment::{LlmOptions, Secret};
+
+/// Backend for servers exposing the OpenAI `/v1/chat/completions` and `/v1/embeddings` wire
+/// format, such as vLLM, llama.cpp server and LocalAI.
+#[derive(Clone)]
+pub struct OpenAiBackend {
+    base_url: String,
+    model: String,
+    embed_model: String,
+    api_key: Option<Secret>,
+    http_client: Client,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OpenAiChatResponse {
+    model: String,
+    choices: Vec<OpenAiChoice>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OpenAiChoice {
+    message: OpenAiMessage,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OpenAiMessage {
+    content: Option<String>,
+    tool_calls: Option<Vec<OpenAiToolCall>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OpenAiToolCall {
+    id: String,
+    #[serde(rename = "type")]
+    kind: String,
+    function: OpenAiFunctionCall,
+}
+
+/// OpenAI encodes the call arguments as a JSON string rather than an object.
+#[derive(Debug, Serialize, Deserialize)]
+struct OpenAiFunctionCall {
+    name: String,
+    arguments: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OpenAiChatChunk {
+    choices: Vec<OpenAiChunkChoice>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OpenAiChunkChoice {
+    delta: OpenAiDelta,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OpenAiDelta {
+    content: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OpenAiEmbedResponse {
+    data: Vec<OpenAiEmbedding>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OpenAiEmbedding {
+    embedding: Vec<f32>,
+    #[serde(default)]
+    index: usize,
+}
+
+impl From<OpenAiMessage> for AssistantMessage {
+    fn from(value: OpenAiMessage) -> Self {
+        Self {
+            content: value.content.unwrap_or_default(),
+            tool_calls: value.tool_calls.map(|tool_calls| {
+                tool_calls
+                    .into_iter()
+                    .map(|call| ToolCall {
+                        id: Some(call.id),
+                        function: ToolCallFunction {
+                            arguments: serde_json::from_str(&call.function.arguments)
+                                .unwrap_or(Value::String(call.function.arguments)),
+                            name: call.function.name,
+                        },
+                    })
+                    .collect()
+            }),
+        }
+    }
+}
+
+impl From<ToolCall> for OpenAiToolCall {
+    fn from(value: ToolCall) -> Self {
+        Self {
+            id: value.id.unwrap_or_default(),
+            kind: "function".to_string(),
+            function: OpenAiFunctionCall {
+                name: value.function.name,
+                arguments: value.function.arguments.to_string(),
+            },
+        }
+    }
+}
+
+impl OpenAiBackend {
+    pub fn new(options: &LlmOptions) -> Result<OpenAiBackend> {
+        Ok(OpenAiBackend {
+            model: options.model.clone().unwrap_or("gpt-4o-mini".to_string()),
+            embed_model: options.embed_model.clone(),
+            base_url: options
+                .base_url
+                .clone()
+                .unwrap_or("http://localhost:8000/v1".to_string()),
+            api_key: options.api_key.clone(),
+            http_client: ClientBuilder::default()
+                .timeout(Duration::from_secs(60))
+                .build()?,
+        })
+    }
+
+    fn post(&self, endpoint: &str) -> RequestBuilder {
+        let request = self
+            .http_client
+            .post(format!("{}/{}", self.base_url, endpoint));
+        match &self.api_key {
+            Some(api_key) => request.bearer_auth(api_key.expose()),
+            None => request,
+        }
+    }
+}
+
+#[async_trait]
+impl EmbedBackend for OpenAiBackend {
+    async fn get_embed(&self, message: &str) -> Result<Vec<f32>> {
+        let payload = json!({
+            "model": self.embed_model,
+            "input": message,
+        });
+
+        send(self.post("embeddings").json(&payload))
+            .await?
+            .json::<OpenAiEmbedResponse>()
+            .await
+            .map_err(|err| Error::HTTPResponseParseFailed(err.to_string()))?
+            .data
+            .into_iter()
+            .next()
+            .map(|data| data.embedding)
+            .ok_or(Error::EmptyResponseError)
+    }
+
+    async fn get_embeds(&self, messages: &[String]) -> Result<Vec<Vec<f32>>> {
+        if messages.is_empty() {
+            return Ok(vec![]);
+        }
+        let payload = json!({
+            "model": self.embed_model,
+            "input": messages,
+        });
+
+        let mut data = send(self.post("embeddings").json(&payload))
+            .await?
+            .json::<OpenAiEmbedResponse>()
+            .await
+            .map_err(|err| Error::HTTPResponseParseFailed(err.to_string()))?
+            .data;
+        if data.len() != messages.len() {
+            return Err(Error::EmbeddingCountMismatch {
+                expected: messages.len(),
+                found: data.len(),
+            });
+        }
+        data.sort_by_key(|embedding| embedding.index);
+        Ok(data.into_iter().map(|data| data.embedding).collect())
+    }
+}
+
+#[async_trait]
+impl ChatBackend for OpenAiBackend {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn with_model(&self, model: &str) -> Box<dyn ChatBackend> {
+        Box::new(OpenAiBackend {
+            model: model.to_string(),
+            ..self.clone()
+        })
+    }
+
+    async fn get_completion(&self, question: &str) -> Result<String> {
+        self.get_chat_completion(vec![UserMessage {
+            content: question.to_string(),
+        }
+        .into()])
+            .await
+    }
+
+    async fn get_chat_completion(&self, messages: LlmChat) -> Result<String> {
+        let payload = json!({
+            "model": self.model,
+            "messages": to_openai_messages(messages),
+            "stream": false
+        });
+        send(self.post("chat/completions").json(&payload))
+            .await?
+            .json::<OpenAiChatResponse>()
+            .await
+            .map_err(|err| Error::HTTPResponseParseFailed(err.to_string()))?
+            .choices
+            .into_iter()
+            .next()
+            .map(|choice| choice.message.content.unwrap_or_default())
+            .ok_or(Error::EmptyResponseError)
+    }
+
+    async fn get_tool_chat_completion(
+        &self,
+        messages: LlmChat,
+        tools: &[ToolDefinition],
+    ) -> Result<AssistantMessage> {
+        let payload = json!({
+            "model": self.model,
+            "messages": to_openai_messages(messages),
+            "tools": tools,
+            "stream": false
+        });
+        send(self.post("chat/completions").json(&payload))
+            .await?
+            .json::<OpenAiChatResponse>()
+            .await
+            .map_err(|err| Error::HTTPResponseParseFailed(err.to_string()))?
+            .choices
+            .into_iter()
+            .next()
+            .map(|choice| choice.message.into())
+            .ok_or(Error::EmptyResponseError)
+    }
+
+    async fn stream_chat_completion(&self, messages: LlmChat) -> Result<ChatStream> {
+        let payload = json!({
+            "model": self.model,
+            "messages": to_openai_messages(messages),
+            "stream": true
+        });
+        let response = send(self.post("chat/completions").json(&payload)).await?;
+
+        // Server sent events, one `data: {chunk}` line per token and `data: [DONE]` at the end.
+        // Comments, keep-alives and anything sent after `[DONE]` are skipped.
+        let events = response_lines(response).try_take_while(|line| {
+            let done = line.strip_prefix("data:").map(str::trim) == Some("[DONE]");
+            future::ok(!done)
+        });
+        Ok(Box::pin(events.try_filter_map(|line| async move {
+            let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+                return Ok(None);
+            };
+            serde_json::from_str::<OpenAiChatChunk>(data)
+                .map(|chunk| {
+                    chunk
+                        .choices
+                        .into_iter()
+                        .next()
+                        .and_then(|choice| choice.delta.content)
+                })
+                .map_err(|err| Error::HTTPResponseParseFailed(err.to_string()))
+        })))
+    }
+}
+
+/// Sends the request, turning an error status into an error carrying the server's explanation.
+/// OpenAI compatible servers explain errors as `{"error": {"message": ...}}`.
+async fn send(request: RequestBuilder) -> Result<Response> {
+    let response = request
+        .send()
+        .await
+        .map_err(|err| Error::HTTPRequestFailed(err.to_string()))?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    let message = serde_json::from_str::<Value>(&body)
+        .ok()
+        .and_then(|body| body["error"]["message"].as_str().map(str::to_string))
+        .unwrap_or(body);
+    Err(Error::HTTPRequestFailed(format!(
+        "{status}, {}",
+        message.trim()
+    )))
+}
+
+fn to_openai_messages(messages: LlmChat) -> Vec<Value> {
+    messages
+        .into_iter()
+        .map(|message| match message {
+            LlmMessage::AssistantMessage(AssistantMessage {
+                content,
+                tool_calls: Some(tool_calls),
+            }) => json!({
+                "role": "assistant",
+                "content": content,
+                "tool_calls": tool_calls
+                    .into_iter()
+                    .map(OpenAiToolCall::from)
+                    .collect::<Vec<_>>(),
+            }),
+            message => json!(message),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        task::JoinHandle,
+    };
+
+    use super::*;
+    use crate::llm::model::SystemMessage;
+
+    /// Answers a single request on a local port with `status` and `body`, handing back the raw
+    /// request it received.
+    async fn stub_server(
+        status: &'static str,
+        content_type: &'static str,
+        body: &'static str,
+    ) -> (String, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}/v1", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 4096];
+            // Read the headers, then as much body as they announce.
+            let body_start = loop {
+                let read = socket.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..read]);
+                if let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+                    break end + 4;
+                }
+            };
+            let headers = String::from_utf8_lossy(&request[..body_start]).to_lowercase();
+            let content_length = headers
+                .lines()
+                .find_map(|line| line.strip_prefix("content-length:"))
+                .map_or(0, |length| length.trim().parse().unwrap());
+            while request.len() < body_start + content_length {
+                let read = socket.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..read]);
+            }
+            let response = format!(
+                "HTTP/1.1 {status}\r\ncontent-type: {content_type}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+            socket.shutdown().await.unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        (base_url, server)
+    }
+
+    fn backend(base_url: &str, api_key: Option<&str>) -> OpenAiBackend {
+        let options: LlmOptions = serde_json::from_value(json!({
+            "backend": "openai",
+            "model": "test-model",
+            "base_url": base_url,
+            "api_key": api_key,
+            "system_prompt": "",
+            "embed_model": "test-embed",
+            "context_window": 4096,
+        }))
+        .unwrap();
+        OpenAiBackend::new(&options).unwrap()
+    }
+
+    fn request_body(request: &str) -> Value {
+        let (_, body) = request.split_once("\r\n\r\n").unwrap();
+        serde_json::from_str(body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn chat_completion_sends_bearer_token_and_reads_first_choice() {
+        let (base_url, server) = stub_server(
+            "200 OK",
+            "application/json",
+            r#"{"model":"test-model","choices":[{"message":{"role":"assistant","content":"Hello there"}},{"message":{"role":"assistant","content":"Ignored"}}]}"#,
+        )
+        .await;
+
+        let completion = backend(&base_url, Some("secret-key"))
+            .get_chat_completion(vec![
+                SystemMessage {
+                    content: "Be brief".to_string(),
+                }
+                .into(),
+                UserMessage {
+                    content: "Hi".to_string(),
+                }
+                .into(),
+            ])
+            .await
+            .unwrap();
+
+        assert_eq!(completion, "Hello there");
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /v1/chat/completions HTTP/1.1\r\n"));
+        assert!(request
+            .to_lowercase()
+            .contains("authorization: bearer secret-key\r\n"));
+        let body = request_body(&request);
+        assert_eq!(body["model"], "test-model");
+        assert_eq!(body["stream"], false);
+        assert_eq!(
+            body["messages"],
+            json!([
+                {"role": "system", "content": "Be brief"},
+                {"role": "user", "content": "Hi"},
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn requests_without_api_key_send_no_authorization() {
+        let (base_url, server) = stub_server(
+            "200 OK",
+            "application/json",
+            r#"{"model":"test-model","choices":[{"message":{"content":"Hi"}}]}"#,
+        )
+        .await;
+
+        backend(&base_url, None).get_completion("Hi").await.unwrap();
+
+        let request = server.await.unwrap();
+        assert!(!request.to_lowercase().contains("authorization:"));
+    }
+
+    #[tokio::test]
+    async fn tool_call_arguments_are_parsed_from_json_string() {
+        let (base_url, server) = stub_server(
+            "200 OK",
+            "application/json",
+            r#"{"model":"test-model","choices":[{"message":{"role":"assistant","content":null,"tool_calls":[{"id":"call_1","type":"function","function":{"name":"search_memory","arguments":"{\"query\":\"rust\",\"limit\":3}"}},{"id":"call_2","type":"function","function":{"name":"current_time","arguments":"not json"}}]}}]}"#,
+        )
+        .await;
+        let tools = [ToolDefinition::function(
+            "search_memory",
+            "Searches memory",
+            json!({"type": "object"}),
+        )];
+
+        let message = backend(&base_url, None)
+            .get_tool_chat_completion(
+                vec![UserMessage {
+                    content: "Find rust".to_string(),
+                }
+                .into()],
+                &tools,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(message.content, "");
+        let calls = message.tool_calls.unwrap();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].id.as_deref(), Some("call_1"));
+        assert_eq!(calls[0].function.name, "search_memory");
+        assert_eq!(
+            calls[0].function.arguments,
+            json!({"query": "rust", "limit": 3})
+        );
+        // Arguments that are not valid JSON are passed on as the raw string.
+        assert_eq!(calls[1].function.arguments, json!("not json"));
+        let body = request_body(&server.await.unwrap());
+        assert_eq!(body["tools"][0]["function"]["name"], "search_memory");
+    }
+
+    #[test]
+    fn tool_calls_are_sent_back_with_string_arguments() {
+        let messages = to_openai_messages(vec![AssistantMessage {
+            content: String::new(),
+            tool_calls: Some(vec![ToolCall {
+                id: Some("call_1".to_string()),
+                function: ToolCallFunction {
+                    name: "search_memory".to_string(),
+                    arguments: json!({"query": "rust"}),
+                },
+            }]),
+        }
+        .into()]);
+
+        assert_eq!(
+            messages[0]["tool_calls"][0],
+            json!({
+                "id": "call_1",
+                "type": "function",
+                "function": {"name": "search_memory", "arguments": "{\"query\":\"rust\"}"},
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn stream_yields_deltas_until_done() {
+        let (base_url, server) = stub_server(
+            "200 OK",
+            "text/event-stream",
+            concat!(
+                ": keep-alive\n\n",
+                "data: {\"choices\":[{\"delta\":{\"role\":\"assistant\"}}]}\n\n",
+                "data: {\"choices\":[{\"delta\":{\"content\":\"Hel\"}}]}\n\n",
+                "data: {\"choices\":[{\"delta\":{\"content\":\"lo\"}}]}\n\n",
+                "data: [DONE]\n\n",
+                "data: {\"choices\":[{\"delta\":{\"content\":\" after done\"}}]}\n\n",
+            ),
+        )
+        .await;
+
+        let pieces: Vec<String> = backend(&base_url, None)
+            .stream_chat_completion(vec![UserMessage {
+                content: "Hi".to_string(),
+            }
+            .into()])
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+
+        assert_eq!(pieces, ["Hel", "lo"]);
+        assert_eq!(request_body(&server.await.unwrap())["stream"], true);
+    }
+
+    #[tokio::test]
+    async fn stream_reports_malformed_events() {
+        let (base_url, _server) =
+            stub_server("200 OK", "text/event-stream", "data: {not json}\n\n").await;
+
+        let result: Result<Vec<String>> = backend(&base_url, None)
+            .stream_chat_completion(vec![])
+            .await
+            .unwrap()
+            .try_collect()
+            .await;
+
+        assert!(matches!(result, Err(Error::HTTPResponseParseFailed(_))));
+    }
+
+    #[tokio::test]
+    async fn error_status_reports_the_servers_message() {
+        let (base_url, _server) = stub_server(
+            "401 Unauthorized",
+            "application/json",
+            r#"{"error":{"message":"Incorrect API key provided","type":"invalid_request_error"}}"#,
+        )
+        .await;
+
+        let err = backend(&base_url, Some("wrong"))
+            .get_completion("Hi")
+            .await
+            .unwrap_err();
+
+        let Error::HTTPRequestFailed(message) = err else {
+            panic!("expected a request error, got {err:?}");
+        };
+        assert!(message.contains("401"), "{message}");
+        assert!(message.contains("Incorrect API key provided"), "{message}");
+    }
+
+    #[tokio::test]
+    async fn error_status_without_json_reports_the_body() {
+        let (base_url, _server) =
+            stub_server("502 Bad Gateway", "text/plain", "upstream unavailable").await;
+
+        let err = backend(&base_url, None).get_embed("Hi").await.unwrap_err();
+
+        assert!(err.to_string().contains("upstream unavailable"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn embeddings_are_returned_in_input_order() {
+        let (base_url, server) = stub_server(
+            "200 OK",
+            "application/json",
+            r#"{"object":"list","data":[{"embedding":[2.0],"index":1},{"embedding":[1.0],"index":0}]}"#,
+        )
+        .await;
+
+        let embeddings = backend(&base_url, None)
+            .get_embeds(&["first".to_string(), "second".to_string()])
+            .await
+            .unwrap();
+
+        assert_eq!(embeddings, [vec![1.0], vec![2.0]]);
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /v1/embeddings HTTP/1.1\r\n"));
+        let body = request_body(&request);
+        assert_eq!(body["model"], "test-embed");
+        assert_eq!(body["input"], json!(["first", "second"]));
+    }
+
+    #[tokio::test]
+    async fn missing_embeddings_are_an_error() {
+        let (base_url, _server) = stub_server(
+            "200 OK",
+            "application/json",
+            r#"{"data":[{"embedding":[1.0],"index":0}]}"#,
+        )
+        .await;
+
+        let err = backend(&base_url, None)
+            .get_embeds(&["first".to_string(), "second".to_string()])
+            .await
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            Error::EmbeddingCountMismatch {
+                expected: 2,
+                found: 1
+            }
+        ));
+    }
+}
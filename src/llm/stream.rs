@@ -0,0 +1,35 @@
+use futures::{stream, Stream, StreamExt};
+
+use super::error::{Error, Result};
+
+/// Splits a streamed HTTP response body into its lines, as used by both Ollama's NDJSON stream
+/// and server sent events.
+pub fn response_lines(response: reqwest::Response) -> impl Stream<Item = Result<String>> + Send {
+    let bytes = Box::pin(response.bytes_stream());
+    stream::try_unfold(
+        (bytes, Vec::<u8>::new(), false),
+        |(mut bytes, mut buffer, mut finished)| async move {
+            loop {
+                if let Some(position) = buffer.iter().position(|byte| *byte == b'\n') {
+                    let line: Vec<u8> = buffer.drain(..=position).collect();
+                    let line = String::from_utf8_lossy(&line).trim().to_string();
+                    return Ok(Some((line, (bytes, buffer, finished))));
+                }
+                if finished {
+                    if buffer.is_empty() {
+                        return Ok(None);
+                    }
+                    let line = String::from_utf8_lossy(&buffer).trim().to_string();
+                    buffer.clear();
+                    return Ok(Some((line, (bytes, buffer, finished))));
+                }
+                match bytes.next().await {
+                    Some(chunk) => buffer.extend_from_slice(
+                        &chunk.map_err(|err| Error::HTTPRequestFailed(err.to_string()))?,
+                    ),
+                    None => finished = true,
+                }
+            }
+        },
+    )
+}
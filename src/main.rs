@@ -1,44 +1,64 @@
-use chattyrs::commands::get_commands;
+use chattyrs::commands::sync::sync_commands;
+use chattyrs::database::Database;
 use chattyrs::environment::{get_environment, Environment};
 use chattyrs::handler::Handler;
+use chattyrs::llm::engine::LlmEngine;
+use chattyrs::reload::{watch_config, Services};
 use chattyrs::vec_db::db_handler::VdbHandler;
-use serenity::all::ApplicationId;
 use serenity::http;
 use serenity::prelude::*;
+use std::sync::Arc;
 
-async fn setup_slash_commands(environment: &Environment) -> http::Http {
-    let http_serenity = http::Http::new(&environment.discord_token);
-    http_serenity.set_application_id(ApplicationId::new(1256701007249936568));
-    serenity::model::application::Command::set_global_commands(
-        &http_serenity,
-        get_commands(environment),
-    )
-    .await
-    .expect("Failed to set global commands");
-    http_serenity
+async fn setup_slash_commands(environment: &Environment) {
+    let http_serenity = http::Http::new(environment.discord_token.expose());
+    sync_commands(&http_serenity, environment)
+        .await
+        .expect("Failed to register slash commands");
 }
 
 #[tokio::main]
 async fn main() {
+    // `--check-config` validates the configuration and exits without connecting.
+    let check_config = std::env::args().skip(1).any(|arg| arg == "--check-config");
     // Configure the client with your Discord bot token in the environment.
-    let environment = get_environment().unwrap();
+    let environment = match get_environment() {
+        Ok(environment) => environment,
+        Err(err) => {
+            println!("{err}");
+            std::process::exit(1);
+        }
+    };
     println!("Loaded environment {environment:?}");
+    if check_config {
+        println!("Configuration is valid");
+        return;
+    }
     // Set gateway intents, which decides what events the bot will be notified about
-    let intents = GatewayIntents::GUILD_MESSAGES
+    let intents = GatewayIntents::GUILDS
+        | GatewayIntents::GUILD_MESSAGES
         | GatewayIntents::DIRECT_MESSAGES
         | GatewayIntents::MESSAGE_CONTENT;
 
-    let http = setup_slash_commands(&environment).await;
-    let vec_db_client = VdbHandler::new(&environment)
+    setup_slash_commands(&environment).await;
+    let llm_engine = LlmEngine::new(&environment).expect("Failed to create llm engine");
+    let vec_db_client = VdbHandler::new(&environment, &llm_engine)
         .await
         .expect("Failed to initialise vector database client");
+    let database = Database::open(&environment.database.path).expect("Failed to open database");
+    let services = Services::new(environment.clone(), llm_engine, vec_db_client)
+        .expect("Failed to create services");
+    let handler = Arc::new(
+        Handler::new(services, database)
+            .await
+            .expect("Failed to create handler"),
+    );
+    // Edits to the configuration files are applied without a restart.
+    tokio::spawn(watch_config(handler.clone()));
 
     // Create a new instance of the Client, logging in as a bot. This will automatically prepend
     // your bot token with "Bot ", which is a requirement by Discord for bot users.
-    let mut client = Client::builder(&environment.discord_token, intents)
-        .event_handler(
-            Handler::new(&environment, http, vec_db_client).expect("Failed to create handler"),
-        )
+    let mut client = Client::builder(environment.discord_token.expose(), intents)
+        .event_handler_arc(handler)
         .await
         .expect("Err creating client");
 
@@ -0,0 +1,737 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use serenity::all::{Cache, ChannelId, Context, GuildId, Message, MessageId, UserId};
+use tokio::sync::Mutex;
+
+use crate::{
+    database::{self, conversations::ConversationTurn, Database},
+    environment::MemoryOptions,
+    llm::{
+        self,
+        backend::ChatBackend,
+        model::{AssistantMessage, LlmChat, LlmMessage, SystemMessage, UserMessage},
+    },
+    privacy::OptOutStore,
+};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Rough number of characters per token, used to size prompts without running a tokenizer.
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Evicted turns are summarised once they add up to this fraction of the token budget, so the
+/// summary is not regenerated for every new message.
+const SUMMARY_BATCH_DIVISOR: usize = 4;
+
+const SUMMARY_PROMPT: &str = "You maintain a running summary of a Discord conversation. Merge the previous summary with the new messages into a single short summary of a few sentences. Keep who said what, decisions and open questions. Reply with the summary only.";
+
+/// Rolling per-channel conversation history, kept as chat turns and trimmed to a token budget.
+/// The turns and running summaries are saved to the database, so histories carry over a
+/// restart. Messages from users who opted out are never remembered.
+pub struct ConversationMemory {
+    channels: Mutex<HashMap<ChannelId, ChannelHistory>>,
+    database: Database,
+    opt_outs: Arc<OptOutStore>,
+    token_budget: AtomicUsize,
+    summarize_evicted: AtomicBool,
+}
+
+#[derive(Default)]
+struct ChannelHistory {
+    /// The channel's guild once known, `None` for direct messages.
+    guild_id: Option<GuildId>,
+    turns: VecDeque<MemoryTurn>,
+    /// Whether recent messages have been fetched from Discord for this channel.
+    seeded: bool,
+    summary: Option<String>,
+    /// Turns trimmed from the history that are not yet part of the summary.
+    evicted: Vec<MemoryTurn>,
+}
+
+struct MemoryTurn {
+    message_id: MessageId,
+    author_id: UserId,
+    message: LlmMessage,
+    tokens: usize,
+}
+
+impl ConversationMemory {
+    pub fn new(options: &MemoryOptions, database: Database, opt_outs: Arc<OptOutStore>) -> Self {
+        Self {
+            channels: Mutex::new(HashMap::new()),
+            database,
+            opt_outs,
+            token_budget: AtomicUsize::new(options.token_budget),
+            summarize_evicted: AtomicBool::new(options.summarize_evicted),
+        }
+    }
+
+    /// Applies reloaded options. Histories are trimmed to a new budget as messages arrive.
+    pub fn reconfigure(&self, options: &MemoryOptions) {
+        self.token_budget
+            .store(options.token_budget, Ordering::Relaxed);
+        self.summarize_evicted
+            .store(options.summarize_evicted, Ordering::Relaxed);
+    }
+
+    /// Adds a message to its channel's history, replacing the stored turn if the message was
+    /// already recorded, e.g. after an edit.
+    pub async fn record(
+        &self,
+        message: &Message,
+        ctx: &Context,
+        llm_engine: &dyn ChatBackend,
+    ) -> Result<()> {
+        if !self.remembers(message) {
+            return Ok(());
+        }
+        let turn = MemoryTurn::new(
+            message.id,
+            message.author.id,
+            to_llm_message(message, ctx.cache.current_user().id, &ctx.cache),
+        );
+
+        let saved = turn.saved();
+
+        let (guild_id, trimmed_to, summary_due) = {
+            let mut channels = self.channels.lock().await;
+            let history = channels.entry(message.channel_id).or_default();
+            history.guild_id = history.guild_id.or(message.guild_id);
+            history.insert(turn);
+            let token_budget = self.token_budget.load(Ordering::Relaxed);
+            let trimmed =
+                history.trim(token_budget, self.summarize_evicted.load(Ordering::Relaxed));
+            (
+                history.guild_id,
+                history.oldest().filter(|_| trimmed),
+                history.evicted_tokens() >= token_budget / SUMMARY_BATCH_DIVISOR,
+            )
+        };
+
+        self.database
+            .save_conversation_turn(guild_id, message.channel_id, saved)
+            .await?;
+        if let Some(oldest) = trimmed_to {
+            self.database
+                .delete_conversation_turns_before(message.channel_id, oldest)
+                .await?;
+        }
+
+        if summary_due {
+            self.summarize(message.channel_id, llm_engine).await?;
+        }
+        Ok(())
+    }
+
+    /// Drops deleted messages from their channel's history.
+    pub async fn forget(&self, channel_id: ChannelId, message_ids: &[MessageId]) {
+        {
+            let mut channels = self.channels.lock().await;
+            if let Some(history) = channels.get_mut(&channel_id) {
+                history
+                    .turns
+                    .retain(|turn| !message_ids.contains(&turn.message_id));
+                history
+                    .evicted
+                    .retain(|turn| !message_ids.contains(&turn.message_id));
+            }
+        }
+        if let Err(err) = self
+            .database
+            .delete_conversation_turns(message_ids.to_vec())
+            .await
+        {
+            println!(
+                "Failed to delete saved turns of channel {}, {}",
+                channel_id, err
+            );
+        }
+    }
+
+    /// Drops the user's messages from every history, once they opted out or asked to be
+    /// forgotten. Running summaries cannot be split by author and are left as they are.
+    pub async fn forget_author(&self, user_id: UserId) {
+        {
+            let mut channels = self.channels.lock().await;
+            for history in channels.values_mut() {
+                history.turns.retain(|turn| turn.author_id != user_id);
+                history.evicted.retain(|turn| turn.author_id != user_id);
+            }
+        }
+        if let Err(err) = self
+            .database
+            .delete_author_conversation_turns(user_id)
+            .await
+        {
+            println!("Failed to delete saved turns of user {}, {}", user_id, err);
+        }
+    }
+
+    /// Drops everything remembered of a deleted channel, including its saved turns and summary.
+    pub async fn forget_channel(&self, channel_id: ChannelId) {
+        self.channels.lock().await.remove(&channel_id);
+        if let Err(err) = self.database.delete_conversation(channel_id).await {
+            println!(
+                "Failed to delete saved conversation of channel {}, {}",
+                channel_id, err
+            );
+        }
+    }
+
+    /// Drops everything remembered of the guild's channels once the bot leaves it, including
+    /// their saved turns and summaries.
+    pub async fn forget_guild(&self, guild_id: GuildId) {
+        self.channels
+            .lock()
+            .await
+            .retain(|_, history| history.guild_id != Some(guild_id));
+        if let Err(err) = self.database.delete_guild_conversations(guild_id).await {
+            println!(
+                "Failed to delete saved conversations of guild {}, {}",
+                guild_id, err
+            );
+        }
+    }
+
+    /// The channel's latest `max_messages` turns, preceded by the running summary if there is
+    /// one. The first time a channel is used, its saved turns are reloaded and recent messages
+    /// fetched from Discord.
+    pub async fn history(
+        &self,
+        guild_id: Option<GuildId>,
+        channel_id: ChannelId,
+        ctx: &Context,
+        max_messages: usize,
+    ) -> Result<LlmChat> {
+        let seeded = self
+            .channels
+            .lock()
+            .await
+            .get(&channel_id)
+            .is_some_and(|history| history.seeded);
+        if !seeded {
+            self.seed(guild_id, channel_id, ctx, max_messages).await?;
+        }
+
+        let channels = self.channels.lock().await;
+        Ok(channels
+            .get(&channel_id)
+            .map(|history| history.to_chat(max_messages))
+            .unwrap_or_default())
+    }
+
+    async fn seed(
+        &self,
+        guild_id: Option<GuildId>,
+        channel_id: ChannelId,
+        ctx: &Context,
+        count: usize,
+    ) -> Result<()> {
+        let latest_messages = ctx
+            .http
+            .get_messages(
+                channel_id,
+                None,
+                Some(
+                    count
+                        .try_into()
+                        .expect("Max memory count could not be parsed into u8"),
+                ),
+            )
+            .await?;
+        let saved_summary = self.database.conversation_summary(channel_id).await?;
+        let saved_turns = self.database.conversation_turns(channel_id).await?;
+        let bot_id = ctx.cache.current_user().id;
+        // A saved turn within the span of the latest messages that Discord no longer returns was
+        // deleted while the bot was away.
+        let fetched: HashSet<MessageId> =
+            latest_messages.iter().map(|message| message.id).collect();
+        let fetched_since = fetched
+            .iter()
+            .min()
+            .copied()
+            .filter(|_| fetched.len() >= count);
+        let deleted = |message_id: MessageId| {
+            !fetched.contains(&message_id)
+                && fetched_since.is_none_or(|oldest| message_id >= oldest)
+        };
+
+        let mut channels = self.channels.lock().await;
+        let history = channels.entry(channel_id).or_default();
+        history.guild_id = history.guild_id.or(guild_id);
+        if history.summary.is_none() {
+            history.summary = saved_summary;
+        }
+        for message in latest_messages
+            .iter()
+            .filter(|message| self.remembers(message))
+        {
+            if !history.contains(message.id) {
+                history.insert(MemoryTurn::new(
+                    message.id,
+                    message.author.id,
+                    to_llm_message(message, bot_id, &ctx.cache),
+                ));
+            }
+        }
+        // Discord has the latest version of the messages it returned.
+        for turn in saved_turns {
+            if !deleted(turn.message_id)
+                && !self.opt_outs.is_opted_out(turn.author_id)
+                && !history.contains(turn.message_id)
+            {
+                history.insert(MemoryTurn::from_saved(turn));
+            }
+        }
+        // Messages from before the bot was watching the channel are not worth a summary.
+        let trimmed = history.trim(self.token_budget.load(Ordering::Relaxed), false);
+        history.seeded = true;
+        let trimmed_to = history.oldest().filter(|_| trimmed);
+        drop(channels);
+
+        if let Some(oldest) = trimmed_to {
+            self.database
+                .delete_conversation_turns_before(channel_id, oldest)
+                .await?;
+        }
+        Ok(())
+    }
+
+    fn remembers(&self, message: &Message) -> bool {
+        !message.content.trim().is_empty() && !self.opt_outs.is_opted_out(message.author.id)
+    }
+
+    /// Folds the evicted turns of a channel into its running summary.
+    async fn summarize(&self, channel_id: ChannelId, llm_engine: &dyn ChatBackend) -> Result<()> {
+        let (guild_id, summary, evicted) = {
+            let mut channels = self.channels.lock().await;
+            let history = channels.entry(channel_id).or_default();
+            (
+                history.guild_id,
+                history.summary.clone(),
+                std::mem::take(&mut history.evicted),
+            )
+        };
+
+        let new_messages = evicted
+            .iter()
+            .map(|turn| turn.message.content())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let llm_context = vec![
+            SystemMessage {
+                content: SUMMARY_PROMPT.to_string(),
+            }
+            .into(),
+            UserMessage {
+                content: format!(
+                    "Previous summary:\n{}\n\nNew messages:\n{}",
+                    summary.as_deref().unwrap_or("None"),
+                    new_messages
+                ),
+            }
+            .into(),
+        ];
+
+        match llm_engine.get_chat_completion(llm_context).await {
+            Ok(new_summary) => {
+                self.channels
+                    .lock()
+                    .await
+                    .entry(channel_id)
+                    .or_default()
+                    .summary = Some(new_summary.clone());
+                self.database
+                    .save_conversation_summary(guild_id, channel_id, new_summary)
+                    .await?;
+                Ok(())
+            }
+            Err(err) => {
+                // Put the turns back so they are retried with the next batch.
+                let mut channels = self.channels.lock().await;
+                let history = channels.entry(channel_id).or_default();
+                history.evicted.splice(0..0, evicted);
+                Err(err.into())
+            }
+        }
+    }
+}
+
+impl ChannelHistory {
+    fn contains(&self, message_id: MessageId) -> bool {
+        self.turns.iter().any(|turn| turn.message_id == message_id)
+    }
+
+    /// Inserts the turn in message order, replacing any turn with the same message id.
+    fn insert(&mut self, turn: MemoryTurn) {
+        if let Some(existing) = self
+            .turns
+            .iter_mut()
+            .find(|existing| existing.message_id == turn.message_id)
+        {
+            *existing = turn;
+            return;
+        }
+        let position = self
+            .turns
+            .iter()
+            .position(|existing| existing.message_id > turn.message_id)
+            .unwrap_or(self.turns.len());
+        self.turns.insert(position, turn);
+    }
+
+    /// Drops the oldest turns until the history fits in `token_budget`, always keeping the
+    /// newest turn. Returns whether any turn was dropped.
+    fn trim(&mut self, token_budget: usize, keep_evicted: bool) -> bool {
+        let mut total_tokens: usize = self.turns.iter().map(|turn| turn.tokens).sum();
+        let mut trimmed = false;
+        while total_tokens > token_budget && self.turns.len() > 1 {
+            let Some(turn) = self.turns.pop_front() else {
+                break;
+            };
+            total_tokens -= turn.tokens;
+            trimmed = true;
+            if keep_evicted {
+                self.evicted.push(turn);
+            }
+        }
+        trimmed
+    }
+
+    fn oldest(&self) -> Option<MessageId> {
+        self.turns.front().map(|turn| turn.message_id)
+    }
+
+    fn evicted_tokens(&self) -> usize {
+        self.evicted.iter().map(|turn| turn.tokens).sum()
+    }
+
+    fn to_chat(&self, max_messages: usize) -> LlmChat {
+        let summary = self.summary.as_ref().map(|summary| {
+            SystemMessage {
+                content: format!("Summary of the earlier conversation: {summary}"),
+            }
+            .into()
+        });
+        summary
+            .into_iter()
+            .chain(
+                self.turns
+                    .iter()
+                    .skip(self.turns.len().saturating_sub(max_messages))
+                    .map(|turn| turn.message.clone()),
+            )
+            .collect()
+    }
+}
+
+impl MemoryTurn {
+    fn new(message_id: MessageId, author_id: UserId, message: LlmMessage) -> Self {
+        Self {
+            message_id,
+            author_id,
+            tokens: estimate_tokens(message.content()),
+            message,
+        }
+    }
+
+    fn from_saved(turn: ConversationTurn) -> Self {
+        let message = if turn.from_bot {
+            AssistantMessage::new(turn.content).into()
+        } else {
+            UserMessage {
+                content: turn.content,
+            }
+            .into()
+        };
+        Self::new(turn.message_id, turn.author_id, message)
+    }
+
+    fn saved(&self) -> ConversationTurn {
+        ConversationTurn {
+            message_id: self.message_id,
+            author_id: self.author_id,
+            from_bot: matches!(self.message, LlmMessage::AssistantMessage(_)),
+            content: self.message.content().to_string(),
+        }
+    }
+}
+
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(CHARS_PER_TOKEN)
+}
+
+/// Converts a Discord message into a chat turn, the bot's own messages becoming assistant turns.
+pub fn to_llm_message(message: &Message, bot_id: UserId, cache: &Cache) -> LlmMessage {
+    let content = message.content_safe(cache);
+    if message.author.id == bot_id {
+        AssistantMessage::new(content).into()
+    } else {
+        UserMessage {
+            content: format!(
+                "({}) {} said: `{}`",
+                message.timestamp.format("%d/%m/%Y %H:%M"),
+                message.author.name,
+                content
+            ),
+        }
+        .into()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to retrieve latest_messages, {0}")]
+    GetChannelFailed(#[from] serenity::Error),
+    #[error("failed to summarise conversation, {0}")]
+    LlmError(#[from] llm::error::Error),
+    #[error("failed to access saved conversation, {0}")]
+    DatabaseError(#[from] database::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn turn(id: u64, content: &str) -> MemoryTurn {
+        authored_turn(id, 1, content)
+    }
+
+    fn authored_turn(id: u64, author_id: u64, content: &str) -> MemoryTurn {
+        MemoryTurn::new(
+            MessageId::new(id),
+            UserId::new(author_id),
+            UserMessage {
+                content: content.to_string(),
+            }
+            .into(),
+        )
+    }
+
+    fn ids(turns: &VecDeque<MemoryTurn>) -> Vec<u64> {
+        turns.iter().map(|turn| turn.message_id.get()).collect()
+    }
+
+    #[test]
+    fn insert_keeps_message_order() {
+        let mut history = ChannelHistory::default();
+        history.insert(turn(2, "second"));
+        history.insert(turn(3, "third"));
+        history.insert(turn(1, "first"));
+        assert_eq!(ids(&history.turns), [1, 2, 3]);
+    }
+
+    #[test]
+    fn insert_replaces_an_edited_message() {
+        let mut history = ChannelHistory::default();
+        history.insert(turn(1, "first"));
+        history.insert(turn(2, "typo"));
+        history.insert(turn(2, "fixed and longer"));
+        assert_eq!(ids(&history.turns), [1, 2]);
+        assert_eq!(history.turns[1].message.content(), "fixed and longer");
+        assert_eq!(history.turns[1].tokens, estimate_tokens("fixed and longer"));
+    }
+
+    #[test]
+    fn trim_evicts_oldest_turns_until_within_budget() {
+        let mut history = ChannelHistory::default();
+        // Eight characters, two tokens each.
+        for id in 1..=5 {
+            history.insert(turn(id, "12345678"));
+        }
+        history.trim(6, true);
+        assert_eq!(ids(&history.turns), [3, 4, 5]);
+        assert_eq!(
+            history
+                .evicted
+                .iter()
+                .map(|turn| turn.message_id.get())
+                .collect::<Vec<_>>(),
+            [1, 2]
+        );
+        assert_eq!(history.evicted_tokens(), 4);
+    }
+
+    #[test]
+    fn trim_at_budget_keeps_everything() {
+        let mut history = ChannelHistory::default();
+        history.insert(turn(1, "12345678"));
+        history.insert(turn(2, "12345678"));
+        history.trim(4, true);
+        assert_eq!(ids(&history.turns), [1, 2]);
+        assert!(history.evicted.is_empty());
+    }
+
+    #[test]
+    fn trim_keeps_the_newest_turn_over_budget() {
+        let mut history = ChannelHistory::default();
+        history.insert(turn(1, "short"));
+        history.insert(turn(2, &"long ".repeat(100)));
+        history.trim(10, true);
+        assert_eq!(ids(&history.turns), [2]);
+
+        history.trim(0, true);
+        assert_eq!(ids(&history.turns), [2]);
+    }
+
+    #[test]
+    fn trim_without_summaries_discards_evicted_turns() {
+        let mut history = ChannelHistory::default();
+        history.insert(turn(1, "12345678"));
+        history.insert(turn(2, "12345678"));
+        history.trim(2, false);
+        assert_eq!(ids(&history.turns), [2]);
+        assert!(history.evicted.is_empty());
+    }
+
+    #[test]
+    fn chat_starts_with_summary_and_keeps_latest_turns() {
+        let mut history = ChannelHistory {
+            summary: Some("Earlier talk".to_string()),
+            ..Default::default()
+        };
+        for id in 1..=4 {
+            history.insert(turn(id, &format!("message {id}")));
+        }
+        let chat = history.to_chat(2);
+        let contents: Vec<&str> = chat.iter().map(LlmMessage::content).collect();
+        assert_eq!(
+            contents,
+            [
+                "Summary of the earlier conversation: Earlier talk",
+                "message 3",
+                "message 4"
+            ]
+        );
+    }
+
+    #[test]
+    fn tokens_are_estimated_from_characters() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("abcd"), 1);
+        assert_eq!(estimate_tokens("abcde"), 2);
+        assert_eq!(estimate_tokens("éééé"), 1);
+    }
+
+    #[test]
+    fn saved_turns_keep_who_spoke() {
+        let from_user = authored_turn(1, 2, "hello");
+        let from_bot = MemoryTurn::new(
+            MessageId::new(2),
+            UserId::new(3),
+            AssistantMessage::new("hi").into(),
+        );
+        for turn in [from_user, from_bot] {
+            let restored = MemoryTurn::from_saved(turn.saved());
+            assert_eq!(restored.message_id, turn.message_id);
+            assert_eq!(restored.author_id, turn.author_id);
+            assert_eq!(restored.message.content(), turn.message.content());
+            assert_eq!(
+                matches!(restored.message, LlmMessage::AssistantMessage(_)),
+                matches!(turn.message, LlmMessage::AssistantMessage(_))
+            );
+            assert_eq!(restored.tokens, turn.tokens);
+        }
+    }
+
+    async fn memory(database: Database) -> ConversationMemory {
+        let opt_outs = Arc::new(OptOutStore::load(database.clone()).await.unwrap());
+        let options = MemoryOptions {
+            max_message_count: 10,
+            token_budget: 100,
+            summarize_evicted: true,
+        };
+        ConversationMemory::new(&options, database, opt_outs)
+    }
+
+    #[tokio::test]
+    async fn forget_author_drops_their_turns_everywhere() {
+        let memory = memory(Database::in_memory().unwrap()).await;
+        {
+            let mut channels = memory.channels.lock().await;
+            let history = channels.entry(ChannelId::new(10)).or_default();
+            history.insert(authored_turn(1, 1, "kept"));
+            history.insert(authored_turn(2, 2, "forgotten"));
+            history.evicted.push(authored_turn(3, 2, "forgotten"));
+            let history = channels.entry(ChannelId::new(11)).or_default();
+            history.insert(authored_turn(4, 2, "forgotten"));
+        }
+
+        memory.forget_author(UserId::new(2)).await;
+
+        let channels = memory.channels.lock().await;
+        assert_eq!(ids(&channels[&ChannelId::new(10)].turns), [1]);
+        assert!(channels[&ChannelId::new(10)].evicted.is_empty());
+        assert!(channels[&ChannelId::new(11)].turns.is_empty());
+    }
+
+    #[tokio::test]
+    async fn forgetting_deletes_saved_turns() {
+        let database = Database::in_memory().unwrap();
+        let memory = memory(database.clone()).await;
+        let channel_id = ChannelId::new(10);
+        for (id, author_id) in [(1, 1), (2, 2), (3, 1)] {
+            database
+                .save_conversation_turn(
+                    None,
+                    channel_id,
+                    authored_turn(id, author_id, "hello").saved(),
+                )
+                .await
+                .unwrap();
+        }
+
+        memory.forget(channel_id, &[MessageId::new(3)]).await;
+        memory.forget_author(UserId::new(2)).await;
+
+        let saved = database.conversation_turns(channel_id).await.unwrap();
+        assert_eq!(
+            saved
+                .iter()
+                .map(|turn| turn.message_id.get())
+                .collect::<Vec<_>>(),
+            [1]
+        );
+    }
+
+    #[tokio::test]
+    async fn forget_guild_drops_its_channels_and_summaries() {
+        let database = Database::in_memory().unwrap();
+        let memory = memory(database.clone()).await;
+        for (guild_id, channel_id) in [(1, 10), (1, 11), (2, 20)] {
+            let (guild_id, channel_id) = (GuildId::new(guild_id), ChannelId::new(channel_id));
+            memory
+                .channels
+                .lock()
+                .await
+                .entry(channel_id)
+                .or_default()
+                .guild_id = Some(guild_id);
+            database
+                .save_conversation_summary(Some(guild_id), channel_id, "summary".to_string())
+                .await
+                .unwrap();
+        }
+
+        memory.forget_guild(GuildId::new(1)).await;
+
+        let channels = memory.channels.lock().await;
+        assert_eq!(channels.keys().collect::<Vec<_>>(), [&ChannelId::new(20)]);
+        for (channel_id, kept) in [(10, false), (11, false), (20, true)] {
+            assert_eq!(
+                database
+                    .conversation_summary(ChannelId::new(channel_id))
+                    .await
+                    .unwrap()
+                    .is_some(),
+                kept
+            );
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::{HashMap, HashSet};
+
+use serenity::all::{
+    Cache, Channel, ChannelId, ChannelType, Guild, GuildChannel, GuildId, Http, Member, Message,
+    Permissions,
+};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Stored messages searched for per result kept, leaving some to spare for the ones from
+/// channels the reader cannot see.
+pub const SEARCH_OVERFETCH: u64 = 3;
+
+/// The member who will see the results of a search, who must only be shown messages from
+/// channels they can read.
+#[derive(Clone, Copy)]
+pub struct Reader<'a> {
+    pub guild_id: GuildId,
+    pub member: &'a Member,
+}
+
+/// The author of a guild message as a member, built from the partial member Discord sends
+/// with it. `None` for direct messages and webhooks.
+pub fn message_author(message: &Message) -> Option<Member> {
+    let guild_id = message.guild_id?;
+    let mut member: Member = message.member.as_deref()?.clone().into();
+    member.user = message.author.clone();
+    member.guild_id = guild_id;
+    Some(member)
+}
+
+/// Keeps the items from channels the reader can read, in order and at most `limit` of them.
+/// Items without a channel are dropped, as their channel cannot be checked.
+pub async fn retain_readable<T>(
+    cache: &Cache,
+    http: &Http,
+    reader: Reader<'_>,
+    items: Vec<T>,
+    channel_of: impl Fn(&T) -> Option<u64>,
+    limit: usize,
+) -> Result<Vec<T>> {
+    let channel_ids = items
+        .iter()
+        .filter_map(&channel_of)
+        .map(ChannelId::new)
+        .collect();
+    let readable = readable_channels(cache, http, reader, channel_ids).await?;
+    Ok(items
+        .into_iter()
+        .filter(|item| {
+            channel_of(item)
+                .is_some_and(|channel_id| readable.contains(&ChannelId::new(channel_id)))
+        })
+        .take(limit)
+        .collect())
+}
+
+/// The channels among `channel_ids` the reader may read the history of. Threads, including
+/// archived ones fetched from Discord, follow their parent channel. Private threads are only
+/// readable with Manage Threads, as thread membership is not known here.
+pub async fn readable_channels(
+    cache: &Cache,
+    http: &Http,
+    reader: Reader<'_>,
+    channel_ids: HashSet<ChannelId>,
+) -> Result<HashSet<ChannelId>> {
+    let uncached: Vec<ChannelId> = {
+        let guild = cache.guild(reader.guild_id).ok_or(Error::GuildNotCached)?;
+        channel_ids
+            .iter()
+            .filter(|channel_id| find_channel(&guild, **channel_id).is_none())
+            .copied()
+            .collect()
+    };
+    let mut fetched = HashMap::new();
+    for channel_id in uncached {
+        // Channels that no longer exist cannot be read.
+        if let Ok(Channel::Guild(channel)) = channel_id.to_channel(http).await {
+            if channel.guild_id == reader.guild_id {
+                fetched.insert(channel_id, channel);
+            }
+        }
+    }
+
+    let guild = cache.guild(reader.guild_id).ok_or(Error::GuildNotCached)?;
+    Ok(channel_ids
+        .into_iter()
+        .filter(|channel_id| {
+            find_channel(&guild, *channel_id)
+                .or_else(|| fetched.get(channel_id))
+                .is_some_and(|channel| member_can_read(&guild, channel, reader.member))
+        })
+        .collect())
+}
+
+/// Whether `permissions` allow reading a channel's earlier messages.
+pub fn can_read(permissions: Permissions) -> bool {
+    permissions.view_channel() && permissions.read_message_history()
+}
+
+fn find_channel(guild: &Guild, channel_id: ChannelId) -> Option<&GuildChannel> {
+    guild
+        .channels
+        .get(&channel_id)
+        .or_else(|| guild.threads.iter().find(|thread| thread.id == channel_id))
+}
+
+fn member_can_read(guild: &Guild, channel: &GuildChannel, member: &Member) -> bool {
+    let is_thread = matches!(
+        channel.kind,
+        ChannelType::PublicThread | ChannelType::PrivateThread | ChannelType::NewsThread
+    );
+    let permission_source = if is_thread {
+        match channel
+            .parent_id
+            .and_then(|parent_id| guild.channels.get(&parent_id))
+        {
+            Some(parent) => parent,
+            None => return false,
+        }
+    } else {
+        channel
+    };
+    let permissions = guild.user_permissions_in(permission_source, member);
+    can_read(permissions)
+        && (channel.kind != ChannelType::PrivateThread || permissions.manage_threads())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Guild is not cached, so channel permissions cannot be checked")]
+    GuildNotCached,
+}
+
+#[cfg(test)]
+mod tests {
+    use serenity::all::{PermissionOverwrite, PermissionOverwriteType, Role, RoleId, User, UserId};
+
+    use super::*;
+
+    const STAFF_ROLE: RoleId = RoleId::new(5);
+
+    fn channel(id: u64, kind: ChannelType, parent_id: Option<u64>) -> GuildChannel {
+        let mut channel = GuildChannel::default();
+        channel.id = ChannelId::new(id);
+        channel.guild_id = GuildId::new(1);
+        channel.kind = kind;
+        channel.parent_id = parent_id.map(ChannelId::new);
+        channel
+    }
+
+    /// A guild with a public channel 10 and a staff-only channel 11, each with a thread.
+    fn guild() -> Guild {
+        let mut everyone = Role::default();
+        everyone.id = RoleId::new(1);
+        everyone.permissions = Permissions::VIEW_CHANNEL | Permissions::READ_MESSAGE_HISTORY;
+
+        let public = channel(10, ChannelType::Text, None);
+        let mut staff = channel(11, ChannelType::Text, None);
+        staff.permission_overwrites = vec![
+            PermissionOverwrite {
+                allow: Permissions::empty(),
+                deny: Permissions::VIEW_CHANNEL,
+                kind: PermissionOverwriteType::Role(everyone.id),
+            },
+            PermissionOverwrite {
+                allow: Permissions::VIEW_CHANNEL,
+                deny: Permissions::empty(),
+                kind: PermissionOverwriteType::Role(STAFF_ROLE),
+            },
+        ];
+
+        let mut guild = Guild::default();
+        guild.id = GuildId::new(1);
+        guild.owner_id = UserId::new(99);
+        guild.roles = [(everyone.id, everyone)].into();
+        guild.channels = [(public.id, public), (staff.id, staff)].into();
+        guild.threads = vec![
+            channel(12, ChannelType::PublicThread, Some(11)),
+            channel(13, ChannelType::PrivateThread, Some(10)),
+        ];
+        guild
+    }
+
+    fn member(roles: Vec<RoleId>) -> Member {
+        let mut user = User::default();
+        user.id = UserId::new(2);
+        let mut member = Member::default();
+        member.user = user;
+        member.guild_id = GuildId::new(1);
+        member.roles = roles;
+        member
+    }
+
+    fn readable(guild: &Guild, member: &Member) -> Vec<u64> {
+        let mut readable: Vec<u64> = (10..=13)
+            .filter(|id| {
+                find_channel(guild, ChannelId::new(*id))
+                    .is_some_and(|channel| member_can_read(guild, channel, member))
+            })
+            .collect();
+        readable.sort();
+        readable
+    }
+
+    #[test]
+    fn members_read_what_their_roles_allow() {
+        let guild = guild();
+        assert_eq!(readable(&guild, &member(vec![])), [10]);
+        assert_eq!(readable(&guild, &member(vec![STAFF_ROLE])), [10, 11, 12]);
+    }
+
+    #[test]
+    fn private_threads_need_manage_threads() {
+        let mut guild = guild();
+        let mut moderator = Role::default();
+        moderator.id = RoleId::new(6);
+        moderator.permissions = Permissions::MANAGE_THREADS;
+        guild.roles.insert(moderator.id, moderator);
+
+        assert_eq!(readable(&guild, &member(vec![RoleId::new(6)])), [10, 13]);
+    }
+
+    #[test]
+    fn reading_needs_the_history_too() {
+        assert!(can_read(
+            Permissions::VIEW_CHANNEL | Permissions::READ_MESSAGE_HISTORY
+        ));
+        assert!(!can_read(Permissions::VIEW_CHANNEL));
+        assert!(!can_read(Permissions::READ_MESSAGE_HISTORY));
+    }
+
+    #[test]
+    fn message_authors_are_guild_members() {
+        let mut message = Message::default();
+        message.author.id = UserId::new(2);
+        assert!(message_author(&message).is_none());
+
+        message.guild_id = Some(GuildId::new(1));
+        assert!(message_author(&message).is_none());
+
+        message.member = Some(Box::new(
+            serde_json::from_value(serde_json::json!({ "roles": ["5"] })).unwrap(),
+        ));
+        let member = message_author(&message).unwrap();
+        assert_eq!(member.user.id, UserId::new(2));
+        assert_eq!(member.guild_id, GuildId::new(1));
+        assert_eq!(member.roles, [STAFF_ROLE]);
+    }
+}
@@ -0,0 +1,140 @@
+use std::{collections::HashSet, sync::Mutex};
+
+use serde::Serialize;
+use serenity::all::UserId;
+
+use crate::{
+    database::{self, Database},
+    vec_db::{db_handler::VdbHandler, store::VectorFilter, vector::DbVector},
+};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Messages read from the vector database per request while exporting.
+const EXPORT_PAGE_SIZE: u32 = 256;
+
+/// Users who asked for their messages not to be stored, kept in the database so the choice
+/// outlives restarts.
+pub struct OptOutStore {
+    database: Database,
+    users: Mutex<HashSet<UserId>>,
+}
+
+/// Everything stored about a user, as sent to them by `/privacy export`.
+#[derive(Debug, Serialize)]
+pub struct UserExport {
+    pub user_id: u64,
+    pub opted_out: bool,
+    pub messages: Vec<ExportedMessage>,
+}
+
+/// A stored message without its embedding, which only means something to the embedding model.
+#[derive(Debug, Serialize)]
+pub struct ExportedMessage {
+    pub message_id: u64,
+    pub guild_id: u64,
+    pub channel_id: Option<u64>,
+    pub timestamp: Option<i64>,
+    pub author_name: Option<String>,
+    pub reply_to_id: Option<u64>,
+    pub content: String,
+    pub has_attachments: bool,
+    pub has_images: bool,
+}
+
+impl OptOutStore {
+    /// Loads every opt-out, so checking a message's author does not wait on the database.
+    pub async fn load(database: Database) -> Result<Self> {
+        let users = database.opted_out_users().await?.into_iter().collect();
+        Ok(Self {
+            database,
+            users: Mutex::new(users),
+        })
+    }
+
+    pub fn is_opted_out(&self, user_id: UserId) -> bool {
+        self.users
+            .lock()
+            .expect("Opt-out lock poisoned")
+            .contains(&user_id)
+    }
+
+    /// Records the user's choice, returning whether it changed anything.
+    pub async fn set_opted_out(&self, user_id: UserId, opted_out: bool) -> Result<bool> {
+        let changed = self.database.set_opted_out(user_id, opted_out).await?;
+        let mut users = self.users.lock().expect("Opt-out lock poisoned");
+        if opted_out {
+            users.insert(user_id);
+        } else {
+            users.remove(&user_id);
+        }
+        Ok(changed)
+    }
+}
+
+/// Collects every message stored from the user, across all guilds. Conversation summaries are
+/// not included, as they mix the messages of everyone in the channel.
+pub async fn export_user(
+    vec_db_client: &VdbHandler,
+    opt_outs: &OptOutStore,
+    user_id: UserId,
+) -> Result<UserExport> {
+    let filter = VectorFilter {
+        author_id: Some(user_id.get()),
+        ..Default::default()
+    };
+    let mut messages = Vec::new();
+    let mut offset = None;
+    loop {
+        let page = vec_db_client
+            .scroll_vectors(&filter, offset, EXPORT_PAGE_SIZE)
+            .await
+            .map_err(Error::VectorDB)?;
+        messages.extend(page.vectors.into_iter().map(ExportedMessage::from));
+        offset = page.next_offset;
+        if offset.is_none() {
+            break;
+        }
+    }
+    Ok(UserExport {
+        user_id: user_id.get(),
+        opted_out: opt_outs.is_opted_out(user_id),
+        messages,
+    })
+}
+
+/// Deletes every message stored from the user, across all guilds. Conversation summaries are
+/// kept, as they mix the messages of everyone in the channel.
+pub async fn forget_user(vec_db_client: &VdbHandler, user_id: UserId) -> Result<()> {
+    vec_db_client
+        .delete_vectors(&VectorFilter {
+            author_id: Some(user_id.get()),
+            ..Default::default()
+        })
+        .await
+        .map_err(Error::VectorDB)
+}
+
+impl From<DbVector> for ExportedMessage {
+    fn from(vector: DbVector) -> Self {
+        Self {
+            message_id: vector.message_id,
+            guild_id: vector.guild_id,
+            channel_id: vector.channel_id,
+            timestamp: vector.timestamp,
+            author_name: vector.author_name,
+            reply_to_id: vector.reply_to_id,
+            content: vector.message,
+            has_attachments: vector.has_attachments,
+            has_images: vector.has_images,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Failed to access opt-outs, {0}")]
+    Database(#[from] database::Error),
+    #[error("Failed to access vector database, {0}")]
+    VectorDB(anyhow::Error),
+}
@@ -0,0 +1,49 @@
+use serenity::all::Context;
+
+use crate::{
+    llm::{self, backend::EmbedBackend},
+    permissions::{self, retain_readable, Reader, SEARCH_OVERFETCH},
+    vec_db::{db_handler::VdbHandler, vector::DbVector},
+};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Finds the `limit` previously stored guild messages semantically closest to `message`, among
+/// those sent in channels the reader can read.
+pub async fn find_near_messages<'a>(
+    message: &'a str,
+    reader: Reader<'a>,
+    limit: u64,
+    discord: &'a Context,
+    embed_engine: &'a dyn EmbedBackend,
+    vec_db_client: &'a VdbHandler,
+) -> Result<Vec<DbVector>> {
+    let embedding = embed_engine.get_embed(message).await?;
+    let close_messages = vec_db_client
+        .get_close_vectors(embedding, reader.guild_id.get(), limit * SEARCH_OVERFETCH)
+        .await
+        .map_err(Error::VectorDB)?;
+    Ok(retain_readable(
+        &discord.cache,
+        &discord.http,
+        reader,
+        close_messages,
+        |point| point.channel_id,
+        limit as usize,
+    )
+    .await?)
+}
+
+pub fn generate_relevant_message_prompt(messages: Vec<DbVector>) -> Option<String> {
+    Some(format!( "Using RAG retrieval, the following messages may or may not contain relevant information of messages that were sent in the past.\nRETRIEVED_MESSAGES\n{}\nEND_OF_RETRIEVED_MESSAGES", messages.into_iter().map(|point| point.to_prompt_line()).reduce(|acc, msg| acc + "\n" + &msg)?))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to embed message for retrieval, {0}")]
+    LlmError(#[from] llm::error::Error),
+    #[error("Failed to retrieve response from vector database client.\n{0}")]
+    VectorDB(anyhow::Error),
+    #[error("{0}")]
+    Permissions(#[from] permissions::Error),
+}
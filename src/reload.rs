@@ -0,0 +1,137 @@
+use std::{
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use arc_swap::ArcSwap;
+
+use crate::{
+    environment::{get_environment, Environment, CONFIG_DIR},
+    error::{Error, Result},
+    handler::Handler,
+    ingest::filter::IngestFilter,
+    llm::engine::LlmEngine,
+    vec_db::db_handler::VdbHandler,
+};
+
+/// How often the configuration directory is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The configuration together with everything built from it, replaced as a whole when the
+/// configuration is reloaded so a request never sees a mix of old and new settings.
+pub struct Services {
+    pub environment: Environment,
+    pub llm_engine: Arc<LlmEngine>,
+    pub vec_db_client: Arc<VdbHandler>,
+    pub filter: Arc<IngestFilter>,
+}
+
+pub type SharedServices = Arc<ArcSwap<Services>>;
+
+impl Services {
+    pub fn new(
+        environment: Environment,
+        llm_engine: LlmEngine,
+        vec_db_client: VdbHandler,
+    ) -> Result<Self> {
+        Ok(Self {
+            filter: Arc::new(IngestFilter::new(&environment.ingest.filter)?),
+            llm_engine: Arc::new(llm_engine),
+            vec_db_client: Arc::new(vec_db_client),
+            environment,
+        })
+    }
+
+    /// Services for the new configuration, reusing the llm engine and vector database client
+    /// unless their endpoints changed.
+    pub async fn rebuild(&self, environment: Environment) -> Result<Self> {
+        let filter = Arc::new(IngestFilter::new(&environment.ingest.filter)?);
+        let (old, new) = (&self.environment.llm, &environment.llm);
+        let embed_changed = old.backend != new.backend
+            || old.base_url != new.base_url
+            || old.api_key != new.api_key
+            || old.embed_model != new.embed_model;
+        let llm_engine = if embed_changed || old.model != new.model {
+            println!("Llm endpoint changed, rebuilding the llm engine");
+            Arc::new(LlmEngine::new(&environment)?)
+        } else {
+            self.llm_engine.clone()
+        };
+        // The collection is named after the embedding model, so a new embedding endpoint can
+        // mean a new collection.
+        let vec_db_client = if embed_changed || self.environment.vdb != environment.vdb {
+            println!("Vector database settings changed, reopening the vector database");
+            Arc::new(
+                VdbHandler::new(&environment, llm_engine.as_ref())
+                    .await
+                    .map_err(Error::VectorDB)?,
+            )
+        } else {
+            self.vec_db_client.clone()
+        };
+        Ok(Self {
+            environment,
+            llm_engine,
+            vec_db_client,
+            filter,
+        })
+    }
+}
+
+/// Settings only read at startup, whose changes are ignored until the bot restarts.
+pub fn restart_required(old: &Environment, new: &Environment) -> Vec<&'static str> {
+    [
+        ("discord_token", old.discord_token != new.discord_token),
+        ("bot_name", old.bot_name != new.bot_name),
+        ("database.path", old.database.path != new.database.path),
+        ("commands", old.commands != new.commands),
+        (
+            "ingest.queue_capacity",
+            old.ingest.queue_capacity != new.ingest.queue_capacity,
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(name, changed)| changed.then_some(name))
+    .collect()
+}
+
+/// Polls the configuration directory and hands every valid new configuration to the handler.
+/// An invalid configuration is reported and the current one kept.
+pub async fn watch_config(handler: Arc<Handler>) {
+    let mut last_seen = config_fingerprint();
+    loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+        let fingerprint = config_fingerprint();
+        if fingerprint == last_seen {
+            continue;
+        }
+        last_seen = fingerprint;
+        println!("Configuration changed, reloading");
+        let reloaded = match get_environment() {
+            Ok(environment) => handler.reload(environment).await,
+            Err(err) => Err(err),
+        };
+        match reloaded {
+            Ok(()) => println!("Configuration reloaded"),
+            Err(err) => println!("Keeping the current configuration, {err}"),
+        }
+    }
+}
+
+/// Modification times of the files in the configuration directory, which change whenever a
+/// file is edited, added or removed.
+fn config_fingerprint() -> Vec<(PathBuf, Option<SystemTime>)> {
+    let mut files: Vec<_> = match std::fs::read_dir(CONFIG_DIR) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| {
+                let modified = entry.metadata().and_then(|meta| meta.modified()).ok();
+                (entry.path(), modified)
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    files.sort();
+    files
+}
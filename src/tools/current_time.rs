@@ -0,0 +1,31 @@
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use serenity::all::Timestamp;
+
+use super::{Result, Tool, ToolContext};
+
+pub struct CurrentTime;
+
+#[async_trait]
+impl Tool for CurrentTime {
+    fn name(&self) -> &str {
+        "current_time"
+    }
+
+    fn description(&self) -> &str {
+        "Get the current date and time in UTC."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {},
+        })
+    }
+
+    async fn call(&self, _arguments: Value, _ctx: &ToolContext<'_>) -> Result<String> {
+        Ok(Timestamp::now()
+            .format("%A %d/%m/%Y %H:%M:%S UTC")
+            .to_string())
+    }
+}
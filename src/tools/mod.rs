@@ -0,0 +1,117 @@
+mod current_time;
+mod recent_messages;
+mod search_memory;
+
+use async_trait::async_trait;
+use serde_json::Value;
+use serenity::all::{Cache, ChannelId, GuildId, Http, Member};
+
+use crate::{
+    llm::{
+        self,
+        backend::EmbedBackend,
+        model::{ToolCall, ToolDefinition},
+    },
+    permissions,
+    vec_db::db_handler::VdbHandler,
+};
+
+pub use current_time::CurrentTime;
+pub use recent_messages::RecentMessages;
+pub use search_memory::SearchMemory;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// A Rust function the model can call, described to it by a JSON schema.
+#[async_trait]
+pub trait Tool: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn description(&self) -> &str;
+
+    /// JSON schema of the arguments object the model passes to [`Tool::call`].
+    fn parameters(&self) -> Value;
+
+    async fn call(&self, arguments: Value, ctx: &ToolContext<'_>) -> Result<String>;
+}
+
+/// What a tool may look at while answering a message in a channel.
+pub struct ToolContext<'a> {
+    pub guild_id: Option<GuildId>,
+    pub channel_id: ChannelId,
+    /// Author of the message being answered, who must only be shown what they can read.
+    pub member: Option<&'a Member>,
+    pub http_client: &'a Http,
+    pub cache: &'a Cache,
+    pub embed_engine: &'a dyn EmbedBackend,
+    pub vec_db_client: &'a VdbHandler,
+    /// Most stored messages a search returns.
+    pub search_limit: u64,
+}
+
+#[derive(Default)]
+pub struct ToolRegistry {
+    tools: Vec<Box<dyn Tool>>,
+}
+
+impl ToolRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registry holding every tool the bot ships with.
+    pub fn with_default_tools() -> Self {
+        Self::new()
+            .register(SearchMemory)
+            .register(RecentMessages)
+            .register(CurrentTime)
+    }
+
+    pub fn register(mut self, tool: impl Tool + 'static) -> Self {
+        self.tools.push(Box::new(tool));
+        self
+    }
+
+    pub fn definitions(&self) -> Vec<ToolDefinition> {
+        self.tools
+            .iter()
+            .map(|tool| {
+                ToolDefinition::function(tool.name(), tool.description(), tool.parameters())
+            })
+            .collect()
+    }
+
+    /// Executes a tool call. Failures are returned as text so the model can recover from them.
+    pub async fn call(&self, tool_call: &ToolCall, ctx: &ToolContext<'_>) -> String {
+        let Some(tool) = self
+            .tools
+            .iter()
+            .find(|tool| tool.name() == tool_call.function.name)
+        else {
+            return Error::UnknownTool(tool_call.function.name.clone()).to_string();
+        };
+        tool.call(tool_call.function.arguments.clone(), ctx)
+            .await
+            .unwrap_or_else(|err| format!("Tool {} failed, {}", tool.name(), err))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("No tool named {0}")]
+    UnknownTool(String),
+    #[error("Invalid tool arguments, {0}")]
+    InvalidArguments(#[from] serde_json::Error),
+    #[error("Tool is only available within a guild")]
+    MissingGuildID,
+    #[error("Tool is only available to guild members")]
+    MissingMember,
+    #[error("failed to retrieve messages from discord, {0}")]
+    Discord(#[from] serenity::Error),
+    #[error("failed to embed query, {0}")]
+    LlmError(#[from] llm::error::Error),
+    #[error("Failed to retrieve response from vector database client.\n{0}")]
+    VectorDB(anyhow::Error),
+    #[error("{0}")]
+    Permissions(#[from] permissions::Error),
+}
@@ -0,0 +1,66 @@
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::{Result, Tool, ToolContext};
+
+const DEFAULT_MESSAGE_COUNT: u8 = 20;
+const MAX_MESSAGE_COUNT: u8 = 50;
+
+pub struct RecentMessages;
+
+#[derive(Deserialize)]
+struct Arguments {
+    count: Option<u8>,
+}
+
+#[async_trait]
+impl Tool for RecentMessages {
+    fn name(&self) -> &str {
+        "recent_messages"
+    }
+
+    fn description(&self) -> &str {
+        "Fetch the most recent messages sent in the current channel, oldest first."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "count": {
+                    "type": "integer",
+                    "description": "How many messages to fetch.",
+                    "minimum": 1,
+                    "maximum": MAX_MESSAGE_COUNT,
+                },
+            },
+        })
+    }
+
+    async fn call(&self, arguments: Value, ctx: &ToolContext<'_>) -> Result<String> {
+        let arguments: Arguments = serde_json::from_value(arguments)?;
+        let count = arguments
+            .count
+            .unwrap_or(DEFAULT_MESSAGE_COUNT)
+            .clamp(1, MAX_MESSAGE_COUNT);
+
+        let messages = ctx
+            .http_client
+            .get_messages(ctx.channel_id, None, Some(count))
+            .await?;
+        Ok(messages
+            .into_iter()
+            .rev()
+            .map(|message| {
+                format!(
+                    "({}) {}: {}",
+                    message.timestamp.format("%d/%m/%Y %H:%M"),
+                    message.author.name,
+                    message.content
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+}
@@ -0,0 +1,71 @@
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::{Error, Result, Tool, ToolContext};
+use crate::permissions::{retain_readable, Reader, SEARCH_OVERFETCH};
+
+pub struct SearchMemory;
+
+#[derive(Deserialize)]
+struct Arguments {
+    query: String,
+}
+
+#[async_trait]
+impl Tool for SearchMemory {
+    fn name(&self) -> &str {
+        "search_memory"
+    }
+
+    fn description(&self) -> &str {
+        "Search messages previously sent in this server for ones related to the query."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "query": {
+                    "type": "string",
+                    "description": "What the messages should be about.",
+                },
+            },
+            "required": ["query"],
+        })
+    }
+
+    async fn call(&self, arguments: Value, ctx: &ToolContext<'_>) -> Result<String> {
+        let arguments: Arguments = serde_json::from_value(arguments)?;
+        let guild_id = ctx.guild_id.ok_or(Error::MissingGuildID)?;
+        let member = ctx.member.ok_or(Error::MissingMember)?;
+
+        let embedding = ctx.embed_engine.get_embed(&arguments.query).await?;
+        let close_messages = ctx
+            .vec_db_client
+            .get_close_vectors(
+                embedding,
+                guild_id.get(),
+                ctx.search_limit * SEARCH_OVERFETCH,
+            )
+            .await
+            .map_err(Error::VectorDB)?;
+        let close_messages = retain_readable(
+            ctx.cache,
+            ctx.http_client,
+            Reader { guild_id, member },
+            close_messages,
+            |point| point.channel_id,
+            ctx.search_limit as usize,
+        )
+        .await?;
+        if close_messages.is_empty() {
+            return Ok("No related messages found".to_string());
+        }
+        Ok(close_messages
+            .into_iter()
+            .map(|point| point.to_prompt_line())
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+}
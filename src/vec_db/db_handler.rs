@@ -1,84 +1,325 @@
-use qdrant_client::{
-    qdrant::{
-        Condition, CreateCollectionBuilder, Filter, ListCollectionsResponse, SearchPointsBuilder,
-        VectorParamsBuilder,
-    },
-    Qdrant,
+use crate::{
+    environment::{Environment, VectorDBOptions},
+    llm::backend::EmbedBackend,
 };
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashSet;
 
-use crate::environment::Environment;
-use anyhow::{Context, Result};
+use serenity::all::Message;
 
-use super::{vector::DbVector, DB_COLLECTION_NAME, DB_VEC_LENGTH};
+use super::{
+    local::LocalStore,
+    qdrant::QdrantStore,
+    store::{ScrollPage, VectorFilter, VectorSpace, VectorStore, VectorStoreKind},
+    vector::{DbVector, ScoredVector},
+    LEGACY_COLLECTION_NAME,
+};
+
+/// Text embedded at startup to learn the embedding model's dimension.
+const DIMENSION_PROBE: &str = "dimension probe";
 
 pub struct VdbHandler {
-    client: Qdrant,
+    store: Box<dyn VectorStore>,
+    space: VectorSpace,
+}
+
+/// Which stored messages a search may return.
+pub struct SearchFilter {
+    pub guild_id: u64,
+    pub channel_id: Option<u64>,
+    pub author_id: Option<u64>,
+    pub limit: u64,
 }
 
 impl VdbHandler {
-    pub async fn new(env: &Environment) -> Result<Self> {
-        let client = Qdrant::from_url(&env.vdb.base_url)
-            .build()
-            .context("Failed to build qdrant client")?;
-
-        Self::initialise_collection(&client).await?;
-
-        Ok(Self { client })
-    }
-
-    async fn initialise_collection(client: &Qdrant) -> Result<()> {
-        match client.collection_exists(DB_COLLECTION_NAME).await? {
-            true => Ok(()),
-            false => {
-                let vectors_config = VectorParamsBuilder::new(
-                    DB_VEC_LENGTH,
-                    qdrant_client::qdrant::Distance::Euclid,
-                );
-                let collection =
-                    CreateCollectionBuilder::new(DB_COLLECTION_NAME).vectors_config(vectors_config);
-                client.create_collection(collection).await?;
-                Ok(())
-            }
+    /// Opens the configured store, probing `embed` for the vector dimension unless
+    /// `vdb.dimension` is set.
+    /// When `vdb.alias` is set the collection it points at is used, and an alias that does not
+    /// exist yet is created for the configured collection. With neither set, messages stored
+    /// before collections were named after the embedding model keep being used until the
+    /// reembed binary has moved them over.
+    pub async fn new(env: &Environment, embed: &dyn EmbedBackend) -> Result<Self> {
+        let mut space = Self::resolve_space(&env.vdb, &env.llm.embed_model, embed).await?;
+        let aliased = match &env.vdb.alias {
+            Some(alias) => Self::resolve_alias(&env.vdb, alias).await?,
+            None => None,
+        };
+        if let Some(collection) = &aliased {
+            space.collection = collection.clone();
+        } else if env.vdb.collection.is_none()
+            && env.vdb.alias.is_none()
+            && Self::uses_legacy_collection(&env.vdb, &space).await?
+        {
+            println!(
+                "Collection {} does not exist yet, using the old {LEGACY_COLLECTION_NAME} \
+                 collection. Run the reembed binary with {LEGACY_COLLECTION_NAME} to move its \
+                 messages over",
+                space.collection
+            );
+            space.collection = LEGACY_COLLECTION_NAME.to_string();
+        }
+        println!(
+            "Using vector collection {} ({} dimensions, {})",
+            space.collection,
+            space.dimension,
+            space.distance.as_str()
+        );
+        let store = Self::open_store(&env.vdb, &space).await?;
+        if let (Some(alias), None) = (&env.vdb.alias, aliased) {
+            store.point_alias(alias).await?;
+        }
+        Ok(Self::from_store(store, space))
+    }
+
+    pub fn from_store(store: Box<dyn VectorStore>, space: VectorSpace) -> Self {
+        Self { store, space }
+    }
+
+    /// Whether the collection named after the embedding model is missing while the legacy one
+    /// holds messages.
+    async fn uses_legacy_collection(
+        options: &VectorDBOptions,
+        space: &VectorSpace,
+    ) -> Result<bool> {
+        Ok(space.collection != LEGACY_COLLECTION_NAME
+            && Self::describe_collection(options, &space.collection)
+                .await?
+                .is_none()
+            && Self::describe_collection(options, LEGACY_COLLECTION_NAME)
+                .await?
+                .is_some())
+    }
+
+    pub async fn open_store(
+        options: &VectorDBOptions,
+        space: &VectorSpace,
+    ) -> Result<Box<dyn VectorStore>> {
+        Ok(match options.backend {
+            VectorStoreKind::Qdrant => Box::new(QdrantStore::new(options, space).await?),
+            VectorStoreKind::Local => Box::new(LocalStore::open(&options.path, space)?),
+        })
+    }
+
+    /// The shape of the vectors in an existing `collection`, `None` if there is no such
+    /// collection.
+    pub async fn describe_collection(
+        options: &VectorDBOptions,
+        collection: &str,
+    ) -> Result<Option<VectorSpace>> {
+        match options.backend {
+            VectorStoreKind::Qdrant => QdrantStore::describe(options, collection).await,
+            VectorStoreKind::Local => LocalStore::describe(&options.path, collection),
+        }
+    }
+
+    pub async fn resolve_alias(options: &VectorDBOptions, alias: &str) -> Result<Option<String>> {
+        match options.backend {
+            VectorStoreKind::Qdrant => QdrantStore::resolve_alias(options, alias).await,
+            VectorStoreKind::Local => LocalStore::resolve_alias(&options.path, alias),
+        }
+    }
+
+    /// The collection and vector shape the configured embedding model writes to, ignoring
+    /// `vdb.alias`.
+    pub async fn resolve_space(
+        options: &VectorDBOptions,
+        embed_model: &str,
+        embed: &dyn EmbedBackend,
+    ) -> Result<VectorSpace> {
+        let dimension = match options.dimension {
+            Some(dimension) => dimension,
+            None => embed
+                .get_embed(DIMENSION_PROBE)
+                .await
+                .context("Failed to probe the embedding dimension, set vdb.dimension to skip")?
+                .len() as u64,
+        };
+        Ok(VectorSpace {
+            collection: options
+                .collection
+                .clone()
+                .unwrap_or_else(|| VectorSpace::collection_name(embed_model, dimension)),
+            dimension,
+            distance: options.distance,
+        })
+    }
+
+    pub fn space(&self) -> &VectorSpace {
+        &self.space
+    }
+
+    fn check_dimension(&self, vector: &[f32]) -> Result<()> {
+        if vector.len() as u64 != self.space.dimension {
+            return Err(anyhow!(
+                "Embedding has {} dimensions but collection {} holds {}-dimensional vectors",
+                vector.len(),
+                self.space.collection,
+                self.space.dimension
+            ));
         }
+        Ok(())
     }
 
-    pub async fn add_vector(
+    pub async fn add_vector(&self, vector: Vec<f32>, message: &Message) -> Result<()> {
+        self.check_dimension(&vector)?;
+        let db_vec = DbVector::new(vector, message)?;
+        self.store.upsert(vec![db_vec]).await
+    }
+
+    /// Stores vectors built elsewhere, such as by a backfill, in one batch.
+    pub async fn add_vectors(&self, vectors: Vec<DbVector>) -> Result<()> {
+        for vector in &vectors {
+            self.check_dimension(&vector.vector)?;
+        }
+        self.store.upsert(vectors).await
+    }
+
+    /// The ids among `message_ids` that are already stored.
+    pub async fn existing_message_ids(&self, message_ids: Vec<u64>) -> Result<HashSet<u64>> {
+        self.store.existing_ids(message_ids).await
+    }
+
+    /// The stored vectors of the given messages, skipping messages that are not stored.
+    pub async fn get_vectors(&self, message_ids: Vec<u64>) -> Result<Vec<DbVector>> {
+        let limit = message_ids.len() as u32;
+        let filter = VectorFilter {
+            message_ids: Some(message_ids),
+            ..Default::default()
+        };
+        Ok(self.store.scroll(&filter, None, limit).await?.vectors)
+    }
+
+    pub async fn get_close_vectors(
         &self,
         vector: Vec<f32>,
-        message: impl ToString,
-        message_id: u64,
         guild_id: u64,
-    ) -> Result<()> {
-        let db_vec = DbVector::new(vector, message, message_id, guild_id)?;
-        self.client
-            .upsert_points(db_vec)
-            .await
-            .context("Failed to insert vector into database")
-            .map(|_| ())
+        limit: u64,
+    ) -> Result<Vec<DbVector>> {
+        self.check_dimension(&vector)?;
+        let filter = VectorFilter {
+            guild_id: Some(guild_id),
+            ..Default::default()
+        };
+        Ok(self
+            .store
+            .search(vector, &filter, limit)
+            .await?
+            .into_iter()
+            .map(|scored| scored.vector)
+            .collect())
     }
 
-    pub async fn get_close_vectors(
+    /// Similarity search within a guild, optionally narrowed to a channel or author.
+    pub async fn search_vectors(
         &self,
         vector: Vec<f32>,
-        guild_id: &str,
-    ) -> Result<Vec<DbVector>> {
-        let filter = Filter::must(vec![Condition::matches_text(
-            "guild_id",
-            guild_id.to_string(),
-        )]);
-        let search_request = SearchPointsBuilder::new(DB_COLLECTION_NAME, vector, 10)
-            .with_payload(true)
-            .filter(filter)
-            .with_vectors(true);
-        self.client
-            .search_points(search_request)
+        filter: &SearchFilter,
+    ) -> Result<Vec<ScoredVector>> {
+        self.check_dimension(&vector)?;
+        let vector_filter = VectorFilter {
+            guild_id: Some(filter.guild_id),
+            channel_id: filter.channel_id,
+            author_id: filter.author_id,
+            message_ids: None,
+        };
+        self.store
+            .search(vector, &vector_filter, filter.limit)
+            .await
+    }
+
+    pub async fn delete_vectors(&self, filter: &VectorFilter) -> Result<()> {
+        self.store.delete(filter).await
+    }
+
+    pub async fn delete_messages(&self, message_ids: Vec<u64>) -> Result<()> {
+        if message_ids.is_empty() {
+            return Ok(());
+        }
+        self.delete_vectors(&VectorFilter {
+            message_ids: Some(message_ids),
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// Removes every message stored from the channel.
+    pub async fn delete_channel(&self, channel_id: u64) -> Result<()> {
+        self.delete_vectors(&VectorFilter {
+            channel_id: Some(channel_id),
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// Removes every message stored from the guild.
+    pub async fn delete_guild(&self, guild_id: u64) -> Result<()> {
+        self.delete_vectors(&VectorFilter {
+            guild_id: Some(guild_id),
+            ..Default::default()
+        })
+        .await
+    }
+
+    pub async fn scroll_vectors(
+        &self,
+        filter: &VectorFilter,
+        offset: Option<u64>,
+        limit: u32,
+    ) -> Result<ScrollPage> {
+        self.store.scroll(filter, offset, limit).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec_db::store::VectorDistance;
+
+    fn space(collection: &str) -> VectorSpace {
+        VectorSpace {
+            collection: collection.to_string(),
+            dimension: 2,
+            distance: VectorDistance::Cosine,
+        }
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_the_legacy_collection_until_moved() {
+        let path = std::env::temp_dir().join(format!(
+            "chattyrs-legacy-{}-{}.sqlite",
+            std::process::id(),
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+        let options = VectorDBOptions {
+            backend: VectorStoreKind::Local,
+            base_url: String::new(),
+            path: path.to_string_lossy().into_owned(),
+            dimension: None,
+            distance: VectorDistance::Cosine,
+            collection: None,
+            alias: None,
+        };
+        let current = space("messages_model_2");
+
+        LocalStore::open(&path, &current).unwrap();
+        assert!(!VdbHandler::uses_legacy_collection(&options, &current)
+            .await
+            .unwrap());
+
+        std::fs::remove_file(&path).unwrap();
+        LocalStore::open(&path, &space(LEGACY_COLLECTION_NAME)).unwrap();
+        assert!(VdbHandler::uses_legacy_collection(&options, &current)
+            .await
+            .unwrap());
+
+        // Once the reembed binary created the new collection, it is used instead.
+        LocalStore::open(&path, &current).unwrap();
+        assert!(!VdbHandler::uses_legacy_collection(&options, &current)
             .await
-            .context("Failed to search nearby vectors")
-            .map(|res| {
-                res.result
-                    .into_iter()
-                    .map(|point| point.try_into())
-                    .collect::<Result<Vec<DbVector>>>()
-            })?
+            .unwrap());
+        std::fs::remove_file(&path).unwrap();
     }
 }
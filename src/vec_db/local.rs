@@ -0,0 +1,681 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Row};
+
+use super::{
+    store::{ScrollPage, VectorDistance, VectorFilter, VectorSpace, VectorStore},
+    vector::{DbVector, ScoredVector},
+};
+
+/// Records the shape of every collection so a changed embedding model is caught on open, and
+/// the aliases naming them.
+const COLLECTIONS_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS collections (
+    name TEXT PRIMARY KEY,
+    dimension INTEGER NOT NULL,
+    distance TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS aliases (
+    alias TEXT PRIMARY KEY,
+    collection TEXT NOT NULL
+);
+";
+
+const COLUMNS: &str = "message_id, guild_id, channel_id, author_id, timestamp, author_name, \
+                       reply_to_id, has_attachments, has_images, message, vector";
+
+/// Vector store kept in a SQLite file. Searches compare against every vector matching the
+/// filter, which is fast enough for the message volume of a handful of servers.
+pub struct LocalStore {
+    connection: Arc<Mutex<Connection>>,
+    collection: String,
+    /// Quoted name of the table holding the collection.
+    table: String,
+    distance: VectorDistance,
+}
+
+impl LocalStore {
+    /// Opens the store at `path`, creating the file and its directory if needed.
+    pub fn open(path: impl AsRef<Path>, space: &VectorSpace) -> Result<Self> {
+        Self::from_connection(Self::connect(path.as_ref())?, space)
+    }
+
+    /// A store that lives only as long as the process, for tests.
+    pub fn in_memory(space: &VectorSpace) -> Result<Self> {
+        let connection = Connection::open_in_memory()?;
+        connection
+            .execute_batch(COLLECTIONS_SCHEMA)
+            .context("Failed to create vector store tables")?;
+        Self::from_connection(connection, space)
+    }
+
+    /// The shape of the vectors in `collection`, `None` if there is no such collection.
+    pub fn describe(path: impl AsRef<Path>, collection: &str) -> Result<Option<VectorSpace>> {
+        Self::describe_collection(&Self::connect(path.as_ref())?, collection)
+    }
+
+    /// The collection `alias` points at, if the alias exists.
+    pub fn resolve_alias(path: impl AsRef<Path>, alias: &str) -> Result<Option<String>> {
+        Ok(Self::connect(path.as_ref())?
+            .query_row(
+                "SELECT collection FROM aliases WHERE alias = ?",
+                [alias],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    fn connect(path: &Path) -> Result<Connection> {
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+        }
+        let connection = Connection::open(path)
+            .with_context(|| format!("Failed to open vector store at {}", path.display()))?;
+        connection
+            .execute_batch(COLLECTIONS_SCHEMA)
+            .context("Failed to create vector store tables")?;
+        Ok(connection)
+    }
+
+    fn describe_collection(
+        connection: &Connection,
+        collection: &str,
+    ) -> Result<Option<VectorSpace>> {
+        let stored: Option<(u64, String)> = connection
+            .query_row(
+                "SELECT dimension, distance FROM collections WHERE name = ?",
+                [collection],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        stored
+            .map(|(dimension, distance)| {
+                Ok(VectorSpace {
+                    collection: collection.to_string(),
+                    dimension,
+                    distance: distance.parse()?,
+                })
+            })
+            .transpose()
+    }
+
+    fn from_connection(connection: Connection, space: &VectorSpace) -> Result<Self> {
+        match Self::describe_collection(&connection, &space.collection)? {
+            Some(existing) => space.check_matches(&existing)?,
+            None => {
+                connection.execute(
+                    "INSERT INTO collections (name, dimension, distance) VALUES (?, ?, ?)",
+                    params![space.collection, space.dimension, space.distance.as_str()],
+                )?;
+            }
+        }
+
+        let table = format!("\"{}\"", space.collection.replace('"', "\"\""));
+        connection
+            .execute_batch(&format!(
+                "CREATE TABLE IF NOT EXISTS {table} (
+                    message_id INTEGER PRIMARY KEY,
+                    guild_id INTEGER NOT NULL,
+                    channel_id INTEGER,
+                    author_id INTEGER,
+                    timestamp INTEGER,
+                    author_name TEXT,
+                    reply_to_id INTEGER,
+                    has_attachments INTEGER NOT NULL,
+                    has_images INTEGER NOT NULL,
+                    message TEXT NOT NULL,
+                    vector BLOB NOT NULL
+                );
+                CREATE INDEX IF NOT EXISTS {guild_channel} ON {table} (guild_id, channel_id);
+                CREATE INDEX IF NOT EXISTS {guild_author} ON {table} (guild_id, author_id);",
+                guild_channel = index_name(&space.collection, "guild_channel"),
+                guild_author = index_name(&space.collection, "guild_author"),
+            ))
+            .context("Failed to create vector store tables")?;
+
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+            collection: space.collection.clone(),
+            table,
+            distance: space.distance,
+        })
+    }
+
+    /// Runs `query` on a blocking thread so SQLite never stalls the async runtime.
+    async fn with_connection<T, F>(&self, query: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut connection = connection
+                .lock()
+                .map_err(|_| anyhow!("Vector store connection poisoned"))?;
+            query(&mut connection)
+        })
+        .await
+        .context("Vector store task failed")?
+    }
+}
+
+/// SQL condition and parameters selecting the rows matching `filter`.
+fn to_where_clause(filter: &VectorFilter) -> (String, Vec<i64>) {
+    let mut conditions = vec!["1 = 1".to_string()];
+    let mut params = Vec::new();
+    for (column, value) in [
+        ("guild_id", filter.guild_id),
+        ("channel_id", filter.channel_id),
+        ("author_id", filter.author_id),
+    ] {
+        if let Some(value) = value {
+            conditions.push(format!("{column} = ?"));
+            params.push(value as i64);
+        }
+    }
+    if let Some(message_ids) = &filter.message_ids {
+        let placeholders = vec!["?"; message_ids.len()].join(", ");
+        conditions.push(format!("message_id IN ({placeholders})"));
+        params.extend(message_ids.iter().map(|message_id| *message_id as i64));
+    }
+    (conditions.join(" AND "), params)
+}
+
+fn to_blob(vector: &[f32]) -> Vec<u8> {
+    vector
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect()
+}
+
+fn from_blob(blob: &[u8]) -> Vec<f32> {
+    blob.chunks_exact(4)
+        .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect()
+}
+
+fn index_name(collection: &str, suffix: &str) -> String {
+    format!("\"{}_{suffix}\"", collection.replace('"', "\"\""))
+}
+
+/// Scores `b` against `a` the way qdrant does for `distance`.
+fn score(distance: VectorDistance, a: &[f32], b: &[f32]) -> f32 {
+    let dot = || a.iter().zip(b).map(|(a, b)| a * b).sum::<f32>();
+    match distance {
+        VectorDistance::Cosine => {
+            let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+            let norms = norm(a) * norm(b);
+            if norms == 0.0 {
+                0.0
+            } else {
+                dot() / norms
+            }
+        }
+        VectorDistance::Dot => dot(),
+        VectorDistance::Euclid => a
+            .iter()
+            .zip(b)
+            .map(|(a, b)| (a - b).powi(2))
+            .sum::<f32>()
+            .sqrt(),
+    }
+}
+
+fn read_row(row: &Row) -> rusqlite::Result<DbVector> {
+    Ok(DbVector {
+        message_id: row.get::<_, i64>(0)? as u64,
+        guild_id: row.get::<_, i64>(1)? as u64,
+        channel_id: row.get::<_, Option<i64>>(2)?.map(|id| id as u64),
+        author_id: row.get::<_, Option<i64>>(3)?.map(|id| id as u64),
+        timestamp: row.get(4)?,
+        author_name: row.get(5)?,
+        reply_to_id: row.get::<_, Option<i64>>(6)?.map(|id| id as u64),
+        has_attachments: row.get(7)?,
+        has_images: row.get(8)?,
+        message: row.get(9)?,
+        vector: from_blob(&row.get::<_, Vec<u8>>(10)?),
+    })
+}
+
+#[async_trait]
+impl VectorStore for LocalStore {
+    async fn upsert(&self, vectors: Vec<DbVector>) -> Result<()> {
+        let table = self.table.clone();
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            {
+                let mut statement = transaction.prepare_cached(&format!(
+                    "INSERT OR REPLACE INTO {table} ({COLUMNS}) \
+                     VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+                ))?;
+                for vector in vectors {
+                    statement.execute(params![
+                        vector.message_id as i64,
+                        vector.guild_id as i64,
+                        vector.channel_id.map(|id| id as i64),
+                        vector.author_id.map(|id| id as i64),
+                        vector.timestamp,
+                        vector.author_name,
+                        vector.reply_to_id.map(|id| id as i64),
+                        vector.has_attachments,
+                        vector.has_images,
+                        vector.message,
+                        to_blob(&vector.vector),
+                    ])?;
+                }
+            }
+            transaction.commit()?;
+            Ok(())
+        })
+        .await
+        .context("Failed to insert vector into database")
+    }
+
+    async fn search(
+        &self,
+        vector: Vec<f32>,
+        filter: &VectorFilter,
+        limit: u64,
+    ) -> Result<Vec<ScoredVector>> {
+        let (condition, params) = to_where_clause(filter);
+        let table = self.table.clone();
+        let distance = self.distance;
+        self.with_connection(move |connection| {
+            let mut statement =
+                connection.prepare(&format!("SELECT {COLUMNS} FROM {table} WHERE {condition}"))?;
+            let mut scored = statement
+                .query_map(params_from_iter(params), read_row)?
+                .map(|row| {
+                    row.map(|stored| ScoredVector {
+                        score: score(distance, &vector, &stored.vector),
+                        vector: stored,
+                    })
+                })
+                .collect::<rusqlite::Result<Vec<ScoredVector>>>()?;
+            // Closest first, as qdrant returns them.
+            match distance {
+                VectorDistance::Euclid => scored.sort_by(|a, b| a.score.total_cmp(&b.score)),
+                VectorDistance::Cosine | VectorDistance::Dot => {
+                    scored.sort_by(|a, b| b.score.total_cmp(&a.score))
+                }
+            }
+            scored.truncate(limit as usize);
+            Ok(scored)
+        })
+        .await
+        .context("Failed to search nearby vectors")
+    }
+
+    async fn point_alias(&self, alias: &str) -> Result<()> {
+        let alias = alias.to_string();
+        let collection = self.collection.clone();
+        self.with_connection(move |connection| {
+            connection.execute(
+                "INSERT OR REPLACE INTO aliases (alias, collection) VALUES (?, ?)",
+                params![alias, collection],
+            )?;
+            Ok(())
+        })
+        .await
+        .context("Failed to point alias")
+    }
+
+    async fn delete(&self, filter: &VectorFilter) -> Result<()> {
+        if filter.is_empty() {
+            return Err(anyhow!("Refusing to delete vectors without a filter"));
+        }
+        let (condition, params) = to_where_clause(filter);
+        let table = self.table.clone();
+        self.with_connection(move |connection| {
+            connection.execute(
+                &format!("DELETE FROM {table} WHERE {condition}"),
+                params_from_iter(params),
+            )?;
+            Ok(())
+        })
+        .await
+        .context("Failed to delete vectors")
+    }
+
+    async fn scroll(
+        &self,
+        filter: &VectorFilter,
+        offset: Option<u64>,
+        limit: u32,
+    ) -> Result<ScrollPage> {
+        let (condition, mut params) = to_where_clause(filter);
+        params.push(offset.unwrap_or_default() as i64);
+        // One extra row tells whether another page follows.
+        params.push(limit as i64 + 1);
+        let table = self.table.clone();
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare(&format!(
+                "SELECT {COLUMNS} FROM {table} WHERE {condition} AND message_id >= ? \
+                 ORDER BY message_id LIMIT ?"
+            ))?;
+            let mut vectors = statement
+                .query_map(params_from_iter(params), read_row)?
+                .collect::<rusqlite::Result<Vec<DbVector>>>()?;
+            let next_offset = if vectors.len() > limit as usize {
+                vectors.pop().map(|vector| vector.message_id)
+            } else {
+                None
+            };
+            Ok(ScrollPage {
+                vectors,
+                next_offset,
+            })
+        })
+        .await
+        .context("Failed to scroll vectors")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    fn space(distance: VectorDistance) -> VectorSpace {
+        VectorSpace {
+            collection: "messages_test_2".to_string(),
+            dimension: 2,
+            distance,
+        }
+    }
+
+    fn store(distance: VectorDistance) -> LocalStore {
+        LocalStore::in_memory(&space(distance)).unwrap()
+    }
+
+    fn vector(message_id: u64, guild_id: u64, channel_id: u64, author_id: u64) -> DbVector {
+        DbVector {
+            vector: vec![message_id as f32, 1.0],
+            message: format!("message {message_id}"),
+            message_id,
+            guild_id,
+            channel_id: Some(channel_id),
+            author_id: Some(author_id),
+            timestamp: Some(1_700_000_000),
+            author_name: Some(format!("user {author_id}")),
+            reply_to_id: None,
+            has_attachments: false,
+            has_images: false,
+        }
+    }
+
+    fn with_embedding(mut vector: DbVector, embedding: [f32; 2]) -> DbVector {
+        vector.vector = embedding.to_vec();
+        vector
+    }
+
+    async fn stored_ids(store: &LocalStore, filter: &VectorFilter) -> Vec<u64> {
+        store
+            .scroll(filter, None, 100)
+            .await
+            .unwrap()
+            .vectors
+            .into_iter()
+            .map(|vector| vector.message_id)
+            .collect()
+    }
+
+    fn search_ids(results: &[ScoredVector]) -> Vec<u64> {
+        results
+            .iter()
+            .map(|result| result.vector.message_id)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn upsert_replaces_by_message_id() {
+        let store = store(VectorDistance::Euclid);
+        store
+            .upsert(vec![vector(1, 10, 100, 1000), vector(2, 10, 100, 1000)])
+            .await
+            .unwrap();
+        let mut edited = vector(1, 10, 100, 1000);
+        edited.message = "edited".to_string();
+        edited.vector = vec![9.0, 9.0];
+        edited.reply_to_id = Some(2);
+        store.upsert(vec![edited]).await.unwrap();
+
+        let page = store
+            .scroll(&VectorFilter::default(), None, 100)
+            .await
+            .unwrap();
+        assert_eq!(page.vectors.len(), 2);
+        let stored = &page.vectors[0];
+        assert_eq!(stored.message_id, 1);
+        assert_eq!(stored.message, "edited");
+        assert_eq!(stored.vector, [9.0, 9.0]);
+        assert_eq!(stored.reply_to_id, Some(2));
+        assert_eq!(stored.author_name.as_deref(), Some("user 1000"));
+    }
+
+    #[tokio::test]
+    async fn euclid_search_returns_nearest_first() {
+        let store = store(VectorDistance::Euclid);
+        store
+            .upsert(vec![
+                with_embedding(vector(1, 10, 100, 1000), [0.0, 0.0]),
+                with_embedding(vector(2, 10, 100, 1000), [3.0, 4.0]),
+                with_embedding(vector(3, 10, 100, 1000), [1.0, 0.0]),
+            ])
+            .await
+            .unwrap();
+
+        let results = store
+            .search(vec![0.0, 0.0], &VectorFilter::default(), 10)
+            .await
+            .unwrap();
+        assert_eq!(search_ids(&results), [1, 3, 2]);
+        assert_eq!(results[0].score, 0.0);
+        assert_eq!(results[2].score, 5.0);
+    }
+
+    #[tokio::test]
+    async fn cosine_search_ranks_by_angle_not_length() {
+        let store = store(VectorDistance::Cosine);
+        store
+            .upsert(vec![
+                with_embedding(vector(1, 10, 100, 1000), [0.0, 1.0]),
+                with_embedding(vector(2, 10, 100, 1000), [10.0, 0.0]),
+                with_embedding(vector(3, 10, 100, 1000), [1.0, 1.0]),
+                // A zero vector has no direction and scores zero rather than NaN.
+                with_embedding(vector(4, 10, 100, 1000), [0.0, 0.0]),
+            ])
+            .await
+            .unwrap();
+
+        let results = store
+            .search(vec![1.0, 0.0], &VectorFilter::default(), 10)
+            .await
+            .unwrap();
+        assert_eq!(search_ids(&results), [2, 3, 1, 4]);
+        assert!((results[0].score - 1.0).abs() < 1e-6);
+        assert!((results[1].score - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-6);
+        assert_eq!(results[3].score, 0.0);
+    }
+
+    #[tokio::test]
+    async fn dot_search_ranks_by_product() {
+        let store = store(VectorDistance::Dot);
+        store
+            .upsert(vec![
+                with_embedding(vector(1, 10, 100, 1000), [1.0, 0.0]),
+                with_embedding(vector(2, 10, 100, 1000), [10.0, 0.0]),
+                with_embedding(vector(3, 10, 100, 1000), [-5.0, 0.0]),
+            ])
+            .await
+            .unwrap();
+
+        let results = store
+            .search(vec![2.0, 0.0], &VectorFilter::default(), 2)
+            .await
+            .unwrap();
+        assert_eq!(search_ids(&results), [2, 1]);
+        assert_eq!(results[0].score, 20.0);
+    }
+
+    #[tokio::test]
+    async fn filters_select_matching_vectors() {
+        let store = store(VectorDistance::Euclid);
+        store
+            .upsert(vec![
+                vector(1, 10, 100, 1000),
+                vector(2, 10, 100, 2000),
+                vector(3, 10, 200, 1000),
+                vector(4, 20, 300, 1000),
+            ])
+            .await
+            .unwrap();
+
+        let guild = VectorFilter {
+            guild_id: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(stored_ids(&store, &guild).await, [1, 2, 3]);
+        let channel = VectorFilter {
+            guild_id: Some(10),
+            channel_id: Some(100),
+            ..Default::default()
+        };
+        assert_eq!(stored_ids(&store, &channel).await, [1, 2]);
+        let author = VectorFilter {
+            author_id: Some(1000),
+            ..Default::default()
+        };
+        assert_eq!(stored_ids(&store, &author).await, [1, 3, 4]);
+        let combined = VectorFilter {
+            guild_id: Some(10),
+            author_id: Some(1000),
+            message_ids: Some(vec![3, 4, 5]),
+            ..Default::default()
+        };
+        assert_eq!(stored_ids(&store, &combined).await, [3]);
+        let no_ids = VectorFilter {
+            message_ids: Some(vec![]),
+            ..Default::default()
+        };
+        assert!(stored_ids(&store, &no_ids).await.is_empty());
+
+        // Searches apply the same filters, so one guild never sees another's messages.
+        let results = store.search(vec![4.0, 1.0], &guild, 10).await.unwrap();
+        assert_eq!(search_ids(&results), [3, 2, 1]);
+    }
+
+    #[tokio::test]
+    async fn delete_removes_only_matching_vectors() {
+        let store = store(VectorDistance::Euclid);
+        store
+            .upsert(vec![
+                vector(1, 10, 100, 1000),
+                vector(2, 10, 200, 1000),
+                vector(3, 20, 300, 2000),
+            ])
+            .await
+            .unwrap();
+
+        store
+            .delete(&VectorFilter {
+                channel_id: Some(100),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(stored_ids(&store, &VectorFilter::default()).await, [2, 3]);
+
+        store
+            .delete(&VectorFilter {
+                author_id: Some(1000),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(stored_ids(&store, &VectorFilter::default()).await, [3]);
+    }
+
+    #[tokio::test]
+    async fn delete_refuses_an_empty_filter() {
+        let store = store(VectorDistance::Euclid);
+        store.upsert(vec![vector(1, 10, 100, 1000)]).await.unwrap();
+
+        assert!(store.delete(&VectorFilter::default()).await.is_err());
+        assert_eq!(stored_ids(&store, &VectorFilter::default()).await, [1]);
+    }
+
+    #[tokio::test]
+    async fn scroll_pages_through_every_vector() {
+        let store = store(VectorDistance::Euclid);
+        store
+            .upsert((1..=7).map(|id| vector(id, 10, 100, 1000)).collect())
+            .await
+            .unwrap();
+
+        let mut pages = Vec::new();
+        let mut offset = None;
+        loop {
+            let page = store
+                .scroll(&VectorFilter::default(), offset, 3)
+                .await
+                .unwrap();
+            pages.push(
+                page.vectors
+                    .iter()
+                    .map(|vector| vector.message_id)
+                    .collect::<Vec<_>>(),
+            );
+            offset = page.next_offset;
+            if offset.is_none() {
+                break;
+            }
+        }
+        assert_eq!(pages, [vec![1, 2, 3], vec![4, 5, 6], vec![7]]);
+    }
+
+    #[tokio::test]
+    async fn scroll_of_an_exact_page_has_no_next_offset() {
+        let store = store(VectorDistance::Euclid);
+        store
+            .upsert((1..=3).map(|id| vector(id, 10, 100, 1000)).collect())
+            .await
+            .unwrap();
+
+        let page = store
+            .scroll(&VectorFilter::default(), None, 3)
+            .await
+            .unwrap();
+        assert_eq!(page.vectors.len(), 3);
+        assert_eq!(page.next_offset, None);
+    }
+
+    #[tokio::test]
+    async fn existing_ids_reports_stored_messages() {
+        let store = store(VectorDistance::Euclid);
+        store
+            .upsert(vec![vector(1, 10, 100, 1000), vector(3, 10, 100, 1000)])
+            .await
+            .unwrap();
+
+        let existing = store.existing_ids(vec![1, 2, 3]).await.unwrap();
+        assert_eq!(existing, HashSet::from([1, 3]));
+        assert!(store.existing_ids(vec![]).await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn blobs_round_trip() {
+        let vector = vec![0.0, -1.5, f32::MAX, 1e-9];
+        assert_eq!(from_blob(&to_blob(&vector)), vector);
+    }
+}
@@ -0,0 +1,152 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    db_handler::VdbHandler,
+    store::{VectorFilter, VectorStore},
+    vector::DbVector,
+};
+use crate::{environment::Environment, llm::backend::EmbedBackend};
+
+/// Where a migration got to, saved after every batch so an interrupted run picks up there.
+#[derive(Debug, Serialize, Deserialize, Default)]
+struct MigrationProgress {
+    next_offset: Option<u64>,
+    migrated: u64,
+}
+
+/// Re-embeds every message in the `source` collection with the configured embedding model into
+/// the collection that model writes to, then points `vdb.alias` at the new collection.
+///
+/// Messages already in the target collection are skipped, so rerunning after the bot kept
+/// writing to the old collection only moves over the new messages.
+pub async fn reembed_collection(
+    env: &Environment,
+    embed: &dyn EmbedBackend,
+    source: &str,
+    batch_size: u32,
+) -> Result<()> {
+    let source_space = VdbHandler::describe_collection(&env.vdb, source)
+        .await?
+        .with_context(|| format!("Collection {source} does not exist"))?;
+    let target_space = VdbHandler::resolve_space(&env.vdb, &env.llm.embed_model, embed).await?;
+    if target_space.collection == source {
+        return Err(anyhow!(
+            "Collection {source} is already the collection for {}",
+            env.llm.embed_model
+        ));
+    }
+    println!(
+        "Re-embedding {source} into {} ({} dimensions, {})",
+        target_space.collection,
+        target_space.dimension,
+        target_space.distance.as_str()
+    );
+
+    let source_store = VdbHandler::open_store(&env.vdb, &source_space).await?;
+    let target_store = VdbHandler::open_store(&env.vdb, &target_space).await?;
+
+    let progress_path = progress_path(&env.vdb.path, source, &target_space.collection);
+    let mut progress = load_progress(&progress_path)?;
+    if progress.next_offset.is_some() {
+        println!(
+            "Resuming after {} migrated messages, from message {}",
+            progress.migrated,
+            progress.next_offset.unwrap_or_default()
+        );
+    }
+
+    loop {
+        let page = source_store
+            .scroll(&VectorFilter::default(), progress.next_offset, batch_size)
+            .await?;
+
+        let pending = missing_from(target_store.as_ref(), page.vectors).await?;
+        let contents: Vec<String> = pending
+            .iter()
+            .map(|vector| vector.message.clone())
+            .collect();
+        let embeddings = embed
+            .get_embeds(&contents)
+            .await
+            .context("Failed to embed messages")?;
+        let mut batch = Vec::with_capacity(pending.len());
+        for (vector, embedding) in pending.into_iter().zip(embeddings) {
+            if embedding.len() as u64 != target_space.dimension {
+                return Err(anyhow!(
+                    "Embedding model returned {} dimensions, expected {}",
+                    embedding.len(),
+                    target_space.dimension
+                ));
+            }
+            batch.push(DbVector {
+                vector: embedding,
+                ..vector
+            });
+        }
+
+        progress.migrated += batch.len() as u64;
+        target_store.upsert(batch).await?;
+        progress.next_offset = page.next_offset;
+        save_progress(&progress_path, &progress)?;
+        println!("Migrated {} messages", progress.migrated);
+
+        if page.next_offset.is_none() {
+            break;
+        }
+    }
+
+    if let Some(alias) = &env.vdb.alias {
+        target_store.point_alias(alias).await?;
+        println!("Alias {alias} now points at {}", target_space.collection);
+    }
+    std::fs::remove_file(&progress_path).with_context(|| {
+        format!(
+            "Failed to remove migration progress {}",
+            progress_path.display()
+        )
+    })?;
+    Ok(())
+}
+
+/// The vectors of `vectors` whose message is not yet stored in `target`.
+async fn missing_from(target: &dyn VectorStore, vectors: Vec<DbVector>) -> Result<Vec<DbVector>> {
+    let existing = target
+        .existing_ids(vectors.iter().map(|vector| vector.message_id).collect())
+        .await?;
+    Ok(vectors
+        .into_iter()
+        .filter(|vector| !existing.contains(&vector.message_id))
+        .collect())
+}
+
+/// Progress files are kept next to the local vector store, whichever backend is in use.
+fn progress_path(store_path: &str, source: &str, target: &str) -> PathBuf {
+    Path::new(store_path)
+        .parent()
+        .unwrap_or(Path::new(""))
+        .join(format!("migration-{source}-to-{target}.json"))
+}
+
+fn load_progress(path: &Path) -> Result<MigrationProgress> {
+    if !path.exists() {
+        return Ok(MigrationProgress::default());
+    }
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read migration progress {}", path.display()))?;
+    serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse migration progress {}", path.display()))
+}
+
+fn save_progress(path: &Path, progress: &MigrationProgress) -> Result<()> {
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, serde_json::to_string(progress)?)
+        .with_context(|| format!("Failed to save migration progress {}", path.display()))
+}
@@ -1,5 +1,10 @@
 pub mod db_handler;
+pub mod local;
+pub mod migration;
+pub mod qdrant;
+pub mod store;
 pub mod vector;
 
-const DB_COLLECTION_NAME: &str = "messages";
-const DB_VEC_LENGTH: u64 = 1024;
+/// Collection every message was stored in before collections were named after the embedding
+/// model.
+const LEGACY_COLLECTION_NAME: &str = "messages";
@@ -0,0 +1,334 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use qdrant_client::{
+    qdrant::{
+        vectors_config::Config, Condition, CreateAliasBuilder, CreateCollectionBuilder,
+        CreateFieldIndexCollectionBuilder, DeletePointsBuilder, Distance, FieldType, Filter,
+        PointId, PointStruct, PointsIdsList, Range, ScrollPointsBuilder, SearchPointsBuilder,
+        SetPayloadPointsBuilder, UpsertPointsBuilder, VectorParamsBuilder,
+    },
+    Payload, Qdrant,
+};
+
+use super::{
+    store::{ScrollPage, VectorDistance, VectorFilter, VectorSpace, VectorStore},
+    vector::{DbVector, ScoredVector},
+};
+use crate::environment::VectorDBOptions;
+
+/// Payload fields searches filter on.
+const PAYLOAD_INDEXES: [(&str, FieldType); 7] = [
+    ("guild_id", FieldType::Integer),
+    ("channel_id", FieldType::Integer),
+    ("author_id", FieldType::Integer),
+    ("author_name", FieldType::Keyword),
+    ("timestamp", FieldType::Integer),
+    ("reply_to_id", FieldType::Integer),
+    ("has_attachments", FieldType::Bool),
+];
+
+/// Points read per page while migrating payloads.
+const MIGRATION_PAGE_SIZE: u32 = 256;
+
+pub struct QdrantStore {
+    client: Qdrant,
+    collection: String,
+}
+
+impl QdrantStore {
+    pub async fn new(options: &VectorDBOptions, space: &VectorSpace) -> Result<Self> {
+        let client = Self::connect(options)?;
+
+        Self::initialise_collection(&client, space).await?;
+
+        Ok(Self {
+            client,
+            collection: space.collection.clone(),
+        })
+    }
+
+    fn connect(options: &VectorDBOptions) -> Result<Qdrant> {
+        Qdrant::from_url(&options.base_url)
+            .build()
+            .context("Failed to build qdrant client")
+    }
+
+    /// The shape of the vectors in `collection`, `None` if there is no such collection.
+    pub async fn describe(
+        options: &VectorDBOptions,
+        collection: &str,
+    ) -> Result<Option<VectorSpace>> {
+        let client = Self::connect(options)?;
+        if !client.collection_exists(collection).await? {
+            return Ok(None);
+        }
+        Self::describe_collection(&client, collection)
+            .await
+            .map(Some)
+    }
+
+    /// The collection `alias` points at, if the alias exists.
+    pub async fn resolve_alias(options: &VectorDBOptions, alias: &str) -> Result<Option<String>> {
+        Ok(Self::connect(options)?
+            .list_aliases()
+            .await
+            .context("Failed to list collection aliases")?
+            .aliases
+            .into_iter()
+            .find(|description| description.alias_name == alias)
+            .map(|description| description.collection_name))
+    }
+
+    async fn initialise_collection(client: &Qdrant, space: &VectorSpace) -> Result<()> {
+        let collection = space.collection.as_str();
+        if client.collection_exists(collection).await? {
+            space.check_matches(&Self::describe_collection(client, collection).await?)?;
+        } else {
+            let vectors_config =
+                VectorParamsBuilder::new(space.dimension, to_qdrant_distance(space.distance));
+            client
+                .create_collection(
+                    CreateCollectionBuilder::new(collection).vectors_config(vectors_config),
+                )
+                .await?;
+        }
+
+        Self::migrate_guild_ids(client, collection).await?;
+
+        // Creating an index that already exists is a no-op, so collections made before a field
+        // was indexed pick it up on the next start.
+        for (field_name, field_type) in PAYLOAD_INDEXES {
+            client
+                .create_field_index(CreateFieldIndexCollectionBuilder::new(
+                    collection, field_name, field_type,
+                ))
+                .await
+                .with_context(|| format!("Failed to create payload index on {field_name}"))?;
+        }
+        Ok(())
+    }
+
+    async fn describe_collection(client: &Qdrant, collection: &str) -> Result<VectorSpace> {
+        let params = client
+            .collection_info(collection)
+            .await?
+            .result
+            .and_then(|info| info.config)
+            .and_then(|config| config.params)
+            .and_then(|params| params.vectors_config)
+            .and_then(|vectors_config| vectors_config.config);
+        let Some(Config::Params(params)) = params else {
+            return Err(anyhow!(
+                "Collection {collection} does not hold a single unnamed vector"
+            ));
+        };
+        let distance = match Distance::try_from(params.distance) {
+            Ok(Distance::Cosine) => VectorDistance::Cosine,
+            Ok(Distance::Dot) => VectorDistance::Dot,
+            Ok(Distance::Euclid) => VectorDistance::Euclid,
+            _ => {
+                return Err(anyhow!(
+                    "Collection {collection} uses an unsupported distance metric"
+                ))
+            }
+        };
+        Ok(VectorSpace {
+            collection: collection.to_string(),
+            dimension: params.size,
+            distance,
+        })
+    }
+
+    /// Points stored by earlier versions hold `guild_id` as a string, which the integer filters
+    /// used for tenant isolation never match. Rewrites them as integers; once every point is
+    /// migrated this finds nothing, so it is cheap to run on every start.
+    async fn migrate_guild_ids(client: &Qdrant, collection: &str) -> Result<()> {
+        let not_integer = Filter::must_not(vec![Condition::range(
+            "guild_id",
+            Range {
+                gte: Some(0.0),
+                ..Default::default()
+            },
+        )]);
+
+        let mut offset: Option<PointId> = None;
+        let mut migrated = 0;
+        loop {
+            let mut scroll = ScrollPointsBuilder::new(collection)
+                .filter(not_integer.clone())
+                .limit(MIGRATION_PAGE_SIZE)
+                .with_payload(true)
+                .with_vectors(false);
+            if let Some(offset) = offset.take() {
+                scroll = scroll.offset(offset);
+            }
+            let page = client
+                .scroll(scroll)
+                .await
+                .context("Failed to scroll points to migrate")?;
+
+            let mut points_by_guild: HashMap<i64, Vec<PointId>> = HashMap::new();
+            for point in page.result {
+                let guild_id = point
+                    .payload
+                    .get("guild_id")
+                    .and_then(|guild_id| guild_id.as_str())
+                    .and_then(|guild_id| guild_id.parse::<i64>().ok());
+                match (guild_id, point.id) {
+                    (Some(guild_id), Some(point_id)) => {
+                        points_by_guild.entry(guild_id).or_default().push(point_id)
+                    }
+                    (None, point_id) => {
+                        println!("Skipping point {point_id:?} without a valid guild id")
+                    }
+                    _ => {}
+                }
+            }
+
+            for (guild_id, point_ids) in points_by_guild {
+                migrated += point_ids.len();
+                client
+                    .set_payload(
+                        SetPayloadPointsBuilder::new(
+                            collection,
+                            Payload::from([("guild_id", guild_id.into())]),
+                        )
+                        .points_selector(PointsIdsList { ids: point_ids })
+                        .wait(true),
+                    )
+                    .await
+                    .context("Failed to migrate guild id payload")?;
+            }
+
+            match page.next_page_offset {
+                Some(next_page_offset) => offset = Some(next_page_offset),
+                None => break,
+            }
+        }
+
+        if migrated > 0 {
+            println!("Migrated {migrated} points to integer guild ids");
+        }
+        Ok(())
+    }
+}
+
+fn to_qdrant_distance(distance: VectorDistance) -> Distance {
+    match distance {
+        VectorDistance::Cosine => Distance::Cosine,
+        VectorDistance::Dot => Distance::Dot,
+        VectorDistance::Euclid => Distance::Euclid,
+    }
+}
+
+fn to_qdrant_filter(filter: &VectorFilter) -> Filter {
+    let mut conditions = Vec::new();
+    if let Some(guild_id) = filter.guild_id {
+        conditions.push(Condition::matches("guild_id", guild_id as i64));
+    }
+    if let Some(channel_id) = filter.channel_id {
+        conditions.push(Condition::matches("channel_id", channel_id as i64));
+    }
+    if let Some(author_id) = filter.author_id {
+        conditions.push(Condition::matches("author_id", author_id as i64));
+    }
+    if let Some(message_ids) = &filter.message_ids {
+        conditions.push(Condition::has_id(message_ids.iter().copied()));
+    }
+    Filter::must(conditions)
+}
+
+#[async_trait]
+impl VectorStore for QdrantStore {
+    async fn upsert(&self, vectors: Vec<DbVector>) -> Result<()> {
+        let points: Vec<PointStruct> = vectors.into_iter().map(PointStruct::from).collect();
+        self.client
+            .upsert_points(UpsertPointsBuilder::new(&self.collection, points))
+            .await
+            .context("Failed to insert vector into database")
+            .map(|_| ())
+    }
+
+    async fn search(
+        &self,
+        vector: Vec<f32>,
+        filter: &VectorFilter,
+        limit: u64,
+    ) -> Result<Vec<ScoredVector>> {
+        let search_request = SearchPointsBuilder::new(&self.collection, vector, limit)
+            .with_payload(true)
+            .filter(to_qdrant_filter(filter))
+            .with_vectors(true);
+        self.client
+            .search_points(search_request)
+            .await
+            .context("Failed to search nearby vectors")
+            .map(|res| {
+                res.result
+                    .into_iter()
+                    .map(|point| point.try_into())
+                    .collect::<Result<Vec<ScoredVector>>>()
+            })?
+    }
+
+    async fn point_alias(&self, alias: &str) -> Result<()> {
+        // Creating an alias that already exists repoints it, which qdrant applies atomically.
+        self.client
+            .create_alias(CreateAliasBuilder::new(&self.collection, alias))
+            .await
+            .with_context(|| format!("Failed to point alias {alias} at {}", self.collection))
+            .map(|_| ())
+    }
+
+    async fn delete(&self, filter: &VectorFilter) -> Result<()> {
+        if filter.is_empty() {
+            return Err(anyhow!("Refusing to delete vectors without a filter"));
+        }
+        self.client
+            .delete_points(
+                DeletePointsBuilder::new(&self.collection)
+                    .points(to_qdrant_filter(filter))
+                    .wait(true),
+            )
+            .await
+            .context("Failed to delete vectors")
+            .map(|_| ())
+    }
+
+    async fn scroll(
+        &self,
+        filter: &VectorFilter,
+        offset: Option<u64>,
+        limit: u32,
+    ) -> Result<ScrollPage> {
+        let mut scroll = ScrollPointsBuilder::new(&self.collection)
+            .filter(to_qdrant_filter(filter))
+            .limit(limit)
+            .with_payload(true)
+            .with_vectors(true);
+        if let Some(offset) = offset {
+            scroll = scroll.offset(offset);
+        }
+        let page = self
+            .client
+            .scroll(scroll)
+            .await
+            .context("Failed to scroll vectors")?;
+        Ok(ScrollPage {
+            vectors: page
+                .result
+                .into_iter()
+                .map(|point| point.try_into())
+                .collect::<Result<Vec<DbVector>>>()?,
+            next_offset: page
+                .next_page_offset
+                .and_then(|point_id| point_id.point_id_options)
+                .and_then(|point_id| match point_id {
+                    qdrant_client::qdrant::point_id::PointIdOptions::Num(id) => Some(id),
+                    _ => None,
+                }),
+        })
+    }
+}
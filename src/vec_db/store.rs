@@ -0,0 +1,171 @@
+use std::{collections::HashSet, str::FromStr};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use super::vector::{DbVector, ScoredVector};
+
+/// Where the bot keeps its message vectors.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum VectorStoreKind {
+    /// A qdrant server, see `docker-compose.yml`.
+    #[default]
+    Qdrant,
+    /// An embedded store in a local file, needing no external services.
+    Local,
+}
+
+/// How closeness between two vectors is measured.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum VectorDistance {
+    /// Cosine similarity, higher scores are closer.
+    Cosine,
+    /// Dot product, higher scores are closer.
+    Dot,
+    /// Euclidean distance, lower scores are closer.
+    #[default]
+    Euclid,
+}
+
+/// The collection vectors are stored in and the shape every vector in it has.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VectorSpace {
+    pub collection: String,
+    pub dimension: u64,
+    pub distance: VectorDistance,
+}
+
+/// Payload conditions a stored message has to meet. Every condition that is set must hold.
+#[derive(Debug, Clone, Default)]
+pub struct VectorFilter {
+    pub guild_id: Option<u64>,
+    pub channel_id: Option<u64>,
+    pub author_id: Option<u64>,
+    pub message_ids: Option<Vec<u64>>,
+}
+
+/// One page of a scroll through the store, ordered by message id.
+pub struct ScrollPage {
+    pub vectors: Vec<DbVector>,
+    /// Message id to continue the scroll from, `None` once the end is reached.
+    pub next_offset: Option<u64>,
+}
+
+#[async_trait]
+pub trait VectorStore: Send + Sync {
+    /// Inserts the vectors, replacing any stored under the same message id.
+    async fn upsert(&self, vectors: Vec<DbVector>) -> Result<()>;
+
+    /// The `limit` vectors closest to `vector` among those matching `filter`, closest first.
+    async fn search(
+        &self,
+        vector: Vec<f32>,
+        filter: &VectorFilter,
+        limit: u64,
+    ) -> Result<Vec<ScoredVector>>;
+
+    /// Points `alias` at this store's collection. An existing alias is switched over in a single
+    /// step, so anything reading through it never finds it missing.
+    async fn point_alias(&self, alias: &str) -> Result<()>;
+
+    /// Removes every vector matching `filter`. An empty filter is rejected rather than
+    /// clearing the store.
+    async fn delete(&self, filter: &VectorFilter) -> Result<()>;
+
+    async fn scroll(
+        &self,
+        filter: &VectorFilter,
+        offset: Option<u64>,
+        limit: u32,
+    ) -> Result<ScrollPage>;
+
+    /// The ids among `message_ids` that have a vector stored.
+    async fn existing_ids(&self, message_ids: Vec<u64>) -> Result<HashSet<u64>> {
+        if message_ids.is_empty() {
+            return Ok(HashSet::new());
+        }
+        let limit = message_ids.len() as u32;
+        let filter = VectorFilter {
+            message_ids: Some(message_ids),
+            ..Default::default()
+        };
+        Ok(self
+            .scroll(&filter, None, limit)
+            .await?
+            .vectors
+            .into_iter()
+            .map(|vector| vector.message_id)
+            .collect())
+    }
+}
+
+impl VectorFilter {
+    pub fn is_empty(&self) -> bool {
+        self.guild_id.is_none()
+            && self.channel_id.is_none()
+            && self.author_id.is_none()
+            && self.message_ids.is_none()
+    }
+}
+
+impl VectorDistance {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VectorDistance::Cosine => "cosine",
+            VectorDistance::Dot => "dot",
+            VectorDistance::Euclid => "euclid",
+        }
+    }
+}
+
+impl FromStr for VectorDistance {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "cosine" => Ok(VectorDistance::Cosine),
+            "dot" => Ok(VectorDistance::Dot),
+            "euclid" => Ok(VectorDistance::Euclid),
+            _ => Err(anyhow!("Unknown distance metric {name}")),
+        }
+    }
+}
+
+impl VectorSpace {
+    /// Collection name for vectors from `embed_model`, so the vectors of different embedding
+    /// models are kept apart.
+    pub fn collection_name(embed_model: &str, dimension: u64) -> String {
+        let model: String = embed_model
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_lowercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        format!("messages_{model}_{dimension}")
+    }
+
+    /// Fails when `existing`, the shape a stored collection was created with, differs from this
+    /// one, which would otherwise only surface as rejected upserts and searches.
+    pub fn check_matches(&self, existing: &VectorSpace) -> Result<()> {
+        if existing.dimension != self.dimension || existing.distance != self.distance {
+            return Err(anyhow!(
+                "Collection {} holds {}-dimensional {} vectors but {}-dimensional {} vectors are \
+                 configured. Use another vdb.collection or move the vectors over with the \
+                 reembed binary",
+                existing.collection,
+                existing.dimension,
+                existing.distance.as_str(),
+                self.dimension,
+                self.distance.as_str()
+            ));
+        }
+        Ok(())
+    }
+}
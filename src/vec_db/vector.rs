@@ -1,55 +1,193 @@
-use super::{DB_COLLECTION_NAME, DB_VEC_LENGTH};
+use std::collections::HashMap;
+
 use anyhow::{anyhow, Context, Result};
-use qdrant_client::qdrant::{PointStruct, ScoredPoint, UpsertPoints, UpsertPointsBuilder};
+use qdrant_client::qdrant::{
+    point_id::PointIdOptions, vectors::VectorsOptions, PointId, PointStruct, RetrievedPoint,
+    ScoredPoint, Value, Vectors,
+};
+use serenity::all::{Attachment, Message, Timestamp};
 
+#[derive(Clone)]
 pub struct DbVector {
     pub vector: Vec<f32>,
     pub message: String,
     pub message_id: u64,
     pub guild_id: u64,
+    /// Missing on points stored before channels were recorded.
+    pub channel_id: Option<u64>,
+    /// Missing on points stored before authors were recorded.
+    pub author_id: Option<u64>,
+    /// Unix timestamp in seconds of when the message was sent.
+    pub timestamp: Option<i64>,
+    /// Name the author was displayed under when the message was sent.
+    pub author_name: Option<String>,
+    /// Message this message replied to.
+    pub reply_to_id: Option<u64>,
+    pub has_attachments: bool,
+    pub has_images: bool,
+}
+
+/// A vector returned by a similarity search along with how closely it matched.
+pub struct ScoredVector {
+    pub vector: DbVector,
+    pub score: f32,
 }
 
 impl DbVector {
-    pub fn new(
-        vector: Vec<f32>,
-        message: impl ToString,
-        message_id: u64,
-        guild_id: u64,
-    ) -> Result<Self> {
-        let message = message.to_string();
-        if vector.len() != DB_VEC_LENGTH as usize {
-            return Err(anyhow!(
-                "Wrong vector size, expected: {} found {}",
-                DB_VEC_LENGTH,
-                vector.len()
-            ));
-        }
+    pub fn new(vector: Vec<f32>, message: &Message) -> Result<Self> {
         Ok(Self {
             vector,
-            message,
-            message_id,
-            guild_id,
+            message: message.content.clone(),
+            message_id: message.id.get(),
+            guild_id: message
+                .guild_id
+                .context("Message has no guild id to store it under")?
+                .get(),
+            channel_id: Some(message.channel_id.get()),
+            author_id: Some(message.author.id.get()),
+            timestamp: Some(message.timestamp.unix_timestamp()),
+            author_name: Some(
+                message
+                    .member
+                    .as_ref()
+                    .and_then(|member| member.nick.clone())
+                    .or(message.author.global_name.clone())
+                    .unwrap_or(message.author.name.clone()),
+            ),
+            reply_to_id: message
+                .message_reference
+                .as_ref()
+                .and_then(|reference| reference.message_id)
+                .map(|message_id| message_id.get()),
+            has_attachments: !message.attachments.is_empty(),
+            has_images: has_images(&message.attachments),
         })
     }
+
+    /// Updates the stored message after an edit, keeping the rest of the payload.
+    pub fn apply_edit(
+        &mut self,
+        vector: Vec<f32>,
+        content: &str,
+        attachments: Option<&[Attachment]>,
+    ) {
+        self.vector = vector;
+        self.message = content.to_string();
+        if let Some(attachments) = attachments {
+            self.has_attachments = !attachments.is_empty();
+            self.has_images = has_images(attachments);
+        }
+    }
+
+    /// Renders the message with its author and date for use in a prompt.
+    pub fn to_prompt_line(&self) -> String {
+        let sent_at = self
+            .timestamp
+            .and_then(|timestamp| Timestamp::from_unix_timestamp(timestamp).ok())
+            .map(|timestamp| format!("({}) ", timestamp.format("%d/%m/%Y %H:%M")))
+            .unwrap_or_default();
+        let author = self.author_name.as_deref().unwrap_or("Someone");
+        let attachments = if self.has_images {
+            " [with image]"
+        } else if self.has_attachments {
+            " [with attachment]"
+        } else {
+            ""
+        };
+        format!("{sent_at}{author} said: `{}`{attachments}", self.message)
+    }
+
+    /// Discord link to the original message, if the channel is known.
+    pub fn link(&self) -> Option<String> {
+        self.channel_id.map(|channel_id| {
+            format!(
+                "https://discord.com/channels/{}/{}/{}",
+                self.guild_id, channel_id, self.message_id
+            )
+        })
+    }
+}
+
+fn has_images(attachments: &[Attachment]) -> bool {
+    attachments.iter().any(|attachment| {
+        attachment
+            .content_type
+            .as_ref()
+            .is_some_and(|content_type| content_type.starts_with("image/"))
+    })
 }
 
-impl From<DbVector> for UpsertPoints {
+impl From<DbVector> for PointStruct {
     fn from(value: DbVector) -> Self {
-        let point_struct = PointStruct::new(
-            value.message_id,
-            value.vector,
-            [
-                ("message", value.message.into()),
-                ("guild_id", value.guild_id.to_string().into()),
-            ],
-        );
-        UpsertPointsBuilder::new(DB_COLLECTION_NAME, vec![point_struct]).build()
+        let mut payload: HashMap<&str, Value> = HashMap::from([
+            ("message", value.message.into()),
+            ("guild_id", (value.guild_id as i64).into()),
+        ]);
+        if let Some(channel_id) = value.channel_id {
+            payload.insert("channel_id", (channel_id as i64).into());
+        }
+        if let Some(author_id) = value.author_id {
+            payload.insert("author_id", (author_id as i64).into());
+        }
+        if let Some(timestamp) = value.timestamp {
+            payload.insert("timestamp", timestamp.into());
+        }
+        if let Some(author_name) = value.author_name {
+            payload.insert("author_name", author_name.into());
+        }
+        if let Some(reply_to_id) = value.reply_to_id {
+            payload.insert("reply_to_id", (reply_to_id as i64).into());
+        }
+        payload.insert("has_attachments", value.has_attachments.into());
+        payload.insert("has_images", value.has_images.into());
+        PointStruct::new(value.message_id, value.vector, payload)
+    }
+}
+
+impl TryFrom<ScoredPoint> for ScoredVector {
+    type Error = anyhow::Error;
+    fn try_from(value: ScoredPoint) -> std::prelude::v1::Result<Self, Self::Error> {
+        Ok(Self {
+            score: value.score,
+            vector: value.try_into()?,
+        })
     }
 }
 
 impl TryFrom<ScoredPoint> for DbVector {
     type Error = anyhow::Error;
     fn try_from(value: ScoredPoint) -> std::prelude::v1::Result<Self, Self::Error> {
+        QdrantPoint {
+            id: value.id,
+            payload: value.payload,
+            vectors: value.vectors,
+        }
+        .try_into()
+    }
+}
+
+impl TryFrom<RetrievedPoint> for DbVector {
+    type Error = anyhow::Error;
+    fn try_from(value: RetrievedPoint) -> std::prelude::v1::Result<Self, Self::Error> {
+        QdrantPoint {
+            id: value.id,
+            payload: value.payload,
+            vectors: value.vectors,
+        }
+        .try_into()
+    }
+}
+
+/// The parts shared by the point types qdrant returns from searches and scrolls.
+struct QdrantPoint {
+    id: Option<PointId>,
+    payload: HashMap<String, Value>,
+    vectors: Option<Vectors>,
+}
+
+impl TryFrom<QdrantPoint> for DbVector {
+    type Error = anyhow::Error;
+    fn try_from(value: QdrantPoint) -> std::prelude::v1::Result<Self, Self::Error> {
         Ok(Self {
             guild_id: value
                 .payload
@@ -57,13 +195,44 @@ impl TryFrom<ScoredPoint> for DbVector {
                 .context("No Guild ID attached to vector payload")?
                 .as_integer()
                 .context("Guild ID on vector not a number")? as u64,
+            channel_id: value
+                .payload
+                .get("channel_id")
+                .and_then(Value::as_integer)
+                .map(|channel_id| channel_id as u64),
+            author_id: value
+                .payload
+                .get("author_id")
+                .and_then(Value::as_integer)
+                .map(|author_id| author_id as u64),
+            timestamp: value.payload.get("timestamp").and_then(Value::as_integer),
+            author_name: value
+                .payload
+                .get("author_name")
+                .and_then(Value::as_str)
+                .cloned(),
+            reply_to_id: value
+                .payload
+                .get("reply_to_id")
+                .and_then(Value::as_integer)
+                .map(|reply_to_id| reply_to_id as u64),
+            has_attachments: value
+                .payload
+                .get("has_attachments")
+                .and_then(Value::as_bool)
+                .unwrap_or_default(),
+            has_images: value
+                .payload
+                .get("has_images")
+                .and_then(Value::as_bool)
+                .unwrap_or_default(),
             message_id: match value
                 .id
                 .context("Vector missing ID")?
                 .point_id_options
                 .context("Cannot get point ID Options")?
             {
-                qdrant_client::qdrant::point_id::PointIdOptions::Num(id) => id,
+                PointIdOptions::Num(id) => id,
                 _ => Err(anyhow!("Point id is not a number"))?,
             },
             vector: match value
@@ -72,7 +241,7 @@ impl TryFrom<ScoredPoint> for DbVector {
                 .vectors_options
                 .context("Vector missing vector options")?
             {
-                qdrant_client::qdrant::vectors::VectorsOptions::Vector(vec) => vec.data,
+                VectorsOptions::Vector(vec) => vec.data,
                 _ => Err(anyhow!(
                     "Qdrant vector options does not contain a single vector"
                 ))?,
@@ -81,6 +250,8 @@ impl TryFrom<ScoredPoint> for DbVector {
                 .payload
                 .get("message")
                 .context("Vector missing message field")?
+                .as_str()
+                .context("Message on vector not a string")?
                 .to_string(),
         })
     }